@@ -0,0 +1,91 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Benchmarks of the hottest model code: parcel
+//! ascent integration and environment access.
+//!
+//! Run with `cargo bench --features bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pats::model::bench::BenchSetup;
+use rayon::ThreadPoolBuilder;
+
+fn parcel_ascent(c: &mut Criterion) {
+    let setup = BenchSetup::new();
+
+    c.bench_function("parcel_ascent", |b| {
+        b.iter(|| black_box(setup.parcel_ascent()))
+    });
+}
+
+fn field_access(c: &mut Criterion) {
+    let setup = BenchSetup::new();
+
+    c.bench_function("field_access_1000", |b| {
+        b.iter(|| black_box(setup.field_access(black_box(1000))))
+    });
+}
+
+fn field_access_cached(c: &mut Criterion) {
+    let setup = BenchSetup::new();
+
+    c.bench_function("field_access_cached_1000", |b| {
+        b.iter(|| black_box(setup.field_access_cached(black_box(1000))))
+    });
+}
+
+fn field_access_height_grid(c: &mut Criterion) {
+    let setup = BenchSetup::with_height_grid(50.0);
+
+    c.bench_function("field_access_height_grid_1000", |b| {
+        b.iter(|| black_box(setup.field_access(black_box(1000))))
+    });
+
+    c.bench_function("parcel_ascent_height_grid", |b| {
+        b.iter(|| black_box(setup.parcel_ascent()))
+    });
+}
+
+fn parcels_distribution(c: &mut Criterion) {
+    let setup = BenchSetup::new();
+    let threadpool = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+
+    let mut group = c.benchmark_group("parcels_distribution");
+    group.sample_size(10);
+
+    group.bench_function("spawned_100", |b| {
+        b.iter(|| black_box(setup.spawned_parcels(&threadpool, black_box(100))))
+    });
+
+    group.bench_function("chunked_100", |b| {
+        b.iter(|| black_box(setup.chunked_parcels(&threadpool, black_box(100))))
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    parcel_ascent,
+    field_access,
+    field_access_cached,
+    field_access_height_grid,
+    parcels_distribution
+);
+criterion_main!(benches);
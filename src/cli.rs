@@ -0,0 +1,125 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Module responsible for parsing command line arguments.
+//!
+//! Most of the model settings are provided in the configuration
+//! file, so command line arguments are used only for options
+//! related to single model run (eg. debugging).
+
+use crate::{model::FieldFloat, Float};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::{mem, path::PathBuf};
+
+/// Parcel Ascent Tracing System (PATS) - the numerical model
+/// for convective parcel ascent simulation in three-dimensions.
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+pub struct Cli {
+    /// Path to the configuration file (`.yaml`, `.yml` or `.json`).
+    #[clap(long, default_value = "config.yaml")]
+    pub config: PathBuf,
+
+    /// Number of threads used by the model,
+    /// overrides `resources.threads` from the configuration.
+    #[clap(long)]
+    pub threads: Option<u16>,
+
+    /// Memory limit for the model in MB,
+    /// overrides `resources.memory` from the configuration.
+    #[clap(long)]
+    pub memory_mb: Option<usize>,
+
+    /// Write buffered environment arrays to the output directory
+    /// (as `.npy` files) before deploying parcels.
+    #[clap(long)]
+    pub dump_environment: bool,
+
+    /// Optional command to run instead of the model.
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Parses command line arguments, with `--version`
+    /// additionally reporting the build information.
+    pub fn parse_with_build_info() -> Self {
+        // command borrows the version string, so it is built
+        // and parsed while the string is alive
+        let long_version = long_version();
+
+        let matches = Cli::command()
+            .long_version(long_version.as_str())
+            .get_matches();
+
+        Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    }
+}
+
+/// Creates version string with cargo features enabled
+/// at compile time and precision of buffered environment data.
+///
+/// Model behaviour depends on those, so they are needed
+/// to tell which build produced the results.
+pub fn long_version() -> String {
+    let features: Vec<&str> = [
+        ("3d", cfg!(feature = "3d")),
+        ("debug", cfg!(feature = "debug")),
+        ("env_vertical_motion", cfg!(feature = "env_vertical_motion")),
+        ("high_precision", cfg!(feature = "high_precision")),
+        ("parquet", cfg!(feature = "parquet")),
+        ("bench", cfg!(feature = "bench")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect();
+
+    let features = if features.is_empty() {
+        "none".to_string()
+    } else {
+        features.join(", ")
+    };
+
+    format!(
+        "{}\nfeatures: {}\nfield float width: {} bits",
+        env!("CARGO_PKG_VERSION"),
+        features,
+        mem::size_of::<FieldFloat>() * 8
+    )
+}
+
+/// Auxiliary commands which do not run the simulation.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print parameters of projection generated for the domain
+    /// and projected coordinates of domain corners.
+    ProjectionInfo,
+
+    /// Write environmental profile (as seen by the model) at given
+    /// location to standard output as CSV, to compare it with the source data.
+    Column {
+        /// Longitude of the location
+        #[clap(long, allow_hyphen_values = true)]
+        lon: Float,
+
+        /// Latitude of the location
+        #[clap(long, allow_hyphen_values = true)]
+        lat: Float,
+    },
+}
@@ -34,6 +34,9 @@ pub const WGS84_B: Float = 6_356_752.314_245;
 pub const WGS84_E: Float =
     0.081_819_190_842_965_558_441_157_725_155_790_103_599_429_130_554_199_218_75;
 
+///Radius of spherical Earth used by ECMWF and NCEP models
+pub const SPHERE_EARTH_RADIUS: Float = 6_371_229.0;
+
 ///WGS84 ellipsoid Ramanujan's `h` parameter
 pub const WGS84_H: Float =
     ((WGS84_A - WGS84_B) * (WGS84_A - WGS84_B)) / ((WGS84_A + WGS84_B) * (WGS84_A - WGS84_B));
@@ -53,3 +56,6 @@ pub const NS_C_EARTH: Float = PI
 
 ///WGS84 ellipsoid circumference along equator
 pub const WE_C_EARTH: Float = 2.0 * PI * WGS84_A;
+
+///Latent heat of sublimation of ice at 0°C
+pub const L_S: Float = 2_834_500.0;
@@ -21,13 +21,100 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! struct and function in the model.
 
 use crate::Float;
+use chrono::NaiveDateTime;
+use serde::{Serialize, Serializer};
+use std::fmt;
 use thiserror::Error;
 
+/// Stable category of an error, independent of the
+/// human-readable message, so that it can be matched on
+/// and written in the output for failed parcels.
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ErrorCode {
+    /// Position is outside of the domain or environment column
+    OutOfDomain,
+
+    /// Geographic projection of the position failed
+    ProjectionFailed,
+
+    /// Input data is missing (masked) around the position
+    MissingData,
+
+    /// Other error of accessing the environment
+    EnvironmentAccess,
+
+    /// Thermodynamic computation got unreasonable values
+    UnreasonableThermo,
+
+    /// Input data does not contain all needed variables
+    InsufficientData,
+
+    /// Input files cannot be read or have unsupported contents
+    InputFailed,
+
+    /// Configuration cannot be read or is incorrect
+    InvalidConfig,
+
+    /// Output cannot be written
+    OutputFailed,
+
+    /// Release level is outside of the environment column
+    ReleaseLevel,
+
+    /// Parcel simulation panicked
+    Panic,
+
+    /// Model was interrupted by the user
+    Interrupted,
+
+    /// Model run was aborted, as too many parcels failed
+    TooManyFailures,
+
+    /// Setting up the model runtime failed
+    Internal,
+}
+
+impl ErrorCode {
+    /// Returns the code as written in the output
+    /// (it is also used when the code is serialized).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::OutOfDomain => "out_of_domain",
+            ErrorCode::ProjectionFailed => "projection_failed",
+            ErrorCode::MissingData => "missing_data",
+            ErrorCode::EnvironmentAccess => "environment_access",
+            ErrorCode::UnreasonableThermo => "unreasonable_thermo",
+            ErrorCode::InsufficientData => "insufficient_data",
+            ErrorCode::InputFailed => "input_failed",
+            ErrorCode::InvalidConfig => "invalid_config",
+            ErrorCode::OutputFailed => "output_failed",
+            ErrorCode::ReleaseLevel => "release_level",
+            ErrorCode::Panic => "panic",
+            ErrorCode::Interrupted => "interrupted",
+            ErrorCode::TooManyFailures => "too_many_failures",
+            ErrorCode::Internal => "internal",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// General errors gathering all errors that can be
 /// returned by the model.
 #[derive(Error, Debug)]
 pub enum ModelError {
-    #[error("Error while reading config.yaml: {0}")]
+    #[error("Error while reading the configuration: {0}")]
     Config(#[from] ConfigError),
 
     #[error("Error while creating ThreadPool: {0}")]
@@ -44,17 +131,77 @@ pub enum ModelError {
 
     #[error("Error with output directory: {0}")]
     FaultyOutput(&'static str),
+
+    #[error("Error while setting the interrupt handler: {0}")]
+    InterruptHandler(#[from] ctrlc::Error),
+
+    #[error("Model was interrupted, results of {0} finished parcels have been saved")]
+    Interrupted(usize),
+
+    #[error("Model run was aborted, as {failed} of {received} received parcels failed (mostly with {dominant} error), check your configuration and input data")]
+    TooManyFailures {
+        failed: usize,
+        received: usize,
+        dominant: ErrorCode,
+    },
+
+    #[error("Error while handling the csv file: {0}")]
+    CSVHandling(#[from] csv::Error),
+
+    #[error("Error while writing the run summary: {0}")]
+    Summary(#[from] serde_json::Error),
+
+    #[error("Parcel simulation failed: {0}")]
+    Parcel(#[from] ParcelError),
+
+    #[cfg(feature = "parquet")]
+    #[error("Error while writing the parquet file: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[cfg(feature = "parquet")]
+    #[error("Error while preparing the parquet output: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+}
+
+impl ModelError {
+    /// Stable code of the error category.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ModelError::Config(err) => err.code(),
+            ModelError::Environment(err) => err.code(),
+            ModelError::Parcel(err) => err.code(),
+            ModelError::UnreasonableVariable(_) => ErrorCode::UnreasonableThermo,
+            ModelError::Interrupted(_) => ErrorCode::Interrupted,
+            ModelError::TooManyFailures { .. } => ErrorCode::TooManyFailures,
+            ModelError::ThreadPool(_) | ModelError::InterruptHandler(_) => ErrorCode::Internal,
+            ModelError::FileHandling(_)
+            | ModelError::FaultyOutput(_)
+            | ModelError::CSVHandling(_)
+            | ModelError::Summary(_) => ErrorCode::OutputFailed,
+            #[cfg(feature = "parquet")]
+            ModelError::Parquet(_) | ModelError::Arrow(_) => ErrorCode::OutputFailed,
+        }
+    }
 }
 
 /// Errors related to reading and handling the model configuration.
 #[derive(Error, Debug)]
 pub enum ConfigError {
-    #[error("Cannot open config.yaml: {0}")]
+    #[error("Cannot open the configuration file: {0}")]
     CantOpenFile(#[from] std::io::Error),
 
-    #[error("Cannot deserialize config.yaml: {0}")]
+    #[error("Cannot deserialize the YAML configuration: {0}")]
     CantDeserialize(#[from] serde_yaml::Error),
 
+    #[error("Cannot deserialize the JSON configuration: {0}")]
+    CantDeserializeJson(#[from] serde_json::Error),
+
+    #[error("Unknown format of the configuration file, use .yaml, .yml or .json extension")]
+    UnknownFormat,
+
+    #[error("Cannot parse the value of environment variable {0}")]
+    IncorrectEnvVariable(&'static str),
+
     #[error("Configuration component is out of bounds: {0}")]
     OutOfBounds(&'static str),
 
@@ -62,6 +209,16 @@ pub enum ConfigError {
     CannotReadInput(#[from] InputError),
 }
 
+impl ConfigError {
+    /// Stable code of the error category.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ConfigError::CannotReadInput(err) => err.code(),
+            _ => ErrorCode::InvalidConfig,
+        }
+    }
+}
+
 /// Errors related to reading and handling
 /// boundary conditions (environment data).
 #[derive(Error, Debug)]
@@ -76,6 +233,37 @@ pub enum EnvironmentError {
 
     #[error("Could not find the value using bisection: {0}")]
     SearchUnable(#[from] SearchError),
+
+    #[error("Incorrect buffer extent, check your configuration: {0}")]
+    IncorrectBufferExtent(&'static str),
+
+    #[error("Not enough input gridpoints buffered around the domain, increase the margins: {0}")]
+    InsufficientMargins(&'static str),
+
+    #[error("Cannot create the regular height grid: {0}")]
+    IncorrectHeightGrid(&'static str),
+
+    #[error("Cannot compute thermodynamic variable of the environment: {0}")]
+    UnreasonableVariable(#[from] floccus::errors::InputError),
+
+    #[error("Input data is missing at gridpoints surrounding the position")]
+    MissingData,
+
+    #[error("Cannot interpolate the environment in time: {0}")]
+    IncorrectLaterEnvironment(&'static str),
+}
+
+impl EnvironmentError {
+    /// Stable code of the error category,
+    /// written in the output for failed parcels.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EnvironmentError::SearchUnable(SearchError::OutOfBounds) => ErrorCode::OutOfDomain,
+            EnvironmentError::ProjectionError(_) => ErrorCode::ProjectionFailed,
+            EnvironmentError::MissingData => ErrorCode::MissingData,
+            _ => ErrorCode::EnvironmentAccess,
+        }
+    }
 }
 
 /// Errors related to reading input GRIB files.
@@ -93,8 +281,52 @@ pub enum InputError {
     #[error("Provided input data is not sufficient to run the model, please check the documentation: {0}")]
     DataNotSufficient(&'static str),
 
+    #[error("Input messages are valid at different times ({first} and {other}), files must contain data only for one datetime")]
+    InconsistentValidTime {
+        first: NaiveDateTime,
+        other: NaiveDateTime,
+    },
+
+    #[error(
+        "Input data is valid at {found}, but valid time {expected} is set in the configuration"
+    )]
+    UnexpectedValidTime {
+        expected: NaiveDateTime,
+        found: NaiveDateTime,
+    },
+
+    #[error("Grid of input data is not supported, only regular grids can be used: {0}")]
+    UnsupportedGrid(&'static str),
+
+    #[error("Level type of input data is not supported, only isobaric levels can be used: {0}")]
+    UnsupportedLevelType(&'static str),
+
+    #[error("Later input data cannot be used for interpolation in time: {0}")]
+    IncorrectLaterData(&'static str),
+
     #[error("Values shape mismatch in GRIB, please check your input data: {0}")]
     IncorrectShape(#[from] ndarray::ShapeError),
+
+    #[error("Cannot compute virtual temperature at gridpoint N{lat:.3} E{lon:.3} {pressure:.0} Pa (temperature: {temperature} K, specific humidity: {spec_humidity}), please check your input data: {source}")]
+    UnreasonableGridpoint {
+        lon: Float,
+        lat: Float,
+        pressure: Float,
+        temperature: Float,
+        spec_humidity: Float,
+        source: floccus::errors::InputError,
+    },
+}
+
+impl InputError {
+    /// Stable code of the error category.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            InputError::DataNotSufficient(_) => ErrorCode::InsufficientData,
+            InputError::UnreasonableGridpoint { .. } => ErrorCode::UnreasonableThermo,
+            _ => ErrorCode::InputFailed,
+        }
+    }
 }
 
 /// Errors related to searching datasets with bisection.
@@ -124,6 +356,27 @@ pub enum ParcelError {
 
     #[error("Parcel released from N{0:.3} E{1:.3} has stopped its ascent with error: {2} Check your configuration.")]
     AscentStopped(Float, Float, ParcelSimulationError),
+
+    #[error("Release level is outside of the environment column: {0}")]
+    ReleaseLevelOutOfColumn(&'static str),
+
+    #[error("Parcel simulation panicked: {0}")]
+    Panicked(String),
+}
+
+impl ParcelError {
+    /// Stable code of the error category,
+    /// written in the output for failed parcels.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ParcelError::UnreasonableVariable(_) => ErrorCode::UnreasonableThermo,
+            ParcelError::EnvironmentAccess(err) => err.code(),
+            ParcelError::FileHandling(_) | ParcelError::CSVHandling(_) => ErrorCode::OutputFailed,
+            ParcelError::AscentStopped(_, _, err) => err.code(),
+            ParcelError::ReleaseLevelOutOfColumn(_) => ErrorCode::ReleaseLevel,
+            ParcelError::Panicked(_) => ErrorCode::Panic,
+        }
+    }
 }
 
 /// Errors related to parcel simulation.
@@ -136,9 +389,67 @@ pub enum ParcelSimulationError {
     EnvironmentAccess(#[from] EnvironmentError),
 }
 
+impl ParcelSimulationError {
+    /// Stable code of the error category,
+    /// written in the output for failed parcels.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ParcelSimulationError::UnreasonableVariable(_) => ErrorCode::UnreasonableThermo,
+            ParcelSimulationError::EnvironmentAccess(err) => err.code(),
+        }
+    }
+}
+
 /// Errors realted to geographic projection.
 #[derive(Error, Debug)]
 pub enum ProjectionError {
     #[error("Incorrect projection parameters: {0}")]
     IncorrectParams(&'static str),
+
+    #[error("Point is outside of the valid projection range: {0}")]
+    OutOfRange(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvironmentError, ErrorCode, ModelError, ParcelError, SearchError};
+
+    #[test]
+    fn nested_error_codes() {
+        let err = ModelError::Parcel(ParcelError::EnvironmentAccess(
+            EnvironmentError::SearchUnable(SearchError::OutOfBounds),
+        ));
+
+        assert_eq!(err.code(), ErrorCode::OutOfDomain);
+        assert_eq!(err.code().to_string(), "out_of_domain");
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::UnreasonableThermo).unwrap(),
+            "\"unreasonable_thermo\""
+        );
+    }
+
+    #[test]
+    fn error_code_strings() {
+        // codes are written in the output, so they must not change
+        let codes = [
+            (ErrorCode::OutOfDomain, "out_of_domain"),
+            (ErrorCode::ProjectionFailed, "projection_failed"),
+            (ErrorCode::MissingData, "missing_data"),
+            (ErrorCode::EnvironmentAccess, "environment_access"),
+            (ErrorCode::UnreasonableThermo, "unreasonable_thermo"),
+            (ErrorCode::InsufficientData, "insufficient_data"),
+            (ErrorCode::InputFailed, "input_failed"),
+            (ErrorCode::InvalidConfig, "invalid_config"),
+            (ErrorCode::OutputFailed, "output_failed"),
+            (ErrorCode::ReleaseLevel, "release_level"),
+            (ErrorCode::Panic, "panic"),
+            (ErrorCode::Interrupted, "interrupted"),
+            (ErrorCode::TooManyFailures, "too_many_failures"),
+            (ErrorCode::Internal, "internal"),
+        ];
+
+        for (code, code_str) in codes {
+            assert_eq!(code.as_str(), code_str);
+        }
+    }
 }
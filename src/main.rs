@@ -17,31 +17,20 @@ You should have received a copy of the GNU General Public License
 along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
 */
 
-//! Technical documentation of Parcel Ascent Tracing System (PATS) -
-//! the numerical model for convective parcel ascent simulation in three-dimensions.
+//! Command line entry point of Parcel Ascent Tracing System (PATS).
 //!
-//! This documentation provides a description of functions and structures
-//! used in the model. Its main purpose is to make it easier to maintain
-//! and contribute to the project codebase. However, it can be also useful
-//! for users who want to understand the model in more detail.
+//! The model itself is implemented in the library crate.
 
-mod constants;
-mod errors;
-mod model;
-
-use cap::Cap;
-use env_logger::Env;
-use log::{error, info};
-use std::alloc;
-
-type Float = f64;
-
-/// Global allocator used by the model.
-///
-/// Use of static global allocator allows for capping the memory to the limit set by user
-/// in configuration file and in effect provide better [OOM error](https://en.wikipedia.org/wiki/Out_of_memory) handling.
-#[global_allocator]
-static ALLOCATOR: Cap<alloc::System> = Cap::new(alloc::System, usize::MAX);
+use env_logger::{fmt::Formatter, Env};
+use log::{error, info, Record};
+use pats::{
+    cli::{self, Command},
+    model,
+};
+use std::{
+    env,
+    io::{self, Write},
+};
 
 /// The main program function.
 /// Prepares the runtime environment and calls the [`model::main`].
@@ -51,18 +40,50 @@ static ALLOCATOR: Cap<alloc::System> = Cap::new(alloc::System, usize::MAX);
 /// Furthermore, errors can occur also during model shutdown and they also
 /// can be handled.
 fn main() {
+    let cli = cli::Cli::parse_with_build_info();
+
     #[cfg(not(feature = "debug"))]
     let logger_env = Env::new().filter_or("PATS_LOG_LEVEL", "info");
 
     #[cfg(feature = "debug")]
     let logger_env = Env::new().filter_or("PATS_LOG_LEVEL", "debug");
 
-    env_logger::Builder::from_env(logger_env)
-        .format_timestamp_millis()
-        .init();
+    let mut logger = env_logger::Builder::from_env(logger_env);
+    logger.format_timestamp_millis();
 
-    match model::main() {
-        Ok(_) => info!("Model execution finished. Check the output directory and log."),
-        Err(err) => error!("Model execution failed with error: {}", err),
+    // machine-parsable logs for log collecting pipelines
+    if matches!(env::var("PATS_LOG_FORMAT").as_deref(), Ok("json")) {
+        logger.format(format_json_record);
     }
+
+    logger.init();
+
+    match cli.command {
+        Some(Command::ProjectionInfo) => match model::projection_info(&cli) {
+            Ok(info) => println!("{}", info),
+            Err(err) => error!("Cannot describe the projection: {}", err),
+        },
+        Some(Command::Column { lon, lat }) => {
+            if let Err(err) = model::column_profile(&cli, lon, lat) {
+                error!("Cannot write the column profile: {}", err);
+            }
+        }
+        None => match model::main(&cli) {
+            Ok(_) => info!("Model execution finished. Check the output directory and log."),
+            Err(err) => error!("Model execution failed with error: {}", err),
+        },
+    }
+}
+
+/// Formats log record as a single-line JSON object
+/// with level, timestamp, module and message.
+fn format_json_record(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let json_record = serde_json::json!({
+        "level": record.level().as_str(),
+        "timestamp": buf.timestamp_millis().to_string(),
+        "module": record.module_path().unwrap_or_default(),
+        "message": record.args().to_string(),
+    });
+
+    writeln!(buf, "{}", json_record)
 }
@@ -0,0 +1,186 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module computing statistics of convective
+//! parameters aggregated over the whole domain.
+//!
+//! Statistics are computed from typed values of parameters
+//! (as written to CSV output), for every numeric column
+//! without listing fields of convective parameters again.
+
+use super::parcel::conv_params::{ConvectiveParams, ParamValue};
+use crate::{errors::ModelError, Float};
+use serde::Serialize;
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Statistics of a single parameter over parcels
+/// for which it has a finite value.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+struct FieldStats {
+    field: String,
+    count: usize,
+    min: Float,
+    mean: Float,
+    max: Float,
+    p10: Float,
+    p25: Float,
+    p50: Float,
+    p75: Float,
+    p90: Float,
+}
+
+/// Aggregated output written to `model_aggregate_params.json`.
+#[derive(Serialize)]
+struct AggregateOutput {
+    parcels: usize,
+    max_cape: Option<Float>,
+    max_cape_lon: Option<Float>,
+    max_cape_lat: Option<Float>,
+    fields: Vec<FieldStats>,
+}
+
+/// Writes statistics of each numeric column of convective
+/// parameters (with Lifted Indices at given levels)
+/// and location of the maximum CAPE.
+pub(super) fn save_aggregate(
+    convective_params_list: &[ConvectiveParams],
+    lifted_index_levels: &[Float],
+    out_path: &Path,
+) -> Result<(), ModelError> {
+    let header = ConvectiveParams::header(lifted_index_levels);
+    let rows: Vec<Vec<ParamValue>> = convective_params_list
+        .iter()
+        .map(|params| params.values(lifted_index_levels.len()))
+        .collect();
+
+    let max_cape_params = convective_params_list
+        .iter()
+        .filter(|params| params.cape().is_some())
+        .max_by(|a, b| a.cape().unwrap().total_cmp(&b.cape().unwrap()));
+
+    let aggregate = AggregateOutput {
+        parcels: convective_params_list.len(),
+        max_cape: max_cape_params.and_then(ConvectiveParams::cape),
+        max_cape_lon: max_cape_params.map(ConvectiveParams::start_lon),
+        max_cape_lat: max_cape_params.map(ConvectiveParams::start_lat),
+        fields: aggregate_fields(&header, &rows),
+    };
+
+    let out_file = BufWriter::new(File::create(out_path)?);
+    serde_json::to_writer_pretty(out_file, &aggregate)?;
+
+    Ok(())
+}
+
+/// Computes statistics of columns with float or integer
+/// values, skipping flags and error codes.
+fn aggregate_fields(header: &[String], rows: &[Vec<ParamValue>]) -> Vec<FieldStats> {
+    let mut fields = vec![];
+
+    'columns: for (i, name) in header.iter().enumerate() {
+        let mut values: Vec<Float> = vec![];
+
+        for row in rows {
+            match row[i] {
+                ParamValue::Float(Some(value)) if value.is_finite() => values.push(value),
+                ParamValue::Integer(Some(value)) => values.push(value as Float),
+                ParamValue::Float(_) | ParamValue::Integer(None) => (),
+                ParamValue::Bool(_) | ParamValue::Text(_) => continue 'columns,
+            }
+        }
+
+        if values.is_empty() {
+            continue;
+        }
+
+        values.sort_unstable_by(Float::total_cmp);
+
+        fields.push(FieldStats {
+            field: name.clone(),
+            count: values.len(),
+            min: values[0],
+            mean: values.iter().sum::<Float>() / values.len() as Float,
+            max: values[values.len() - 1],
+            p10: percentile(&values, 10.0),
+            p25: percentile(&values, 25.0),
+            p50: percentile(&values, 50.0),
+            p75: percentile(&values, 75.0),
+            p90: percentile(&values, 90.0),
+        });
+    }
+
+    fields
+}
+
+/// Percentile of sorted values, linearly
+/// interpolated between the closest ranks.
+fn percentile(sorted: &[Float], percent: Float) -> Float {
+    let rank = percent / 100.0 * (sorted.len() - 1) as Float;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+
+    sorted[below] + (rank - below as Float) * (sorted[above] - sorted[below])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate_fields, percentile};
+    use crate::model::parcel::conv_params::ParamValue::{Bool, Float, Text};
+
+    #[test]
+    fn column_statistics() {
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 50.0), 3.0);
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 90.0), 4.6);
+        assert_eq!(percentile(&[7.0], 25.0), 7.0);
+
+        let header = ["cape", "saturated", "lfc", "error_code"].map(String::from);
+        let rows = vec![
+            vec![
+                Float(Some(100.0)),
+                Bool(true),
+                Float(Some(1200.0)),
+                Text(None),
+            ],
+            vec![Float(Some(300.0)), Bool(false), Float(None), Text(None)],
+            vec![
+                Float(Some(f64::NAN)),
+                Bool(false),
+                Float(None),
+                Text(Some("out_of_domain")),
+            ],
+            vec![
+                Float(Some(200.0)),
+                Bool(true),
+                Float(Some(800.0)),
+                Text(None),
+            ],
+        ];
+
+        let fields = aggregate_fields(&header, &rows);
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].field, "cape");
+        assert_eq!(fields[0].count, 3);
+        assert_eq!(
+            (fields[0].min, fields[0].mean, fields[0].max),
+            (100.0, 200.0, 300.0)
+        );
+        assert_eq!(fields[1].field, "lfc");
+        assert_eq!(fields[1].p50, 1000.0);
+    }
+}
@@ -0,0 +1,197 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Model internals exposed for benchmarks (with `bench` feature).
+//!
+//! Benchmarks use a synthetic environment,
+//! so they do not need any GRIB input.
+
+use super::{
+    configuration::Config,
+    environment::{analytic::AnalyticProfile, EnvFields, Environment, SearchCache},
+    parcel, PARCELS_CHUNK_SIZE,
+};
+use crate::Float;
+use floccus::constants::{C_P, G, R_D};
+use rayon::{prelude::*, ThreadPool};
+use std::sync::{mpsc, Arc};
+
+const SURFACE_PRESSURE: Float = 100_000.0;
+const SURFACE_TEMPERATURE: Float = 300.0;
+
+/// Conditionally unstable environment with dry adiabatic
+/// lapse rate up to 3 km and isothermal layer above.
+const BENCH_PROFILE: AnalyticProfile = AnalyticProfile {
+    temperature: |z| SURFACE_TEMPERATURE - (G / C_P) * z.min(3000.0),
+    dewpoint: |z| (290.0 - 0.05 * z).max(240.0),
+    pressure: |z| {
+        let temp = SURFACE_TEMPERATURE - (G / C_P) * z.min(3000.0);
+        let pres = SURFACE_PRESSURE * (temp / SURFACE_TEMPERATURE).powf(C_P / R_D);
+
+        pres * (-G * (z - 3000.0).max(0.0) / (R_D * temp)).exp()
+    },
+};
+
+/// Single parcel setup in a synthetic environment.
+pub struct BenchSetup {
+    config: Arc<Config>,
+    environment: Arc<Environment>,
+    start_coords: (Float, Float),
+}
+
+impl BenchSetup {
+    /// Creates the environment covering 0-12 km with 100 m levels.
+    pub fn new() -> Self {
+        let config: Config = serde_yaml::from_str(
+            r#"
+            domain:
+              ref_lat: 50.0
+              ref_lon: 20.0
+              spacing: 1000.0
+              shape: [1, 1]
+            datetime:
+              start: "2021-06-24T06:00:00"
+              timestep: 1.0
+            input:
+              level_type: "isobaricInhPa"
+              data_files: []
+            "#,
+        )
+        .expect("Benchmark configuration is incorrect");
+
+        let heights: Vec<Float> = (0..=120).map(|i| Float::from(i) * 100.0).collect();
+        let environment = Environment::from_analytic(&config.domain, &heights, &BENCH_PROFILE)
+            .expect("Cannot create benchmark environment");
+
+        let start_coords = environment
+            .projection
+            .project(config.domain.ref_lon, config.domain.ref_lat);
+
+        BenchSetup {
+            config: Arc::new(config),
+            environment: Arc::new(environment),
+            start_coords,
+        }
+    }
+
+    /// Creates the same setup as [`BenchSetup::new`], with fields
+    /// interpolated onto regular height levels with given spacing.
+    pub fn with_height_grid(spacing: Float) -> Self {
+        let mut setup = Self::new();
+
+        Arc::get_mut(&mut setup.environment)
+            .expect("Benchmark environment is shared")
+            .resample_to_height_grid(spacing)
+            .expect("Cannot interpolate benchmark environment");
+
+        setup
+    }
+
+    /// Simulates the full parcel ascent and returns its CAPE
+    /// (zero when the parcel has no LFC).
+    pub fn parcel_ascent(&self) -> Float {
+        parcel::deploy(
+            self.start_coords,
+            (0, 0),
+            None,
+            &self.config,
+            &self.environment,
+            None,
+        )
+        .expect("Benchmark parcel failed")
+        .cape()
+        .unwrap_or(0.0)
+    }
+
+    /// Simulates `count` parcels on the `threadpool`, spawning a task
+    /// for each parcel, and returns the sum of their CAPE.
+    pub fn spawned_parcels(&self, threadpool: &ThreadPool, count: usize) -> Float {
+        let (tx, rx) = mpsc::channel();
+
+        threadpool.scope(|scope| {
+            for _ in 0..count {
+                let tx = tx.clone();
+                scope.spawn(move |_| {
+                    tx.send(self.parcel_ascent()).ok();
+                });
+            }
+        });
+
+        drop(tx);
+        rx.iter().sum()
+    }
+
+    /// Same as [`BenchSetup::spawned_parcels`], but parcels are
+    /// distributed in chunks by parallel iterator, as in the model.
+    pub fn chunked_parcels(&self, threadpool: &ThreadPool, count: usize) -> Float {
+        threadpool.install(|| {
+            (0..count)
+                .into_par_iter()
+                .with_min_len(PARCELS_CHUNK_SIZE)
+                .map(|_| self.parcel_ascent())
+                .sum()
+        })
+    }
+
+    /// Reads pressure at `count` heights above the release point
+    /// and returns the sum of read values.
+    pub fn field_access(&self, count: usize) -> Float {
+        let step = 10_000.0 / count as Float;
+
+        (0..count)
+            .map(|i| {
+                self.environment
+                    .get_field_value(
+                        self.start_coords.0,
+                        self.start_coords.1,
+                        i as Float * step,
+                        EnvFields::Pressure,
+                    )
+                    .expect("Benchmark field access failed")
+            })
+            .sum()
+    }
+
+    /// Same as [`BenchSetup::field_access`], but with
+    /// the search cache kept between the reads.
+    pub fn field_access_cached(&self, count: usize) -> Float {
+        let step = 10_000.0 / count as Float;
+        let cache = SearchCache::default();
+
+        (0..count)
+            .map(|i| {
+                self.environment
+                    .get_field_value_cached(
+                        self.start_coords.0,
+                        self.start_coords.1,
+                        i as Float * step,
+                        EnvFields::Pressure,
+                        &cache,
+                    )
+                    .expect("Benchmark field access failed")
+            })
+            .sum()
+    }
+}
+
+impl Default for BenchSetup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
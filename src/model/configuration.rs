@@ -20,27 +20,42 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! Module responsible for parsing and checking the configuration file.
 //!
 //! To provide meaningful error messages. The configuration file uses
-//! [YAML](https://en.wikipedia.org/wiki/YAML) and `serde` to enforce
-//! strong typing and automatic type checking.
+//! [YAML](https://en.wikipedia.org/wiki/YAML) (or JSON, when the file
+//! has `.json` extension) and `serde` to enforce strong typing
+//! and automatic type checking.
 //!
 //! The structures and their fields in this module directly correspond to
 //! the fields inside `config.yaml` so you can check this documentation
 //! for more details how to set the config file.
+//!
+//! Some fields can be overriden with environment variables, which take
+//! precedence over the values in the configuration file:
+//!
+//! - `PATS_THREADS` - [`Resources::threads`]
+//! - `PATS_MEMORY_MB` - [`Resources::memory`]
+//! - `PATS_TIMESTEP` - [`DateTime::timestep`]
+//!
+//! Resources can be also set with `--threads` and `--memory-mb` command
+//! line options, which take precedence over both.
 
-use super::LonLat;
+use super::{environment::read_valid_time, LonLat};
 use crate::errors::{ConfigError, InputError};
 use crate::Float;
 use chrono::NaiveDateTime;
 use eccodes::{
     CodesHandle, FallibleIterator,
-    KeyType::{FloatArray, Int},
+    KeyType::{FloatArray, Int, Str},
     ProductKind::GRIB,
 };
-use serde::Deserialize;
+use log::debug;
+use serde::{Deserialize, Serialize};
 use std::{
+    env::{self, VarError},
     fs,
     path::{Path, PathBuf},
+    str::FromStr,
 };
+use sysinfo::{RefreshKind, System, SystemExt};
 
 type Shape = (usize, usize);
 
@@ -48,7 +63,7 @@ type Shape = (usize, usize);
 ///
 /// Model domain is defined as the area from which parcels
 /// start their plus margins for parcels released near the domain edge.
-#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize)]
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
 pub struct Domain {
     /// Longitude (in degrees) of south-west domain corner.
     ///
@@ -69,6 +84,10 @@ pub struct Domain {
     /// Domain shape (in model gridpoints/parcels). Represents
     /// how much parcels will be released along each axis.
     ///
+    /// Parcels are released only within this shape, margins
+    /// do not contribute any parcels. Parcels released near the edge
+    /// can be tagged in output with [`Output::edge_distance`].
+    ///
     /// Total number of released parcels cannot be smaller than `1`.
     pub shape: (u16, u16),
 
@@ -77,11 +96,106 @@ pub struct Domain {
     /// area, but the input data will be read there so that parcels can use it.
     ///
     /// Defaults to `1.0`. Cannot be less than `0.1`.
+    /// Ignored when [`Input::buffer_extent`] or
+    /// [`Domain::margin_gridpoints`] is set.
     #[serde(default = "Domain::default_margins")]
     pub margins: (Float, Float),
+
+    /// _(Optional)_ Domain margins as a number of input data gridpoints
+    /// for lon and lat axis respectively, alternative to [`Domain::margins`].
+    ///
+    /// Interpolation of environment data near the domain edge needs
+    /// neighbouring gridpoints, so specifying margins in gridpoints
+    /// is independent of the input data resolution.
+    /// Cannot be less than `2`. Ignored when [`Input::buffer_extent`] is set.
+    #[serde(default)]
+    pub margin_gridpoints: Option<(usize, usize)>,
+
+    /// _(Optional)_ List of `[lon, lat]` points (in degrees) from which
+    /// parcels are released, eg. locations of radiosonde stations.
+    ///
+    /// When set, parcels are released only from these points instead
+    /// of the regular domain grid. Input data is still buffered in
+    /// the extent of the domain grid and margins, so all points
+    /// must lie within that extent.
+    #[serde(default)]
+    pub points: Option<Vec<(Float, Float)>>,
+
+    /// _(Optional)_ Expected duration (in seconds) of parcel ascent,
+    /// used to check margins when parcels are advected (`3d` feature).
+    ///
+    /// When margins are smaller than the distance parcels can be advected
+    /// by the strongest wind in buffered data during this time, buffered
+    /// extent is expanded (with a warning), so that parcels released
+    /// near the domain edge do not leave it. Must be positive.
+    #[serde(default)]
+    pub advection_time: Option<Float>,
+
+    /// _(Optional)_ Rotation (in degrees, counterclockwise) of the domain grid
+    /// about its center, eg. to align the grid with the mean flow.
+    ///
+    /// The reference point remains the south-west corner of the rotated grid.
+    /// Angle is measured from the x axis of the projection (approximately
+    /// from the east). Must be in range `-180..=180`.
+    #[serde(default)]
+    pub rotation_deg: Option<Float>,
+
+    /// _(Optional)_ Shape of the Earth used by the domain projection.
+    ///
+    /// Spherical Earth should be used when input data comes from a model
+    /// which assumes it, so that the domain grid matches the input grid.
+    /// Defaults to `wgs84`.
+    #[serde(default)]
+    pub earth_model: EarthModel,
+
+    /// _(Optional)_ Offset (in meters) of release points along x and y axis
+    /// of the domain grid, applied after projecting the reference point.
+    ///
+    /// Offsetting parcels (eg. by half of the input grid cell) from the input
+    /// data nodes allows to probe interpolation accuracy across the cell.
+    /// Defaults to `[0.0, 0.0]`. Offset points must remain within the buffered
+    /// extent of input data. Ignored when [`Domain::points`] is set.
+    #[serde(default)]
+    pub stagger: (Float, Float),
 }
 
 impl Domain {
+    /// Creates a domain with default margins,
+    /// checking that it is within limits.
+    pub fn new(
+        ref_lon: Float,
+        ref_lat: Float,
+        spacing: Float,
+        shape: (u16, u16),
+    ) -> Result<Self, ConfigError> {
+        let domain = Domain {
+            ref_lon,
+            ref_lat,
+            spacing,
+            shape,
+            margins: Domain::default_margins(),
+            margin_gridpoints: None,
+            points: None,
+            advection_time: None,
+            rotation_deg: None,
+            earth_model: EarthModel::default(),
+            stagger: (0.0, 0.0),
+        };
+
+        domain.check_bounds()?;
+
+        Ok(domain)
+    }
+
+    /// Sets domain margins (in degrees) for lon and lat axis,
+    /// checking that they are within limits.
+    pub fn with_margins(mut self, margins: (Float, Float)) -> Result<Self, ConfigError> {
+        self.margins = margins;
+        self.check_bounds()?;
+
+        Ok(self)
+    }
+
     /// Checks if domain specification follows conventions
     /// and limits.
     pub fn check_bounds(&self) -> Result<(), ConfigError> {
@@ -115,16 +229,72 @@ impl Domain {
             ));
         }
 
+        if let Some(margin_gridpoints) = self.margin_gridpoints {
+            if margin_gridpoints.0 < 2 || margin_gridpoints.1 < 2 {
+                return Err(ConfigError::OutOfBounds(
+                    "Margins cannot be smaller than 2 gridpoints",
+                ));
+            }
+        }
+
+        if matches!(self.advection_time, Some(time) if !(time > 0.0 && time.is_finite())) {
+            return Err(ConfigError::OutOfBounds("Advection time must be positive"));
+        }
+
+        if matches!(self.rotation_deg, Some(rotation) if !(-180.0..=180.0).contains(&rotation)) {
+            return Err(ConfigError::OutOfBounds(
+                "Domain rotation must be between -180 and 180 degrees",
+            ));
+        }
+
+        if !self.stagger.0.is_finite() || !self.stagger.1.is_finite() {
+            return Err(ConfigError::OutOfBounds("Grid stagger must be finite"));
+        }
+
+        if let Some(points) = &self.points {
+            if points.is_empty() {
+                return Err(ConfigError::OutOfBounds(
+                    "List of release points cannot be empty",
+                ));
+            }
+
+            if points
+                .iter()
+                .any(|(lon, lat)| !(-180.0..180.0).contains(lon) || !(-90.0..90.0).contains(lat))
+            {
+                return Err(ConfigError::OutOfBounds(
+                    "Release point longitude or latitude is too low or too high",
+                ));
+            }
+        }
+
         Ok(())
     }
 
     fn default_margins() -> (Float, Float) {
         (1.0, 1.0)
     }
+
+    /// Distance (in domain gridpoints) of parcel release point
+    /// with given grid index to the nearest domain edge.
+    ///
+    /// Returns `None` when parcels are released from listed points,
+    /// as they are not placed on the domain grid.
+    pub fn edge_distance(&self, grid_index: (usize, usize)) -> Option<usize> {
+        if self.points.is_some() {
+            return None;
+        }
+
+        let (i, j) = grid_index;
+        let last_i = usize::from(self.shape.0) - 1;
+        let last_j = usize::from(self.shape.1) - 1;
+
+        [i, j, last_i - i, last_j - j].into_iter().min()
+    }
 }
 
 /// Fields with information about time used by model.
-#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize)]
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
 pub struct DateTime {
     /// Timestep (in seconds) used by the model.
     ///
@@ -139,15 +309,36 @@ pub struct DateTime {
     pub start: NaiveDateTime,
 }
 
+impl DateTime {
+    /// Creates datetime settings,
+    /// checking that they are within limits.
+    pub fn new(start: NaiveDateTime, timestep: Float) -> Result<Self, ConfigError> {
+        let datetime = DateTime { timestep, start };
+
+        datetime.check_bounds()?;
+
+        Ok(datetime)
+    }
+
+    /// Checks if timestep is positive.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if self.timestep <= 0.0 {
+            return Err(ConfigError::OutOfBounds("Timestep must be positive"));
+        }
+
+        Ok(())
+    }
+}
+
 /// Fields with information about model input data
 /// for providing boundary conditions.
-#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize)]
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
 pub struct Input {
     /// Level type of GRIB messages in input files from
     /// which 3D boundary conditions data should be read.
     ///
-    /// Currently the model will only work when this field
-    /// is set to "isobaricInhPa".
+    /// Must be `isobaricInhPa` or `isobaricInPa` (pressure levels with
+    /// values in hPa or Pa respectively), as only isobaric levels are supported.
     pub level_type: String,
 
     /// List of input GRIB files to read boundary coonditions.
@@ -156,29 +347,380 @@ pub struct Input {
     ///
     /// - Data inside files must cover at least whole with margins.
     /// - Required variables for surface levels are: temperature, dewpoint,
-    /// u and v wind components, pressure and geopotential.
+    /// pressure and geopotential (and u and v wind components with `3d` feature).
+    /// - Without `terrain_following` surface wind can be missing (wind at release height is used).
     /// - Required variables for pressure levels are: temperature, geopotential,
     /// specific humidity and u and v wind components.
-    /// - For each variable all levels must be unique.
-    /// - Files must contain data only for one datetime.
+    /// - Repeated levels of a variable (eg. in overlapping files) are used only once.
+    /// - Files must contain data only for one valid time (it is checked).
     /// - None of the files can be empty.
+    /// - Data must be on a regular grid (reduced Gaussian grids must be regridded).
     /// - Ideally, there should be only data actually used by model in files.
     pub data_files: Vec<PathBuf>,
 
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    #[serde(default = "Input::uninitialized_shape")]
+    #[serde(default = "Input::uninitialized_shape", skip_serializing)]
     pub shape: (usize, usize),
 
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    #[serde(default = "Input::uninitialized_distinct_lonlats")]
+    #[serde(default = "Input::uninitialized_distinct_lonlats", skip_serializing)]
     pub distinct_lonlats: LonLat<Vec<Float>>,
+
+    /// _(Optional)_ Geographic extent of input data buffered by the model.
+    /// When set, it is used instead of the extent derived from domain
+    /// and margins, so the data can be buffered in a larger (or differently
+    /// shaped) area than the area from which parcels are released.
+    ///
+    /// The extent must fully contain the domain and must be covered
+    /// by the input data.
+    #[serde(default)]
+    pub buffer_extent: Option<BufferExtent>,
+
+    /// _(Optional)_ GRIB short names of variables read by the model.
+    ///
+    /// Different data providers use different short names for the same
+    /// variable, so they can be changed here. Each variable not set here
+    /// uses the default (ECMWF) short name.
+    ///
+    /// Note that only names can be changed, variables must have
+    /// the same meaning and units as the default ones.
+    #[serde(default)]
+    pub variable_names: VariableNames,
+
+    /// _(Optional)_ Use surface data as the lower boundary
+    /// of each environment column. Defaults to `false`.
+    ///
+    /// Over elevated terrain the lowest pressure levels can be
+    /// below the surface. When enabled, such levels are ignored and
+    /// fields between the surface and the lowest level above it
+    /// are interpolated from surface values instead of extrapolated.
+    #[serde(default)]
+    pub terrain_following: bool,
+
+    /// _(Optional)_ Check buffered fields for superadiabatic layers
+    /// and log a warning when any is found. Defaults to `false`.
+    ///
+    /// Such layers are often an artifact of input data
+    /// and result in unrealistic parcel ascent and CAPE.
+    #[serde(default)]
+    pub check_superadiabatic: bool,
+
+    /// _(Optional)_ Smooth vertical velocity computed from input
+    /// vertical motion with 1-2-1 filter over levels. Defaults to `false`.
+    ///
+    /// Velocity computed from layers thickness is noisy at level
+    /// boundaries, which makes initial velocity of parcels
+    /// released with `use_environmental_w` jumpy.
+    #[serde(default)]
+    pub smooth_vertical_velocity: bool,
+
+    /// _(Optional)_ Treat the humidity field read as specific humidity
+    /// ([`VariableNames::spec_humidity`]) as mixing ratio. Defaults to `false`.
+    ///
+    /// Some input data provide mixing ratio labelled as specific humidity.
+    /// When set, the field is converted to specific humidity on input,
+    /// so that virtual temperature of the environment is not biased.
+    #[serde(default)]
+    pub humidity_is_mixing_ratio: bool,
+
+    /// _(Optional)_ Value replacing input data points masked as missing
+    /// by GRIB bitmap (eg. pressure levels below the ground).
+    ///
+    /// Defaults to NaN, so that parcels accessing such points
+    /// fail with `missing_data` error instead of using the missing
+    /// value indicator as regular data.
+    #[serde(default)]
+    pub missing_value_fill: Option<Float>,
+
+    /// _(Optional)_ Valid time of the input data.
+    ///
+    /// When set, the model checks that input data is valid at this time.
+    /// Otherwise it is read from the first message of input files
+    /// and all other messages must have the same valid time.
+    #[serde(default)]
+    pub valid_time: Option<NaiveDateTime>,
+
+    /// _(Optional)_ Input GRIB files with the same variables and grid
+    /// as [`Input::data_files`], but valid at a later time.
+    ///
+    /// When set, parcels sample the environment linearly interpolated
+    /// in time between both valid times, at the model time of the parcel
+    /// (before the first and after the later valid time the nearest one is used).
+    /// Note that fields are buffered for both times, so the memory usage doubles.
+    #[serde(default)]
+    pub later_data_files: Option<Vec<PathBuf>>,
+
+    /// _(Optional)_ Valid time of the later input data.
+    ///
+    /// It is checked or read in the same way as [`Input::valid_time`]
+    /// and must be after it.
+    #[serde(default)]
+    pub later_valid_time: Option<NaiveDateTime>,
+}
+
+/// GRIB short names of variables read by the model.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct VariableNames {
+    /// Geopotential (in m^2/s^2) on levels above ground. Defaults to `z`.
+    #[serde(default = "VariableNames::default_geopotential")]
+    pub geopotential: String,
+
+    /// Temperature on levels above ground. Defaults to `t`.
+    #[serde(default = "VariableNames::default_temperature")]
+    pub temperature: String,
+
+    /// Specific humidity on levels above ground. Defaults to `q`.
+    #[serde(default = "VariableNames::default_spec_humidity")]
+    pub spec_humidity: String,
+
+    /// U component of wind on levels above ground. Defaults to `u`.
+    #[serde(default = "VariableNames::default_u_wind")]
+    pub u_wind: String,
+
+    /// V component of wind on levels above ground. Defaults to `v`.
+    #[serde(default = "VariableNames::default_v_wind")]
+    pub v_wind: String,
+
+    /// Vertical velocity (in Pa/s) on levels above ground. Defaults to `w`.
+    ///
+    /// This variable is optional, when it is missing in input
+    /// vertical velocity is assumed to be zero.
+    #[serde(default = "VariableNames::default_vertical_motion")]
+    pub vertical_motion: String,
+
+    /// Geopotential (in m^2/s^2) at surface. Defaults to `z`.
+    #[serde(default = "VariableNames::default_geopotential")]
+    pub surface_geopotential: String,
+
+    /// Surface pressure. Defaults to `sp`.
+    #[serde(default = "VariableNames::default_surface_pressure")]
+    pub surface_pressure: String,
+
+    /// 2 metre temperature. Defaults to `2t`.
+    #[serde(default = "VariableNames::default_temperature_2m")]
+    pub temperature_2m: String,
+
+    /// 2 metre dewpoint temperature. Defaults to `2d`.
+    #[serde(default = "VariableNames::default_dewpoint_2m")]
+    pub dewpoint_2m: String,
+
+    /// 10 metre U wind component. Defaults to `10u`.
+    /// Read only when horizontal advection (`3d` feature) is enabled.
+    #[serde(default = "VariableNames::default_u_wind_10m")]
+    pub u_wind_10m: String,
+
+    /// 10 metre V wind component. Defaults to `10v`.
+    /// Read only when horizontal advection (`3d` feature) is enabled.
+    #[serde(default = "VariableNames::default_v_wind_10m")]
+    pub v_wind_10m: String,
+}
+
+impl VariableNames {
+    fn default_geopotential() -> String {
+        "z".to_string()
+    }
+
+    fn default_temperature() -> String {
+        "t".to_string()
+    }
+
+    fn default_spec_humidity() -> String {
+        "q".to_string()
+    }
+
+    fn default_u_wind() -> String {
+        "u".to_string()
+    }
+
+    fn default_v_wind() -> String {
+        "v".to_string()
+    }
+
+    fn default_vertical_motion() -> String {
+        "w".to_string()
+    }
+
+    fn default_surface_pressure() -> String {
+        "sp".to_string()
+    }
+
+    fn default_temperature_2m() -> String {
+        "2t".to_string()
+    }
+
+    fn default_dewpoint_2m() -> String {
+        "2d".to_string()
+    }
+
+    fn default_u_wind_10m() -> String {
+        "10u".to_string()
+    }
+
+    fn default_v_wind_10m() -> String {
+        "10v".to_string()
+    }
+
+    /// Short names of all variables read on levels above ground.
+    pub fn fields_names(&self) -> [&str; 6] {
+        [
+            &self.geopotential,
+            &self.temperature,
+            &self.spec_humidity,
+            &self.u_wind,
+            &self.v_wind,
+            &self.vertical_motion,
+        ]
+    }
+
+    /// Short names of all variables read at surface.
+    ///
+    /// Surface wind is only needed for horizontal advection,
+    /// so it is omitted when `3d` feature is disabled.
+    pub fn surfaces_names(&self) -> Vec<&str> {
+        let mut names = vec![
+            self.surface_geopotential.as_str(),
+            &self.surface_pressure,
+            &self.temperature_2m,
+            &self.dewpoint_2m,
+        ];
+
+        if cfg!(feature = "3d") {
+            names.push(&self.u_wind_10m);
+            names.push(&self.v_wind_10m);
+        }
+
+        names
+    }
+}
+
+impl Default for VariableNames {
+    fn default() -> Self {
+        VariableNames {
+            geopotential: VariableNames::default_geopotential(),
+            temperature: VariableNames::default_temperature(),
+            spec_humidity: VariableNames::default_spec_humidity(),
+            u_wind: VariableNames::default_u_wind(),
+            v_wind: VariableNames::default_v_wind(),
+            vertical_motion: VariableNames::default_vertical_motion(),
+            surface_geopotential: VariableNames::default_geopotential(),
+            surface_pressure: VariableNames::default_surface_pressure(),
+            temperature_2m: VariableNames::default_temperature_2m(),
+            dewpoint_2m: VariableNames::default_dewpoint_2m(),
+            u_wind_10m: VariableNames::default_u_wind_10m(),
+            v_wind_10m: VariableNames::default_v_wind_10m(),
+        }
+    }
+}
+
+/// Geographic extent (in degrees) of input data buffered by the model.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct BufferExtent {
+    /// Northern edge latitude. Must meet the condition: `-90 < north < 90`
+    pub north: Float,
+
+    /// Southern edge latitude. Must meet the condition: `-90 < south < north`
+    pub south: Float,
+
+    /// Western edge longitude. Must meet the condition: `-180 < west < 180`
+    pub west: Float,
+
+    /// Eastern edge longitude. Must meet the condition: `west < east < 180`
+    pub east: Float,
+}
+
+impl BufferExtent {
+    /// Checks if buffer extent edges are within
+    /// geographic limits and are correctly ordered.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if !(-90.0..90.0).contains(&self.north) || !(-90.0..90.0).contains(&self.south) {
+            return Err(ConfigError::OutOfBounds(
+                "Buffer extent latitude is too low or too high",
+            ));
+        }
+
+        if !(-180.0..180.0).contains(&self.west) || !(-180.0..180.0).contains(&self.east) {
+            return Err(ConfigError::OutOfBounds(
+                "Buffer extent longitude is too low or too high",
+            ));
+        }
+
+        if self.north <= self.south || self.east <= self.west {
+            return Err(ConfigError::OutOfBounds(
+                "Buffer extent north and east edges must be greater than south and west edges",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
+/// Types of isobaric GRIB levels supported in input data,
+/// with pressure (in Pa) of the unit of level value.
+const ISOBARIC_LEVEL_TYPES: [(&str, Float); 2] = [("isobaricInhPa", 100.0), ("isobaricInPa", 1.0)];
+
 impl Input {
+    /// Creates input settings with default variable names
+    /// and without buffer extent.
+    ///
+    /// Shape and coordinates of input grid are not read
+    /// until [`Input::init_shape_and_distinct_lonlats`] is called
+    /// (it is done by [`Config::new`]).
+    pub fn new(level_type: &str, data_files: Vec<PathBuf>) -> Self {
+        Input {
+            level_type: level_type.to_string(),
+            data_files,
+            shape: Input::uninitialized_shape(),
+            distinct_lonlats: Input::uninitialized_distinct_lonlats(),
+            buffer_extent: None,
+            variable_names: VariableNames::default(),
+            terrain_following: false,
+            check_superadiabatic: false,
+            smooth_vertical_velocity: false,
+            humidity_is_mixing_ratio: false,
+            missing_value_fill: None,
+            valid_time: None,
+            later_data_files: None,
+            later_valid_time: None,
+        }
+    }
+
+    /// Checks if the level type is supported and
+    /// optional input settings are within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if self.level_pressure_scale().is_none() {
+            return Err(ConfigError::OutOfBounds(
+                "Level type must be isobaricInhPa or isobaricInPa",
+            ));
+        }
+
+        if let Some(buffer_extent) = &self.buffer_extent {
+            buffer_extent.check_bounds()?;
+        }
+
+        if let Some(later_data_files) = &self.later_data_files {
+            if later_data_files.is_empty() {
+                return Err(ConfigError::OutOfBounds(
+                    "Later data files must be omitted or contain at least one file",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pressure (in Pa) of the unit of input level value,
+    /// empty when the level type is not supported.
+    pub fn level_pressure_scale(&self) -> Option<Float> {
+        ISOBARIC_LEVEL_TYPES
+            .iter()
+            .find(|(level_type, _)| *level_type == self.level_type)
+            .map(|(_, scale)| *scale)
+    }
+
     fn uninitialized_shape() -> (usize, usize) {
         (0, 0)
     }
@@ -198,6 +740,61 @@ impl Input {
         Ok(())
     }
 
+    /// Reads valid time of input data from the first message
+    /// of the first file, or checks it against the configured one.
+    pub fn init_valid_time(&mut self) -> Result<(), InputError> {
+        let any_file = &self.data_files[0];
+        let mut any_file = CodesHandle::new_from_file(any_file, GRIB)?;
+
+        let any_message = any_file.next()?.ok_or(InputError::DataNotSufficient(
+            "One or more input files does not contain any valid GRIB message",
+        ))?;
+
+        let found = read_valid_time(&any_message)?;
+
+        match self.valid_time {
+            Some(expected) if expected != found => {
+                Err(InputError::UnexpectedValidTime { expected, found })
+            }
+            _ => {
+                self.valid_time = Some(found);
+                Ok(())
+            }
+        }
+    }
+
+    /// Input settings for reading the later input data,
+    /// when [`Input::later_data_files`] are set.
+    pub fn later_input(&self) -> Option<Input> {
+        let data_files = self.later_data_files.clone()?;
+
+        Some(Input {
+            data_files,
+            valid_time: self.later_valid_time,
+            later_data_files: None,
+            later_valid_time: None,
+            ..self.clone()
+        })
+    }
+
+    /// Reads (or checks) valid time of the later input data,
+    /// and checks that it is after valid time of input data.
+    pub fn init_later_valid_time(&mut self) -> Result<(), InputError> {
+        if let Some(mut later_input) = self.later_input() {
+            later_input.init_valid_time()?;
+
+            if later_input.valid_time <= self.valid_time {
+                return Err(InputError::IncorrectLaterData(
+                    "later data must be valid after the input data",
+                ));
+            }
+
+            self.later_valid_time = later_input.valid_time;
+        }
+
+        Ok(())
+    }
+
     /// Function to read distinct longitudes and latitudes
     /// and a grid shape of input GRIB files.
     fn read_distinct_lonlats_and_shape(&self) -> Result<(LonLat<Vec<Float>>, Shape), InputError> {
@@ -212,6 +809,16 @@ impl Input {
             "One or more input files does not contain any valid GRIB message",
         ))?;
 
+        // reduced grids do not have the same longitudes in each row,
+        // so they cannot be described by distinct coordinates
+        if let Str(grid_type) = any_message.read_key("gridType")?.value {
+            if grid_type.starts_with("reduced") {
+                return Err(InputError::UnsupportedGrid(
+                    "reduced grids (eg. native ERA5 and IFS output) must be regridded first, for example with `cdo setgridtype,regular`",
+                ));
+            }
+        }
+
         let mut distinct_latitudes: Vec<Float> =
             if let FloatArray(lats) = any_message.read_key("distinctLatitudes")?.value {
                 lats.into_iter().map(|v| v as Float).collect()
@@ -249,13 +856,46 @@ impl Input {
             return Err(InputError::IncorrectKeyType("Nj"));
         };
 
-        Ok(((distinct_longitudes, distinct_latitudes), (ni, nj)))
+        let distinct_lonlats = (distinct_longitudes, distinct_latitudes);
+        check_distinct_lonlats(&distinct_lonlats, (ni, nj))?;
+
+        Ok((distinct_lonlats, (ni, nj)))
+    }
+}
+
+/// Checks that sorted distinct coordinates of input grid describe
+/// a regular grid of given shape, with at least two distinct values
+/// along each axis, as interpolation needs neighbouring gridpoints.
+fn check_distinct_lonlats(
+    distinct_lonlats: &LonLat<Vec<Float>>,
+    shape: Shape,
+) -> Result<(), InputError> {
+    let (lons, lats) = distinct_lonlats;
+
+    if lons.len() < 2 || lats.len() < 2 {
+        return Err(InputError::UnsupportedGrid(
+            "input grid must have at least two distinct longitudes and latitudes",
+        ));
+    }
+
+    if lons.windows(2).any(|w| w[0] >= w[1]) || lats.windows(2).any(|w| w[0] <= w[1]) {
+        return Err(InputError::UnsupportedGrid(
+            "distinct longitudes or latitudes of input grid are repeated",
+        ));
     }
+
+    if lons.len() != shape.0 || lats.len() != shape.1 {
+        return Err(InputError::UnsupportedGrid(
+            "number of distinct longitudes or latitudes does not match the grid shape",
+        ));
+    }
+
+    Ok(())
 }
 
 /// _(Optional)_ Fields with information about
 /// resources available for model.
-#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize)]
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
 pub struct Resources {
     /// _(Optional)_ Thread count used by the model.
     /// The thread pool initiated by this model will use
@@ -268,8 +908,9 @@ pub struct Resources {
     /// _(Optional)_ Heap memory limit for the model in MB.
     /// Useful for enabling meaningful Out-of-memory error messages.
     ///
-    /// Cannot be less than `128`. Defaults to whole addressable-space
-    /// (`2^32` or `2^64` bytes).
+    /// Cannot be less than `128`. Defaults to 90% of memory available
+    /// in the system when the model starts (or to whole addressable-space,
+    /// when available memory cannot be detected).
     ///
     /// Currently, Rust memory allocator aborts the program
     /// when OOM occurs. By default, Rust memory allocator
@@ -288,21 +929,51 @@ pub struct Resources {
     /// memory limit lower than your avilable system memory and check if
     /// OOM error occurs. Be generous when setting the limit but leave some
     /// space for other processes.
-    #[serde(default = "Resources::default_memory")]
-    pub memory: usize,
+    #[serde(default)]
+    pub memory: Option<usize>,
+
+    /// _(Optional)_ Stack size (in MB) of each thread in the thread pool.
+    ///
+    /// Cannot be less than `1`. Defaults to `2`. Increase it
+    /// when workers crash with stack overflow.
+    #[serde(default = "Resources::default_stack_size_mb")]
+    pub stack_size_mb: usize,
 }
 
+/// Fraction of available system memory used
+/// as a default memory limit.
+const AVAILABLE_MEMORY_FRACTION: f64 = 0.9;
+
 impl Resources {
     fn default_threads() -> u16 {
         1
     }
 
-    fn default_memory() -> usize {
-        usize::MAX / (1024 * 1024)
+    fn default_stack_size_mb() -> usize {
+        2
+    }
+
+    /// Memory limit for the model in MB, either
+    /// set in configuration or detected from the system.
+    pub fn memory_limit(&self) -> usize {
+        if let Some(memory) = self.memory {
+            return memory;
+        }
+
+        let system = System::new_with_specifics(RefreshKind::new().with_memory());
+        let available_memory = system.available_memory();
+
+        // memory cannot be detected on some platforms
+        if available_memory == 0 {
+            return usize::MAX / (1024 * 1024);
+        }
+
+        // available memory is in kB
+        ((available_memory as f64 * AVAILABLE_MEMORY_FRACTION) / 1024.0) as usize
     }
 
-    /// Checks if thread count and memory limit are
-    /// above limits.
+    /// Checks if thread count, memory limit
+    /// and stack size are above limits.
     pub fn check_bounds(&self) -> Result<(), ConfigError> {
         if self.threads < 1 {
             return Err(ConfigError::OutOfBounds(
@@ -310,12 +981,18 @@ impl Resources {
             ));
         }
 
-        if self.memory < 128 {
+        if matches!(self.memory, Some(memory) if memory < 128) {
             return Err(ConfigError::OutOfBounds(
                 "Available memory cannot be less than 128 MB",
             ));
         }
 
+        if self.stack_size_mb < 1 {
+            return Err(ConfigError::OutOfBounds(
+                "Thread stack size cannot be less than 1 MB",
+            ));
+        }
+
         Ok(())
     }
 }
@@ -324,36 +1001,1001 @@ impl Default for Resources {
     fn default() -> Self {
         Resources {
             threads: Resources::default_threads(),
-            memory: Resources::default_memory(),
+            memory: None,
+            stack_size_mb: Resources::default_stack_size_mb(),
         }
     }
 }
 
-/// Main config structure representing the fields in
-/// configuration file.
-#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize)]
-pub struct Config {
-    pub domain: Domain,
+/// _(Optional)_ Fields with settings of
+/// the model output.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Output {
+    /// _(Optional)_ Whether to write convective parameters
+    /// and trajectories of parcels. Defaults to `true`.
+    ///
+    /// When disabled, parcels are still simulated, but only
+    /// the run summary is written (eg. for benchmarking).
+    #[serde(default = "Output::default_enabled")]
+    pub enabled: bool,
 
-    pub datetime: DateTime,
+    /// _(Optional)_ Level of detail of convective parameters output.
+    ///
+    /// - `full` - parameters of every parcel in the format selected
+    ///   with [`Output::conv_params_format`].
+    /// - `aggregate` - only statistics (min, mean, max and percentiles)
+    ///   of each parameter over the domain and location of the maximum CAPE
+    ///   in `model_aggregate_params.json` file.
+    ///
+    /// Defaults to `full`. Aggregated output is much faster to consume
+    /// (eg. for monitoring), but trajectories cannot be saved with it.
+    #[serde(default)]
+    pub mode: OutputMode,
 
-    pub input: Input,
+    /// _(Optional)_ Whether to save the full trajectory
+    /// (state at each timestep) of every parcel
+    /// in a separate CSV file.
+    ///
+    /// Defaults to `false`. Writing trajectories is expensive
+    /// for large domains, as thousands of files can be created.
+    #[serde(default = "Output::default_save_trajectories")]
+    pub save_trajectories: bool,
 
+    /// _(Optional)_ Layout of saved trajectories files.
+    ///
+    /// - `per_parcel` - each parcel trajectory is saved in a separate file.
+    /// - `consolidated` - all trajectories are appended to a single
+    ///   `trajectories.csv` file with `parcelId` column.
+    ///
+    /// Defaults to `per_parcel`. Consolidated layout is recommended for
+    /// large domains, as it avoids creating thousands of small files.
     #[serde(default)]
-    pub resources: Resources,
-}
+    pub trajectories_layout: TrajectoriesLayout,
 
-impl Config {
-    /// Config structure constructor, responsible for
-    /// deserializing configuration and checking it.
-    pub fn new_from_file(file_path: &Path) -> Result<Config, ConfigError> {
-        let data = fs::read(file_path)?;
-        let mut config: Config = serde_yaml::from_slice(data.as_slice())?;
+    /// _(Optional)_ File format of computed convective parameters.
+    ///
+    /// - `csv` - `model_convective_params.csv` file.
+    /// - `parquet` - `model_convective_params.parquet` file with typed
+    ///   and nullable columns. Requires the `parquet` feature.
+    ///
+    /// Defaults to `csv`. Parquet is much faster to load for large domains
+    /// and preserves floats exactly and missing values as nulls.
+    #[serde(default)]
+    pub conv_params_format: OutputFormat,
+
+    /// _(Optional)_ Whether to additionally save a single `parcels_joined.csv`
+    /// file in long format, with convective parameters of each parcel
+    /// joined with its trajectory by `parcelId` column.
+    ///
+    /// When trajectories are saved, the file has one row for each
+    /// parcel timestep, otherwise one row for each parcel.
+    /// Defaults to `false`. Rows are written in the order parcels finish.
+    #[serde(default)]
+    pub joined_output: bool,
+
+    /// _(Optional)_ Vertical coordinate of reported parcel top
+    /// and levels (condensation level, LFC, EL).
+    ///
+    /// - `msl` - height above mean sea level (in meters).
+    /// - `agl` - height above ground level at release point (in meters).
+    /// - `pressure` - parcel pressure at the level (in Pa).
+    ///
+    /// Defaults to `msl`.
+    #[serde(default)]
+    pub vertical_coordinate: VerticalCoordinate,
+
+    /// _(Optional)_ Vertical coordinate in which buoyancy
+    /// is integrated to compute CAPE and CIN.
+    ///
+    /// - `height` - relative virtual temperature excess integrated over height.
+    /// - `log_pressure` - virtual temperature excess integrated over
+    ///   logarithm of parcel pressure, which is less sensitive
+    ///   to irregular spacing of parcel steps.
+    ///
+    /// Defaults to `height`.
+    #[serde(default)]
+    pub cape_integration: CapeIntegration,
+
+    /// _(Optional)_ Whether to report distance (in domain gridpoints)
+    /// of each parcel release point to the nearest domain edge
+    /// in `edge_distance` column of convective parameters.
+    ///
+    /// With `3d` feature parcels released near the edge can be advected
+    /// close to the boundary of buffered data, so such rows
+    /// can be easily dropped in the analysis. The column is empty
+    /// when disabled or when parcels are released from listed points.
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub edge_distance: bool,
+
+    /// _(Optional)_ Whether to report wall-time (in microseconds)
+    /// of each parcel ascent integration in `solver_micros` column
+    /// of convective parameters. Defaults to `false`.
+    ///
+    /// Together with always reported `step_count` it helps to find
+    /// parcels that dominate the runtime. Timing depends on the
+    /// machine load, so it makes the output not reproducible.
+    #[serde(default)]
+    pub solver_timing: bool,
+
+    /// _(Optional)_ Reference pressure levels (in hPa) at which
+    /// Lifted Index of each parcel is computed, eg. `[700.0, 500.0, 300.0]`.
+    ///
+    /// Each level adds `lifted_index_<level>` column after other
+    /// convective parameters. Levels must be between `50` and `1050`,
+    /// and at most 8 levels can be set. Defaults to no levels.
+    #[serde(default)]
+    pub lifted_index_levels: Vec<Float>,
+
+    /// _(Optional)_ Format of parcel start coordinates
+    /// (`start_lon` and `start_lat`) in CSV output files.
+    ///
+    /// - `{ decimal: { digits: 4 } }` - decimal degrees with given number
+    ///   of decimal places (full precision when `digits` is not set).
+    /// - `dms` - degrees, minutes and seconds with hemisphere letter,
+    ///   eg. `50°30′15.24″N`.
+    ///
+    /// Defaults to decimal degrees with full precision.
+    /// Parquet and aggregated output always keep decimal degrees.
+    #[serde(default)]
+    pub coordinate_format: CoordinateFormat,
+
+    /// _(Optional)_ Format of CSV output files.
+    #[serde(default)]
+    pub csv: CsvOutput,
+
+    /// _(Optional)_ Compression of CSV output files.
+    ///
+    /// - `none` - files are not compressed.
+    /// - `gzip` - files are compressed with gzip and have `.csv.gz` extension.
+    ///
+    /// Defaults to `none`. Output of large domains is highly compressible
+    /// and many analysis tools read gzipped CSV files directly.
+    #[serde(default)]
+    pub compress: Compression,
+}
+
+/// Format of values in CSV output files.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct CsvOutput {
+    /// _(Optional)_ Character separating fields. Defaults to `,`.
+    ///
+    /// Must be an ASCII character other than quote,
+    /// newline and dot (which is the decimal separator).
+    #[serde(default = "CsvOutput::default_delimiter")]
+    pub delimiter: char,
+
+    /// _(Optional)_ Number of significant digits to which
+    /// floating point values are rounded.
+    ///
+    /// Defaults to full precision. Must be between `1` and `17`.
+    #[serde(default)]
+    pub float_digits: Option<usize>,
+}
+
+/// Format of parcel coordinates in CSV output files.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateFormat {
+    /// Decimal degrees, with given number of decimal places.
+    Decimal {
+        #[serde(default)]
+        digits: Option<usize>,
+    },
+
+    /// Degrees, minutes and seconds.
+    Dms,
+}
+
+impl Default for CoordinateFormat {
+    fn default() -> Self {
+        CoordinateFormat::Decimal { digits: None }
+    }
+}
+
+/// Level of detail of convective parameters output.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    #[default]
+    Full,
+    Aggregate,
+}
+
+/// File format of model output.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+/// Shape of the Earth assumed by the domain projection.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EarthModel {
+    /// WGS84 ellipsoid.
+    #[default]
+    Wgs84,
+
+    /// Sphere with radius of 6371229 m, as in ECMWF and NCEP models.
+    Sphere,
+}
+
+/// Compression of CSV output files.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// Vertical coordinate of levels in model output.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerticalCoordinate {
+    #[default]
+    Msl,
+    Agl,
+    Pressure,
+}
+
+/// Vertical coordinate of CAPE and CIN integration.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapeIntegration {
+    #[default]
+    Height,
+    LogPressure,
+}
+
+/// Layout of saved trajectories files.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrajectoriesLayout {
+    #[default]
+    PerParcel,
+    Consolidated,
+}
+
+/// Largest number of Lifted Index levels, so that
+/// convective parameters of each parcel have a fixed size.
+pub const MAX_LIFTED_INDEX_LEVELS: usize = 8;
+
+impl Output {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_save_trajectories() -> bool {
+        false
+    }
+
+    /// Checks if selected output formats are available
+    /// in this build and if Lifted Index levels
+    /// and coordinate format are correct.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if self.conv_params_format == OutputFormat::Parquet && !cfg!(feature = "parquet") {
+            return Err(ConfigError::OutOfBounds(
+                "Parquet output requires the model to be compiled with parquet feature",
+            ));
+        }
+
+        if self.mode == OutputMode::Aggregate && self.save_trajectories {
+            return Err(ConfigError::OutOfBounds(
+                "Trajectories cannot be saved with aggregated output",
+            ));
+        }
+
+        if matches!(
+            self.coordinate_format,
+            CoordinateFormat::Decimal { digits: Some(digits) } if digits > 15
+        ) {
+            return Err(ConfigError::OutOfBounds(
+                "Number of decimal places of coordinates must be between 0 and 15",
+            ));
+        }
+
+        if self
+            .lifted_index_levels
+            .iter()
+            .any(|level| !(50.0..=1050.0).contains(level))
+        {
+            return Err(ConfigError::OutOfBounds(
+                "Lifted Index levels must be between 50 and 1050 hPa",
+            ));
+        }
+
+        if self.lifted_index_levels.len() > MAX_LIFTED_INDEX_LEVELS {
+            return Err(ConfigError::OutOfBounds(
+                "At most 8 Lifted Index levels can be set",
+            ));
+        }
+
+        self.csv.check_bounds()?;
+
+        Ok(())
+    }
+}
+
+impl CsvOutput {
+    fn default_delimiter() -> char {
+        ','
+    }
+
+    /// Checks if delimiter and number
+    /// of digits are within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if !self.delimiter.is_ascii() || ['"', '\n', '\r', '.'].contains(&self.delimiter) {
+            return Err(ConfigError::OutOfBounds(
+                "CSV delimiter must be an ASCII character other than quote, newline and dot",
+            ));
+        }
+
+        if matches!(self.float_digits, Some(digits) if !(1..=17).contains(&digits)) {
+            return Err(ConfigError::OutOfBounds(
+                "Number of significant digits in CSV output must be between 1 and 17",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for CsvOutput {
+    fn default() -> Self {
+        CsvOutput {
+            delimiter: CsvOutput::default_delimiter(),
+            float_digits: None,
+        }
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            enabled: Output::default_enabled(),
+            mode: OutputMode::default(),
+            save_trajectories: Output::default_save_trajectories(),
+            trajectories_layout: TrajectoriesLayout::default(),
+            conv_params_format: OutputFormat::default(),
+            joined_output: false,
+            vertical_coordinate: VerticalCoordinate::default(),
+            cape_integration: CapeIntegration::default(),
+            edge_distance: false,
+            solver_timing: false,
+            lifted_index_levels: vec![],
+            coordinate_format: CoordinateFormat::default(),
+            csv: CsvOutput::default(),
+            compress: Compression::default(),
+        }
+    }
+}
+
+/// _(Optional)_ Fields with settings of
+/// parcels released by the model.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Parcel {
+    /// _(Optional)_ Whether to initialize parcel vertical velocity
+    /// with environmental vertical velocity at the release point,
+    /// instead of constant `0.2 m/s`.
+    ///
+    /// Parcels released where there is no synoptic ascent
+    /// (vertical velocity is not positive) will not ascent.
+    ///
+    /// Defaults to `false`.
+    #[serde(default = "Parcel::default_use_environmental_w")]
+    pub use_environmental_w: bool,
+
+    /// _(Optional)_ Temperature used for computing parcel buoyancy.
+    ///
+    /// - `virtual` - virtual temperature, which accounts only for water vapour.
+    /// - `density` - density temperature, which additionally accounts for
+    ///   the weight of water condensed in the parcel (water loading).
+    ///
+    /// Defaults to `virtual`. Parcel ascent is still pseudoadiabatic, so
+    /// condensed water does not change parcel thermodynamics, but with
+    /// `density` it is carried by the parcel and reduces its buoyancy above
+    /// the condensation level. Convective parameters (LFC, EL, CAPE, CIN)
+    /// are computed from virtual temperature with both settings.
+    #[serde(default)]
+    pub buoyancy: BuoyancyFormulation,
+
+    /// _(Optional)_ Number of timesteps for which the parcel can
+    /// continue its ascent with non-positive vertical velocity.
+    ///
+    /// Defaults to `0`, so the ascent ends when the parcel stops.
+    /// Near the equilibrium level parcel can briefly descend and
+    /// ascend again, and with this tolerance such overshoot is
+    /// included in the ascent. Steps are counted for the whole ascent,
+    /// so oscillating parcel eventually stops. Large values are not
+    /// recommended, as descending parcel can leave the input data extent.
+    #[serde(default)]
+    pub overshoot_steps: u32,
+
+    /// _(Optional)_ Level from which parcels are released.
+    ///
+    /// - `surface` - surface, with 2 metre temperature and dewpoint.
+    /// - `pressure_hpa: <p>` - pressure level (in hPa).
+    /// - `height_agl: <h>` - height above the surface (in meters).
+    ///
+    /// Defaults to `surface`. Above the surface the parcel is initialized
+    /// with environmental temperature, pressure and humidity at the level,
+    /// so the level must be within the environment column.
+    #[serde(default)]
+    pub release_level: ReleaseLevel,
+
+    /// _(Optional)_ Number of times the ascent is repeated with halved
+    /// timestep when it stops with an error of thermodynamic computation.
+    ///
+    /// Defaults to `0` (no retries). Such errors are often numerical
+    /// (too large step near saturation) rather than physical, so the parcel
+    /// can complete its ascent with smaller timestep. Retried parcels have
+    /// different results than with the configured timestep, so the number
+    /// of retries is reported in `timestep_retries` column.
+    /// Cannot be larger than `10`.
+    #[serde(default = "Parcel::default_timestep_retries")]
+    pub timestep_retries: u32,
+
+    /// _(Optional)_ Random perturbations of initial parcel
+    /// temperature and humidity.
+    ///
+    /// When set, several parcels (ensemble members) are released
+    /// from each point, each with perturbations drawn from normal
+    /// distributions. Output rows are tagged with the member index.
+    /// Defaults to none, so a single unperturbed parcel is released.
+    #[serde(default)]
+    pub perturbation: Option<Perturbation>,
+}
+
+/// Random perturbations of initial parcel state.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Perturbation {
+    /// Standard deviation of temperature perturbation (in K).
+    pub temp_sigma: Float,
+
+    /// Standard deviation of mixing ratio perturbation (in kg/kg).
+    ///
+    /// Perturbed mixing ratio is limited by the saturation.
+    pub q_sigma: Float,
+
+    /// Number of parcels released from each point.
+    pub members: usize,
+
+    /// _(Optional)_ Seed of the random number generator. Defaults to `0`.
+    ///
+    /// Perturbations depend only on the seed, release point and member
+    /// index, so runs with the same seed give the same results.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Level from which parcels are released.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseLevel {
+    #[default]
+    Surface,
+    PressureHpa(Float),
+    HeightAgl(Float),
+}
+
+/// Temperature used for computing parcel buoyancy.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuoyancyFormulation {
+    #[default]
+    Virtual,
+    Density,
+}
+
+/// Largest number of retries with halved timestep,
+/// after which the timestep is over thousand times smaller.
+const MAX_TIMESTEP_RETRIES: u32 = 10;
+
+impl Parcel {
+    fn default_use_environmental_w() -> bool {
+        false
+    }
+
+    fn default_timestep_retries() -> u32 {
+        0
+    }
+
+    /// Checks if the release level and
+    /// perturbations are within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        match self.release_level {
+            ReleaseLevel::PressureHpa(pres) if !(pres > 0.0 && pres < 1100.0) => {
+                return Err(ConfigError::OutOfBounds(
+                    "Release pressure level must be between 0 and 1100 hPa",
+                ));
+            }
+            ReleaseLevel::HeightAgl(height) if !(height >= 0.0 && height.is_finite()) => {
+                return Err(ConfigError::OutOfBounds(
+                    "Release height must be non-negative",
+                ));
+            }
+            _ => (),
+        }
+
+        if self.timestep_retries > MAX_TIMESTEP_RETRIES {
+            return Err(ConfigError::OutOfBounds(
+                "Number of timestep retries cannot be larger than 10",
+            ));
+        }
+
+        if let Some(perturbation) = &self.perturbation {
+            perturbation.check_bounds()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Parcel {
+    fn default() -> Self {
+        Parcel {
+            use_environmental_w: Parcel::default_use_environmental_w(),
+            buoyancy: BuoyancyFormulation::default(),
+            overshoot_steps: 0,
+            release_level: ReleaseLevel::default(),
+            timestep_retries: Parcel::default_timestep_retries(),
+            perturbation: None,
+        }
+    }
+}
+
+impl Perturbation {
+    /// Checks if standard deviations and
+    /// number of members are within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        let valid_sigma = |sigma: Float| sigma >= 0.0 && sigma.is_finite();
+
+        if !valid_sigma(self.temp_sigma) || !valid_sigma(self.q_sigma) {
+            return Err(ConfigError::OutOfBounds(
+                "Standard deviations of perturbations must be non-negative",
+            ));
+        }
+
+        if self.members == 0 {
+            return Err(ConfigError::OutOfBounds(
+                "Number of perturbed members must be positive",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// _(Optional)_ Fields with settings of
+/// thermodynamic formulas used by the model.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Thermo {
+    /// _(Optional)_ Formula used for computing saturation
+    /// vapour pressure of the parcel.
+    ///
+    /// - `cascade` - Buck formula over water above 0°C, Buck formula
+    ///   over ice down to -80°C and Wexler formula over ice below.
+    /// - `buck` - Buck formulas over water above 0°C and over ice below.
+    /// - `wexler` - Wexler formulas over water above 0°C and over ice below.
+    /// - `tetens` - Tetens formula over water above 0°C and its
+    ///   variant over ice (Murray, 1967) below.
+    ///
+    /// Defaults to `cascade`. Buck and Wexler formulas have limited
+    /// range of validity, so with `buck` the parcel ascent fails
+    /// when parcel temperature drops below -80°C.
+    #[serde(default)]
+    pub saturation_formula: SaturationFormula,
+
+    /// _(Optional)_ Whether to include ice in the saturated ascent.
+    /// Defaults to `false`.
+    ///
+    /// When enabled, within the mixed-phase temperature range
+    /// saturation vapour pressure and latent heat are blended between
+    /// values for water and ice, linearly with temperature. Below the range
+    /// the parcel is saturated with respect to ice and latent heat of
+    /// sublimation is used. When disabled, latent heat of vaporisation
+    /// is used at all temperatures.
+    #[serde(default)]
+    pub ice_phase: bool,
+
+    /// _(Optional)_ Temperatures (in K) of warm and cold end of
+    /// the mixed-phase range. Used only when `ice_phase` is enabled.
+    ///
+    /// Defaults to `[273.15, 233.15]` (0°C and -40°C). The range must
+    /// be between 232 K and 273.15 K, where saturation over supercooled
+    /// water can be computed.
+    #[serde(default = "Thermo::default_mixed_phase_temperatures")]
+    pub mixed_phase_temperatures: (Float, Float),
+
+    /// _(Optional)_ Mixing ratio (in kg/kg) below which the parcel
+    /// is considered dry and its pseudoadiabatic ascent is switched
+    /// back to the adiabatic one. Defaults to `1e-6`.
+    ///
+    /// Lower values extend the pseudoadiabatic ascent in cold, dry
+    /// upper levels. Must be positive and smaller than `1e-3`.
+    #[serde(default = "Thermo::default_min_mixing_ratio")]
+    pub min_mixing_ratio: Float,
+}
+
+impl Thermo {
+    fn default_mixed_phase_temperatures() -> (Float, Float) {
+        (273.15, 233.15)
+    }
+
+    fn default_min_mixing_ratio() -> Float {
+        1e-6
+    }
+
+    /// Checks if minimal mixing ratio is correct and
+    /// if mixed-phase temperature range is correct
+    /// when ice phase is enabled.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if self.min_mixing_ratio <= 0.0 || self.min_mixing_ratio >= 1e-3 {
+            return Err(ConfigError::OutOfBounds(
+                "Minimal mixing ratio must be positive and smaller than 1e-3",
+            ));
+        }
+
+        if !self.ice_phase {
+            return Ok(());
+        }
+
+        let (warm, cold) = self.mixed_phase_temperatures;
+
+        if cold >= warm {
+            return Err(ConfigError::OutOfBounds(
+                "Warm end of mixed-phase range must be above the cold end",
+            ));
+        }
+
+        if warm > 273.15 || cold < 232.0 {
+            return Err(ConfigError::OutOfBounds(
+                "Mixed-phase range must be between 232 K and 273.15 K",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Thermo {
+    fn default() -> Self {
+        Thermo {
+            saturation_formula: SaturationFormula::default(),
+            ice_phase: false,
+            mixed_phase_temperatures: Thermo::default_mixed_phase_temperatures(),
+            min_mixing_ratio: Thermo::default_min_mixing_ratio(),
+        }
+    }
+}
+
+/// Formula used for computing saturation vapour pressure.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaturationFormula {
+    #[default]
+    Cascade,
+    Buck,
+    Wexler,
+    Tetens,
+}
+
+/// _(Optional)_ Fields with settings of
+/// environment data interpolation.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Default, Deserialize, Serialize)]
+pub struct Interpolation {
+    /// _(Optional)_ Regular height levels onto which input fields
+    /// are interpolated once, after they are buffered.
+    ///
+    /// By default (when not set) fields are kept on input levels, and
+    /// levels surrounding the parcel are searched in each column at every
+    /// access. On regular levels they are found directly, which speeds up
+    /// parcel simulations, but it needs more memory when levels are denser
+    /// than input levels (memory usage of fields scales with the number
+    /// of levels). Fields are interpolated linearly in height, so with spacing
+    /// smaller than spacing of input levels results are almost unchanged.
+    #[serde(default)]
+    pub precompute_height_grid: Option<HeightGridSpacing>,
+}
+
+/// Spacing of regular height levels.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct HeightGridSpacing {
+    /// Spacing (in meters) between levels. Cannot be smaller than `1`.
+    pub dz_m: Float,
+}
+
+impl Interpolation {
+    /// Checks if height grid spacing is within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if matches!(self.precompute_height_grid, Some(grid) if grid.dz_m < 1.0) {
+            return Err(ConfigError::OutOfBounds(
+                "Height grid spacing cannot be smaller than 1 m",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// _(Optional)_ Fields with settings
+/// controlling the model run.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Run {
+    /// _(Optional)_ Fraction of failed parcels above which
+    /// the whole run is aborted. Defaults to `1.0` (never abort).
+    ///
+    /// The fraction is checked every 100 received parcels
+    /// and after all parcels are received, so that
+    /// runs with a broken configuration (eg. wrong units of input
+    /// or domain outside of the data) do not waste time producing
+    /// mostly empty output. Must be between `0` and `1`.
+    #[serde(default = "Run::default_max_failure_fraction")]
+    pub max_failure_fraction: Float,
+
+    /// _(Optional)_ Whether to skip the simulation of parcels
+    /// which cannot become buoyant. Defaults to `false`.
+    ///
+    /// Before the simulation the parcel is lifted in the column above
+    /// the release point, and when above its condensation level it is
+    /// nowhere warmer (in virtual temperature) than the environment
+    /// it has no CAPE. Such parcels are not simulated, but are written
+    /// in the output with zero CAPE and `prescreened` flag. This speeds up
+    /// runs on large domains, but ignores horizontal advection of the parcel
+    /// and its lifting by the environmental vertical motion.
+    #[serde(default)]
+    pub prescreen: bool,
+}
+
+impl Run {
+    fn default_max_failure_fraction() -> Float {
+        1.0
+    }
+
+    /// Checks if maximal fraction of failures is within limits.
+    pub fn check_bounds(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.max_failure_fraction) {
+            return Err(ConfigError::OutOfBounds(
+                "Maximal fraction of failed parcels must be between 0 and 1",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Run {
+    fn default() -> Self {
+        Run {
+            max_failure_fraction: Run::default_max_failure_fraction(),
+            prescreen: false,
+        }
+    }
+}
+
+/// Main config structure representing the fields in
+/// configuration file.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Deserialize, Serialize)]
+pub struct Config {
+    pub domain: Domain,
+
+    pub datetime: DateTime,
+
+    pub input: Input,
+
+    #[serde(default)]
+    pub resources: Resources,
+
+    #[serde(default)]
+    pub output: Output,
+
+    #[serde(default)]
+    pub parcel: Parcel,
+
+    #[serde(default)]
+    pub thermo: Thermo,
+
+    #[serde(default)]
+    pub interpolation: Interpolation,
+
+    #[serde(default)]
+    pub run: Run,
+}
+
+impl Config {
+    /// Config structure constructor, responsible for
+    /// deserializing configuration and checking it.
+    ///
+    /// The file is parsed as YAML or JSON depending on its extension.
+    pub fn new_from_file(file_path: &Path) -> Result<Config, ConfigError> {
+        let data = fs::read(file_path)?;
+
+        let mut config: Config = match file_path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_slice(data.as_slice())?,
+            Some("json") => serde_json::from_slice(data.as_slice())?,
+            _ => return Err(ConfigError::UnknownFormat),
+        };
+
+        config.apply_env_overrides()?;
+
+        config.validated()
+    }
+
+    /// Config structure constructor for assembling
+    /// the configuration in code, without the config file.
+    ///
+    /// Optional settings are set to defaults and
+    /// can be changed before passing the config to the model.
+    /// The config is checked in the same way as [`Config::new_from_file`].
+    pub fn new(domain: Domain, datetime: DateTime, input: Input) -> Result<Config, ConfigError> {
+        let config = Config {
+            domain,
+            datetime,
+            input,
+            resources: Resources::default(),
+            output: Output::default(),
+            parcel: Parcel::default(),
+            thermo: Thermo::default(),
+            interpolation: Interpolation::default(),
+            run: Run::default(),
+        };
+
+        config.validated()
+    }
+
+    /// Overrides configuration fields with values of
+    /// environment variables, when they are set.
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        if let Some(threads) = read_env_override("PATS_THREADS")? {
+            self.resources.threads = threads;
+        }
+
+        if let Some(memory) = read_env_override("PATS_MEMORY_MB")? {
+            self.resources.memory = Some(memory);
+        }
+
+        if let Some(timestep) = read_env_override("PATS_TIMESTEP")? {
+            self.datetime.timestep = timestep;
+        }
+
+        Ok(())
+    }
+
+    /// Checks all configuration components and reads
+    /// the input grid information.
+    fn validated(mut self) -> Result<Config, ConfigError> {
+        self.domain.check_bounds()?;
+        self.datetime.check_bounds()?;
+        self.resources.check_bounds()?;
+        self.input.check_bounds()?;
+        self.output.check_bounds()?;
+        self.parcel.check_bounds()?;
+        self.thermo.check_bounds()?;
+        self.interpolation.check_bounds()?;
+        self.run.check_bounds()?;
+        self.input.init_shape_and_distinct_lonlats()?;
+        self.input.init_valid_time()?;
+        self.input.init_later_valid_time()?;
+
+        Ok(self)
+    }
+}
+
+/// Reads and parses the value of environment variable
+/// overriding a configuration field.
+fn read_env_override<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => {
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::IncorrectEnvVariable(name))?;
+
+            debug!("Configuration overriden with {}", name);
+
+            Ok(Some(value))
+        }
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(ConfigError::IncorrectEnvVariable(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_distinct_lonlats, read_env_override, DateTime, Domain, Input};
+    use crate::Float;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn domain_constructor_validation() {
+        assert!(Domain::new(14.0, 49.0, 1000.0, (10, 10)).is_ok());
+        assert!(Domain::new(14.0, 95.0, 1000.0, (10, 10)).is_err());
+        assert!(Domain::new(14.0, 49.0, 0.5, (10, 10)).is_err());
+
+        let domain = Domain::new(14.0, 49.0, 1000.0, (10, 10)).unwrap();
+        assert!(domain.with_margins((0.0, 1.0)).is_err());
+
+        let mut domain = Domain::new(14.0, 49.0, 1000.0, (10, 10)).unwrap();
+        domain.points = Some(vec![]);
+        assert!(domain.check_bounds().is_err());
+
+        domain.points = Some(vec![(14.5, 49.5), (200.0, 49.5)]);
+        assert!(domain.check_bounds().is_err());
+
+        domain.points = Some(vec![(14.5, 49.5), (15.2, 49.8)]);
+        assert!(domain.check_bounds().is_ok());
+
+        domain.stagger = (500.0, Float::NAN);
+        assert!(domain.check_bounds().is_err());
+    }
+
+    #[test]
+    fn input_level_types() {
+        let input = Input::new("isobaricInhPa", vec![]);
+        assert_eq!(input.level_pressure_scale(), Some(100.0));
+
+        let input = Input::new("isobaricInPa", vec![]);
+        assert_eq!(input.level_pressure_scale(), Some(1.0));
+        assert!(input.check_bounds().is_ok());
+
+        let input = Input::new("hybrid", vec![]);
+        assert!(input.check_bounds().is_err());
+    }
+
+    #[test]
+    fn release_edge_distance() {
+        let mut domain = Domain::new(14.0, 49.0, 1000.0, (10, 6)).unwrap();
+
+        assert_eq!(domain.edge_distance((0, 3)), Some(0));
+        assert_eq!(domain.edge_distance((9, 3)), Some(0));
+        assert_eq!(domain.edge_distance((4, 2)), Some(2));
+        assert_eq!(domain.edge_distance((5, 3)), Some(2));
+        assert_eq!(domain.edge_distance((3, 5)), Some(0));
+
+        domain.points = Some(vec![(14.5, 49.5)]);
+        assert_eq!(domain.edge_distance((0, 0)), None);
+    }
+
+    #[test]
+    fn datetime_constructor_validation() {
+        let start = NaiveDate::from_ymd_opt(2021, 6, 24)
+            .and_then(|date| date.and_hms_opt(6, 0, 0))
+            .unwrap();
+
+        assert!(DateTime::new(start, 1.0).is_ok());
+        assert!(DateTime::new(start, 0.0).is_err());
+    }
+
+    #[test]
+    fn distinct_lonlats_validation() {
+        let lons = vec![14.0, 14.25, 14.5];
+        let lats = vec![50.25, 50.0];
+
+        assert!(check_distinct_lonlats(&(lons.clone(), lats.clone()), (3, 2)).is_ok());
+        assert!(check_distinct_lonlats(&(lons.clone(), lats.clone()), (3, 4)).is_err());
+        assert!(check_distinct_lonlats(&(lons.clone(), vec![50.0]), (3, 1)).is_err());
+        assert!(check_distinct_lonlats(&(vec![], lats.clone()), (0, 2)).is_err());
+        assert!(check_distinct_lonlats(&(vec![14.0, 14.0, 14.5], lats), (3, 2)).is_err());
+    }
+
+    #[test]
+    fn env_override_parsing() {
+        assert_eq!(
+            read_env_override::<u16>("PATS_TEST_UNSET_OVERRIDE").unwrap(),
+            None
+        );
 
-        config.domain.check_bounds()?;
-        config.resources.check_bounds()?;
-        config.input.init_shape_and_distinct_lonlats()?;
+        std::env::set_var("PATS_TEST_THREADS_OVERRIDE", "4");
+        assert_eq!(
+            read_env_override::<u16>("PATS_TEST_THREADS_OVERRIDE").unwrap(),
+            Some(4)
+        );
 
-        Ok(config)
+        std::env::set_var("PATS_TEST_TIMESTEP_OVERRIDE", "fast");
+        assert!(read_env_override::<f64>("PATS_TEST_TIMESTEP_OVERRIDE").is_err());
     }
 }
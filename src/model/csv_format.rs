@@ -0,0 +1,290 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module applying the configured format
+//! (delimiter, float precision, coordinates and compression) to CSV output.
+//!
+//! Values are formatted into fields while writing,
+//! so floats are rounded with their types known.
+
+use super::{
+    configuration::{Compression, CoordinateFormat, CsvOutput},
+    parcel::conv_params::ParamValue,
+};
+use crate::Float;
+use flate2::write::GzEncoder;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+/// CSV writer of output file.
+pub(super) type CsvWriter = csv::Writer<OutputFile>;
+
+/// Output file, which can be compressed on the fly.
+#[derive(Debug)]
+pub(super) enum OutputFile {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(file) => file.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(file) => file.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl OutputFile {
+    /// Flushes the file and, when it is compressed,
+    /// writes the end of the gzip stream.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(mut file) => file.flush(),
+            OutputFile::Gzip(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+
+/// Creates CSV writer of a new file at `out_path`
+/// with the configured delimiter and compression.
+///
+/// Compressed files have `.gz` appended to the path.
+/// After the last record the file must be finished with [`finish_writer`].
+pub(super) fn create_writer(
+    out_path: &Path,
+    format: &CsvOutput,
+    compress: Compression,
+) -> Result<CsvWriter, csv::Error> {
+    let out_file = match compress {
+        Compression::None => OutputFile::Plain(File::create(out_path)?),
+        Compression::Gzip => {
+            let mut gz_path = out_path.as_os_str().to_owned();
+            gz_path.push(".gz");
+
+            OutputFile::Gzip(GzEncoder::new(
+                File::create(gz_path)?,
+                flate2::Compression::default(),
+            ))
+        }
+    };
+
+    Ok(csv::WriterBuilder::new()
+        .delimiter(format.delimiter as u8)
+        .from_writer(out_file))
+}
+
+/// Flushes buffered records and finishes the output file.
+///
+/// Gzip stream would be also finished when the writer is dropped,
+/// but then errors of writing the end of the stream are ignored.
+pub(super) fn finish_writer(writer: CsvWriter) -> Result<(), csv::Error> {
+    let out_file = writer.into_inner().map_err(|err| err.into_error())?;
+    out_file.finish()?;
+
+    Ok(())
+}
+
+/// Converts values of convective parameters into fields, with floats
+/// rounded and start coordinates (the first two values) formatted as configured.
+pub(super) fn params_fields(
+    values: &[ParamValue],
+    format: &CsvOutput,
+    coordinate_format: CoordinateFormat,
+) -> Vec<String> {
+    let format_float =
+        |value: Float| round_float(value, format.float_digits).unwrap_or(format!("{:?}", value));
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match (i, value) {
+            (0 | 1, ParamValue::Float(Some(angle))) => match coordinate_format {
+                CoordinateFormat::Decimal { digits: None } => format_float(*angle),
+                CoordinateFormat::Decimal {
+                    digits: Some(digits),
+                } => format!("{:.*}", digits, angle),
+                CoordinateFormat::Dms if i == 0 => format_dms(*angle, ['E', 'W']),
+                CoordinateFormat::Dms => format_dms(*angle, ['N', 'S']),
+            },
+            (_, ParamValue::Float(Some(value))) => format_float(*value),
+            (_, value) => value.to_string(),
+        })
+        .collect()
+}
+
+/// Formats the angle as degrees, minutes and seconds
+/// (to hundredths) followed by the hemisphere letter.
+///
+/// Prime symbols are used instead of quotes, so that
+/// fields do not need quoting in CSV files.
+fn format_dms(angle: Float, hemispheres: [char; 2]) -> String {
+    if !angle.is_finite() {
+        return angle.to_string();
+    }
+
+    let hemisphere = if angle < 0.0 {
+        hemispheres[1]
+    } else {
+        hemispheres[0]
+    };
+
+    // rounding whole angle prevents 60 seconds or minutes
+    let centiseconds = (angle.abs() * 360_000.0).round() as u64;
+    let degrees = centiseconds / 360_000;
+    let minutes = centiseconds % 360_000 / 6000;
+    let seconds = (centiseconds % 6000) as Float / 100.0;
+
+    format!("{}°{:02}′{:05.2}″{}", degrees, minutes, seconds, hemisphere)
+}
+
+/// Formats the value with given number of significant digits,
+/// when it is set and the value is finite and not zero.
+pub(super) fn round_float(value: Float, digits: Option<usize>) -> Option<String> {
+    let digits = digits?;
+
+    if value == 0.0 || !value.is_finite() {
+        return None;
+    }
+
+    let magnitude = value.abs().log10().floor() as i32;
+    let decimals = digits as i32 - 1 - magnitude;
+
+    if decimals <= 0 {
+        let unit = 10f64.powi(-decimals);
+        return Some(format!("{:.1}", (value / unit).round() * unit));
+    }
+
+    let formatted = format!("{:.*}", decimals as usize, value);
+    let trimmed = formatted.trim_end_matches('0');
+
+    if trimmed.ends_with('.') {
+        return Some(format!("{}0", trimmed));
+    }
+
+    Some(trimmed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_writer, finish_writer, params_fields, round_float};
+    use crate::model::{
+        configuration::{Compression, CoordinateFormat, CsvOutput},
+        parcel::conv_params::{ConvectiveParams, ParamValue},
+    };
+    use crate::Float;
+    use flate2::read::GzDecoder;
+    use std::{env, fs, io::Read, process};
+
+    #[test]
+    fn significant_digits_rounding() {
+        assert_eq!(round_float(301.2587, Some(4)).unwrap(), "301.3");
+        assert_eq!(round_float(-0.000123456, Some(3)).unwrap(), "-0.000123");
+        assert_eq!(round_float(165586.3, Some(3)).unwrap(), "166000.0");
+        assert_eq!(round_float(20.0, Some(5)).unwrap(), "20.0");
+        assert_eq!(round_float(2.5e-7, Some(2)).unwrap(), "0.00000025");
+
+        assert_eq!(round_float(Float::NAN, Some(3)), None);
+        assert_eq!(round_float(0.0, Some(3)), None);
+        assert_eq!(round_float(301.2587, None), None);
+
+        // only floats are rounded, other values are written as they are
+        let format = CsvOutput {
+            float_digits: Some(2),
+            ..CsvOutput::default()
+        };
+        let values = [
+            ParamValue::Float(Some(19.5042)),
+            ParamValue::Float(Some(50.2)),
+            ParamValue::Float(Some(1234.5)),
+            ParamValue::Float(None),
+            ParamValue::Integer(Some(1234)),
+            ParamValue::Bool(true),
+            ParamValue::Text(Some("panic")),
+        ];
+        assert_eq!(
+            params_fields(&values, &format, CoordinateFormat::default()),
+            ["20.0", "50.0", "1200.0", "", "1234", "true", "panic"]
+        );
+    }
+
+    #[test]
+    fn coordinates_formatting() {
+        let header = ConvectiveParams::header(&[]);
+        assert_eq!(
+            &header.iter().take(2).collect::<Vec<_>>(),
+            &["start_lon", "start_lat"]
+        );
+
+        let formatted = |(lon, lat), format| {
+            let values = [ParamValue::Float(Some(lon)), ParamValue::Float(Some(lat))];
+            params_fields(&values, &CsvOutput::default(), format)
+        };
+
+        let format: CoordinateFormat = serde_yaml::from_str("dms").unwrap();
+        assert_eq!(
+            formatted((19.5042, -50.999999), format),
+            ["19°30′15.12″E", "51°00′00.00″S"]
+        );
+        assert_eq!(
+            formatted((-0.25, 0.0), format),
+            ["0°15′00.00″W", "0°00′00.00″N"]
+        );
+
+        let format: CoordinateFormat = serde_yaml::from_str("{ decimal: { digits: 2 } }").unwrap();
+        assert_eq!(formatted((19.5042, 50.2), format), ["19.50", "50.20"]);
+        assert_eq!(
+            formatted((19.5042, 50.2), CoordinateFormat::default()),
+            ["19.5042", "50.2"]
+        );
+    }
+
+    #[test]
+    fn gzip_compressed_output() {
+        let out_dir = env::temp_dir().join(format!("pats_gzip_output_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let out_path = out_dir.join("params.csv");
+        let mut writer =
+            create_writer(&out_path, &CsvOutput::default(), Compression::Gzip).unwrap();
+        writer.write_record(["start_lon", "start_lat"]).unwrap();
+        writer.write_record(["20.0", "50.0"]).unwrap();
+        finish_writer(writer).unwrap();
+
+        assert!(!out_path.exists());
+
+        let mut content = String::new();
+        GzDecoder::new(fs::File::open(out_dir.join("params.csv.gz")).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "start_lon,start_lat\n20.0,50.0\n");
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
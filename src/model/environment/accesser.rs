@@ -21,7 +21,9 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! environment and surface boundary
 //! conditions data.
 
-use super::{bisection, EnvFields, Environment, SurfaceFields};
+use super::{
+    bisection, terrain::TerrainBoundary, EnvFields, Environment, FieldFloat, SurfaceFields,
+};
 use crate::{
     errors::{EnvironmentError, SearchError},
     model::environment::interpolation::{
@@ -29,7 +31,19 @@ use crate::{
     },
     Float,
 };
-use ndarray::s;
+use chrono::NaiveDateTime;
+use ndarray::{s, ArrayView1, ArrayView3};
+use std::cell::Cell;
+
+/// Accuracy (in meters) of the height found by [`Environment::height_of_pressure`].
+const PRESSURE_HEIGHT_TOLERANCE: Float = 0.01;
+
+/// Distance (in degrees) from the edge node of the grid within which
+/// coordinates outside of the grid are considered to be on that node.
+///
+/// Projecting coordinates of a gridpoint back and forth
+/// can move them slightly outside of the grid.
+const NODE_TOLERANCE: Float = 1e-9;
 
 impl Environment {
     /// Function to get interpolated value of given
@@ -40,21 +54,14 @@ impl Environment {
         y: Float,
         field: SurfaceFields,
     ) -> Result<Float, EnvironmentError> {
-        let (lon, lat) = self.projection.inverse_project(x, y);
-
-        let west_lon_index = bisection::find_left_closest(
-            self.surfaces.lons.slice(s![.., 0]).as_slice().unwrap(),
-            &lon,
-        )?;
-
-        let south_lat_index = bisection::find_left_closest(
-            self.surfaces
-                .lats
-                .slice(s![west_lon_index, ..])
-                .as_slice()
-                .unwrap(),
-            &lat,
-        )?;
+        let (lon, lat) = self.projection.try_inverse_project(x, y)?;
+        let (x, y) = self.projection.unrotate(x, y);
+
+        let (west_lon_index, east_lon_index) =
+            find_node_bracket(self.surfaces.lons.slice(s![.., 0]), lon)?;
+
+        let (south_lat_index, north_lat_index) =
+            find_node_bracket(self.surfaces.lats.slice(s![west_lon_index, ..]), lat)?;
 
         let field = match field {
             SurfaceFields::Temperature => self.surfaces.temperature.view(),
@@ -69,9 +76,9 @@ impl Environment {
 
         let horizontal_points = [
             (west_lon_index, south_lat_index),
-            (west_lon_index, south_lat_index + 1),
-            (west_lon_index + 1, south_lat_index),
-            (west_lon_index + 1, south_lat_index + 1),
+            (west_lon_index, north_lat_index),
+            (east_lon_index, south_lat_index),
+            (east_lon_index, north_lat_index),
         ];
 
         let mut ref_points = [Point2D::default(); 4];
@@ -81,15 +88,19 @@ impl Environment {
                 self.fields.lons[[*x_index, *y_index]],
                 self.fields.lats[[*x_index, *y_index]],
             );
-            let (x, y) = self.projection.project(lon, lat);
+            let (x, y) = self.projection.project_unrotated(lon, lat);
 
             ref_points[i] = Point2D {
                 x,
                 y,
-                value: field[[*x_index, *y_index]],
+                value: field[[*x_index, *y_index]] as Float,
             };
         }
 
+        if ref_points.iter().any(|point| point.value.is_nan()) {
+            return Err(EnvironmentError::MissingData);
+        }
+
         let result_val = interpolate_bilinear(x, y, ref_points);
 
         Ok(result_val)
@@ -104,26 +115,37 @@ impl Environment {
         z: Float,
         field: EnvFields,
     ) -> Result<Float, EnvironmentError> {
-        let (lon, lat) = self.projection.inverse_project(x, y);
-
-        let west_lon_index = bisection::find_left_closest(
-            self.fields.lons.slice(s![.., 0]).as_slice().unwrap(),
-            &lon,
-        )?;
-
-        let south_lat_index = bisection::find_left_closest(
-            self.fields
-                .lats
-                .slice(s![west_lon_index, ..])
-                .as_slice()
-                .unwrap(),
-            &lat,
-        )?;
+        self.get_field_value_cached(x, y, z, field, &SearchCache::default())
+    }
 
-        let field = match field {
+    /// Same as [`Environment::get_field_value`], but reuses grid
+    /// indices found in previous searches stored in the `cache`.
+    ///
+    /// Parcel moves only slightly between subsequent field accesses,
+    /// so most of the time it stays in the same grid cell and
+    /// searching the grid again can be skipped.
+    pub fn get_field_value_cached(
+        &self,
+        x: Float,
+        y: Float,
+        z: Float,
+        field: EnvFields,
+        cache: &SearchCache,
+    ) -> Result<Float, EnvironmentError> {
+        let (lon, lat) = self.projection.try_inverse_project(x, y)?;
+
+        // fields are interpolated in unrotated coordinates,
+        // in which neighbouring gridpoints form nearly rectangular cells
+        let (x, y) = self.projection.unrotate(x, y);
+
+        let [west_lon_index, east_lon_index, south_lat_index, north_lat_index] =
+            self.horizontal_indices(lon, lat, cache)?;
+
+        let field_values = match field {
             EnvFields::Pressure => self.fields.pressure.view(),
             EnvFields::Temperature => self.fields.temperature.view(),
             EnvFields::VirtualTemperature => self.fields.virtual_temp.view(),
+            EnvFields::SpecHumidity => self.fields.spec_humidity.view(),
             EnvFields::UWind => self.fields.u_wind.view(),
             EnvFields::VWind => self.fields.v_wind.view(),
             EnvFields::VerticalVel => self.fields.vertical_vel.view(),
@@ -131,63 +153,684 @@ impl Environment {
 
         let horizontal_points = [
             (west_lon_index, south_lat_index),
-            (west_lon_index, south_lat_index + 1),
-            (west_lon_index + 1, south_lat_index),
-            (west_lon_index + 1, south_lat_index + 1),
+            (west_lon_index, north_lat_index),
+            (east_lon_index, south_lat_index),
+            (east_lon_index, north_lat_index),
         ];
 
         let mut ref_points = [Point3D::default(); 8];
 
         for (i, (x_index, y_index)) in horizontal_points.iter().enumerate() {
-            let z_index_search_array = self
-                .fields
-                .height
-                .slice(s![.., *x_index, *y_index])
-                .to_vec();
-
-            let z_index =
-                bisection::find_left_closest(&z_index_search_array, &z).or_else(|err| {
-                    // when searched height is below the lowest level
-                    // we set lowest point to 0-level for extrapolation
-                    // in all other cases error is returned
-
-                    match err {
-                        SearchError::OutOfBounds => {
-                            if z <= self.fields.height[[0, *x_index, *y_index]] {
-                                Ok(0)
-                            } else {
-                                Err(err)
-                            }
-                        }
-                        SearchError::EmptyArray => Err(err),
-                    }
-                })?;
+            let [bottom_point, upper_point] = self.column_points(
+                field,
+                field_values,
+                (*x_index, *y_index),
+                z,
+                &cache.vertical[i],
+            )?;
 
             let (lon, lat) = (
                 self.fields.lons[[*x_index, *y_index]],
                 self.fields.lats[[*x_index, *y_index]],
             );
-            let (x, y) = self.projection.project(lon, lat);
+            let (x, y) = self.projection.project_unrotated(lon, lat);
 
-            // bottom point
             ref_points[i] = Point3D {
                 x,
                 y,
-                z: self.fields.height[[z_index, *x_index, *y_index]],
-                value: field[[z_index, *x_index, *y_index]],
+                z: bottom_point.0,
+                value: bottom_point.1,
             };
 
-            // upper point
             ref_points[i + 4] = Point3D {
                 x,
                 y,
-                z: self.fields.height[[z_index + 1, *x_index, *y_index]],
-                value: field[[z_index + 1, *x_index, *y_index]],
+                z: upper_point.0,
+                value: upper_point.1,
             };
         }
 
+        // points masked as missing in input data are NaN
+        if ref_points
+            .iter()
+            .any(|point| point.value.is_nan() || point.z.is_nan())
+        {
+            return Err(EnvironmentError::MissingData);
+        }
+
         let result_val = interpolate_tilinear(x, y, z, ref_points);
 
         Ok(result_val)
     }
+
+    /// Same as [`Environment::get_field_value`], but at given model time.
+    ///
+    /// When the environment has a later one (see [`Environment::with_later`])
+    /// the value is linearly interpolated in time between both environments.
+    pub fn get_field_value_at(
+        &self,
+        x: Float,
+        y: Float,
+        z: Float,
+        field: EnvFields,
+        datetime: NaiveDateTime,
+    ) -> Result<Float, EnvironmentError> {
+        self.get_field_value_cached_at(x, y, z, field, datetime, &SearchCache::default())
+    }
+
+    /// Same as [`Environment::get_field_value_at`], but reuses grid
+    /// indices found in previous searches stored in the `cache`.
+    ///
+    /// Environments at both times share the grid, so the same cache is used.
+    pub fn get_field_value_cached_at(
+        &self,
+        x: Float,
+        y: Float,
+        z: Float,
+        field: EnvFields,
+        datetime: NaiveDateTime,
+        cache: &SearchCache,
+    ) -> Result<Float, EnvironmentError> {
+        let value = self.get_field_value_cached(x, y, z, field, cache)?;
+
+        let later = match &self.later {
+            Some(later) => later,
+            None => return Ok(value),
+        };
+
+        let weight = later.time_weight(datetime);
+
+        if weight == 0.0 {
+            return Ok(value);
+        }
+
+        let later_value = later
+            .environment
+            .get_field_value_cached(x, y, z, field, cache)?;
+
+        Ok(value + weight * (later_value - value))
+    }
+
+    /// Finds the height at which environmental pressure at given
+    /// (cartographic) coordinates is equal to `pres`, with bisection
+    /// between the `bottom` height and the top of the column.
+    ///
+    /// Returns [`SearchError::OutOfBounds`] when the pressure
+    /// is not found between those heights.
+    pub fn height_of_pressure(
+        &self,
+        x: Float,
+        y: Float,
+        pres: Float,
+        bottom: Float,
+    ) -> Result<Float, EnvironmentError> {
+        let cache = SearchCache::default();
+        let (lon, lat) = self.projection.try_inverse_project(x, y)?;
+
+        let [west_lon_index, east_lon_index, south_lat_index, north_lat_index] =
+            self.horizontal_indices(lon, lat, &cache)?;
+
+        // the lowest of top levels of surrounding columns
+        // is the highest level at which fields can be accessed
+        let top_level = self.fields.height.dim().0 - 1;
+        let top = [
+            (west_lon_index, south_lat_index),
+            (west_lon_index, north_lat_index),
+            (east_lon_index, south_lat_index),
+            (east_lon_index, north_lat_index),
+        ]
+        .iter()
+        .map(|&(x_index, y_index)| self.fields.height[[top_level, x_index, y_index]] as Float)
+        .fold(Float::MAX, Float::min);
+
+        let pressure_at = |z| self.get_field_value_cached(x, y, z, EnvFields::Pressure, &cache);
+
+        if bottom > top || pres > pressure_at(bottom)? || pres < pressure_at(top)? {
+            return Err(SearchError::OutOfBounds.into());
+        }
+
+        let (mut bottom, mut top) = (bottom, top);
+
+        while top - bottom > PRESSURE_HEIGHT_TOLERANCE {
+            let middle = 0.5 * (bottom + top);
+
+            if pressure_at(middle)? > pres {
+                bottom = middle;
+            } else {
+                top = middle;
+            }
+        }
+
+        Ok(0.5 * (bottom + top))
+    }
+
+    /// Finds indices of fields gridpoints surrounding given
+    /// geographic coordinates, or reuses them from the `cache`
+    /// when coordinates are still in the same grid cell.
+    ///
+    /// Returned indices are in order: west, east, south, north.
+    fn horizontal_indices(
+        &self,
+        lon: Float,
+        lat: Float,
+        cache: &SearchCache,
+    ) -> Result<[usize; 4], EnvironmentError> {
+        if let Some(indices) = cache.horizontal.get() {
+            let [west_lon_index, east_lon_index, south_lat_index, north_lat_index] = indices;
+
+            if within_bracket(
+                self.fields.lons[[west_lon_index, 0]],
+                self.fields.lons[[east_lon_index, 0]],
+                lon,
+            ) && within_bracket(
+                self.fields.lats[[west_lon_index, south_lat_index]],
+                self.fields.lats[[west_lon_index, north_lat_index]],
+                lat,
+            ) {
+                return Ok(indices);
+            }
+        }
+
+        let (west_lon_index, east_lon_index) =
+            find_node_bracket(self.fields.lons.slice(s![.., 0]), lon)?;
+
+        let (south_lat_index, north_lat_index) =
+            find_node_bracket(self.fields.lats.slice(s![west_lon_index, ..]), lat)?;
+
+        let indices = [
+            west_lon_index,
+            east_lon_index,
+            south_lat_index,
+            north_lat_index,
+        ];
+
+        // vertical indices are valid only in the columns they were found
+        cache.horizontal.set(Some(indices));
+        for column in &cache.vertical {
+            column.set(None);
+        }
+
+        Ok(indices)
+    }
+
+    /// Finds heights and values of the field in the given column
+    /// at the closest points below and above the searched height.
+    fn column_points(
+        &self,
+        field: EnvFields,
+        field_values: ArrayView3<FieldFloat>,
+        (x_index, y_index): (usize, usize),
+        z: Float,
+        cache: &Cell<Option<(usize, usize)>>,
+    ) -> Result<[(Float, Float); 2], EnvironmentError> {
+        // with terrain-following lower boundary levels below the surface
+        // are skipped and the surface is the bottom of the lowest layer
+        let lowest_level = match &self.terrain {
+            Some(terrain) => {
+                let lowest_level = terrain.lowest_levels[[x_index, y_index]];
+
+                if z < self.fields.height[[lowest_level, x_index, y_index]] as Float {
+                    let surface_point = (
+                        self.surfaces.height[[x_index, y_index]] as Float,
+                        self.surface_boundary_value(field, terrain, x_index, y_index),
+                    );
+
+                    let upper_point = (
+                        self.fields.height[[lowest_level, x_index, y_index]] as Float,
+                        field_values[[lowest_level, x_index, y_index]] as Float,
+                    );
+
+                    return Ok([surface_point, upper_point]);
+                }
+
+                lowest_level
+            }
+            None => 0,
+        };
+
+        // searched height is cast to precision of stored fields
+        let search_z = z as FieldFloat;
+
+        let cached_indices = cache.get().filter(|&(bottom_index, upper_index)| {
+            within_bracket(
+                self.fields.height[[bottom_index, x_index, y_index]],
+                self.fields.height[[upper_index, x_index, y_index]],
+                search_z,
+            )
+        });
+
+        let (bottom_index, upper_index) = match cached_indices {
+            Some(indices) => indices,
+            None => {
+                let indices = self.search_column(x_index, y_index, lowest_level, z)?;
+                cache.set(Some(indices));
+                indices
+            }
+        };
+
+        let bottom_point = (
+            self.fields.height[[bottom_index, x_index, y_index]] as Float,
+            field_values[[bottom_index, x_index, y_index]] as Float,
+        );
+
+        let upper_point = (
+            self.fields.height[[upper_index, x_index, y_index]] as Float,
+            field_values[[upper_index, x_index, y_index]] as Float,
+        );
+
+        Ok([bottom_point, upper_point])
+    }
+
+    /// Searches the column for indices of levels
+    /// below and above the given height.
+    fn search_column(
+        &self,
+        x_index: usize,
+        y_index: usize,
+        lowest_level: usize,
+        z: Float,
+    ) -> Result<(usize, usize), EnvironmentError> {
+        // on regular height levels indices can be computed directly
+        if let Some(height_grid) = &self.height_grid {
+            return Ok(height_grid.bracket(z, lowest_level)?);
+        }
+
+        // column is searched in place, as it is done for every access
+        let z_index_search_array = self
+            .fields
+            .height
+            .slice(s![lowest_level.., x_index, y_index]);
+
+        // searched height is cast to precision of stored fields
+        let search_z = z as FieldFloat;
+
+        let (bottom_index, upper_index) = bisection::find_bracket(z_index_search_array, &search_z)
+            .or_else(|err| {
+                // when searched height is below the lowest level
+                // we set lowest points to 0 and 1 levels for extrapolation
+                // in all other cases error is returned
+
+                match err {
+                    SearchError::OutOfBounds => {
+                        if z <= self.fields.height[[lowest_level, x_index, y_index]] as Float {
+                            Ok((0, 1))
+                        } else {
+                            Err(err)
+                        }
+                    }
+                    SearchError::EmptyArray => Err(err),
+                }
+            })?;
+
+        Ok((lowest_level + bottom_index, lowest_level + upper_index))
+    }
+
+    /// Value of the field at the surface,
+    /// used as the terrain-following lower boundary.
+    fn surface_boundary_value(
+        &self,
+        field: EnvFields,
+        terrain: &TerrainBoundary,
+        x_index: usize,
+        y_index: usize,
+    ) -> Float {
+        let value = match field {
+            EnvFields::Pressure => self.surfaces.pressure[[x_index, y_index]],
+            EnvFields::Temperature => self.surfaces.temperature[[x_index, y_index]],
+            EnvFields::VirtualTemperature => terrain.virtual_temp[[x_index, y_index]],
+            EnvFields::SpecHumidity => terrain.spec_humidity[[x_index, y_index]],
+            EnvFields::UWind => self.surfaces.u_wind[[x_index, y_index]],
+            EnvFields::VWind => self.surfaces.v_wind[[x_index, y_index]],
+            // air cannot flow through the surface, so vertical velocity
+            // vanishes there (terrain slope is neglected)
+            EnvFields::VerticalVel => 0.0,
+        };
+
+        value as Float
+    }
+}
+
+/// Grid indices found in the last search of environment fields.
+///
+/// It is kept by each parcel, so [`Environment::get_field_value_cached`]
+/// can skip the search when the parcel has not left the grid cell.
+#[derive(Clone, Debug, Default)]
+pub struct SearchCache {
+    horizontal: Cell<Option<[usize; 4]>>,
+    vertical: [Cell<Option<(usize, usize)>>; 4],
+}
+
+/// Finds indices of grid nodes bracketing the geographic coordinate,
+/// explicitly handling coordinates on a grid node.
+///
+/// Coordinates within [`NODE_TOLERANCE`] of the edge node are moved onto it.
+/// Coordinate on a node selects the cell starting at that node, except for
+/// the last node for which the left cell is selected (see [`bisection::find_bracket`]),
+/// so parcels released exactly on the grid edge are not out of bounds.
+fn find_node_bracket(axis: ArrayView1<Float>, coord: Float) -> Result<(usize, usize), SearchError> {
+    if axis.is_empty() {
+        return Err(SearchError::EmptyArray);
+    }
+
+    let edge_node = [axis[0], axis[axis.len() - 1]]
+        .into_iter()
+        .find(|node| (coord - node).abs() < NODE_TOLERANCE);
+
+    bisection::find_bracket(axis, &edge_node.unwrap_or(coord))
+}
+
+/// Checks if the value is between two consecutive array items,
+/// with the same bounds as in [`bisection::find_left_closest`].
+fn within_bracket<T: PartialOrd>(left: T, right: T, x: T) -> bool {
+    if left < right {
+        left <= x && x < right
+    } else {
+        left >= x && x > right
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::super::{analytic::AnalyticProfile, terrain::TerrainBoundary, FieldFloat};
+    use super::{find_node_bracket, EnvFields, Environment, SearchCache, SurfaceFields};
+    use crate::{
+        errors::{EnvironmentError, ErrorCode},
+        model::configuration::Domain,
+        Float,
+    };
+    use chrono::{Duration, NaiveDate};
+    use float_cmp::assert_approx_eq;
+    use ndarray::{aview1, Axis};
+
+    const MOUNTAIN_HEIGHT: Float = 1200.0;
+
+    fn pressure(z: Float) -> Float {
+        100_000.0 * (-z / 8000.0).exp()
+    }
+
+    pub(crate) fn temperature(z: Float) -> Float {
+        300.0 - 0.0065 * z
+    }
+
+    fn dewpoint(z: Float) -> Float {
+        285.0 - 0.002 * z
+    }
+
+    /// Environment with the analytic profile on levels every 500 m
+    /// up to 12 km, buffered over domain of given shape.
+    pub(crate) fn analytic_env(shape: (u16, u16)) -> (Domain, Environment) {
+        let profile = AnalyticProfile {
+            temperature,
+            dewpoint,
+            pressure,
+        };
+
+        let domain = Domain::new(20.0, 50.0, 1000.0, shape).unwrap();
+        let heights: Vec<Float> = (0..=24).map(|i| Float::from(i) * 500.0).collect();
+        let env = Environment::from_analytic(&domain, &heights, &profile).unwrap();
+
+        (domain, env)
+    }
+
+    #[test]
+    fn terrain_following_boundary() {
+        let (domain, mut env) = analytic_env((1, 1));
+
+        // elevate the whole surface, so the three lowest levels are below the ground
+        env.surfaces.height.fill(MOUNTAIN_HEIGHT as _);
+        env.surfaces.pressure.fill(pressure(MOUNTAIN_HEIGHT) as _);
+        env.surfaces
+            .temperature
+            .fill(temperature(MOUNTAIN_HEIGHT) as _);
+        env.surfaces.dewpoint.fill(dewpoint(MOUNTAIN_HEIGHT) as _);
+
+        let (x, y) = env.projection.project(domain.ref_lon, domain.ref_lat);
+        let pressure_between_levels = env
+            .get_field_value(x, y, MOUNTAIN_HEIGHT, EnvFields::Pressure)
+            .unwrap();
+
+        env.terrain = Some(TerrainBoundary::new(&env.fields, &env.surfaces).unwrap());
+        assert_eq!(env.terrain.as_ref().unwrap().lowest_levels[[0, 0]], 3);
+
+        // at the surface, surface values are used instead of levels below it
+        let surface_pressure = env
+            .get_field_value(x, y, MOUNTAIN_HEIGHT, EnvFields::Pressure)
+            .unwrap();
+        assert_approx_eq!(
+            Float,
+            surface_pressure,
+            pressure(MOUNTAIN_HEIGHT),
+            epsilon = 0.1
+        );
+        assert!((pressure_between_levels - surface_pressure).abs() > 1.0);
+
+        let surface_temperature = env
+            .get_field_value(x, y, MOUNTAIN_HEIGHT, EnvFields::Temperature)
+            .unwrap();
+        assert_approx_eq!(
+            Float,
+            surface_temperature,
+            temperature(MOUNTAIN_HEIGHT),
+            epsilon = 1e-3
+        );
+
+        let surface_vertical_vel = env
+            .get_field_value(x, y, MOUNTAIN_HEIGHT, EnvFields::VerticalVel)
+            .unwrap();
+        assert_approx_eq!(Float, surface_vertical_vel, 0.0);
+
+        // above the lowest level above the surface, fields are unchanged
+        let upper_pressure = env
+            .get_field_value(x, y, 2000.0, EnvFields::Pressure)
+            .unwrap();
+        assert_approx_eq!(Float, upper_pressure, pressure(2000.0), epsilon = 0.1);
+    }
+
+    #[test]
+    fn height_grid_consistency() {
+        let (domain, env) = analytic_env((1, 1));
+
+        // levels of the grid include input levels, and fields
+        // are linear between them, so results are the same
+        let (_, mut grid_env) = analytic_env((1, 1));
+        grid_env.resample_to_height_grid(100.0).unwrap();
+        assert_eq!(grid_env.fields.height.dim().0, 121);
+
+        let (x, y) = env.projection.project(domain.ref_lon, domain.ref_lat);
+
+        for i in 0..120 {
+            let z = Float::from(i) * 99.0;
+
+            let searched = env.get_field_value(x, y, z, EnvFields::Pressure).unwrap();
+            let gridded = grid_env
+                .get_field_value(x, y, z, EnvFields::Pressure)
+                .unwrap();
+
+            assert_approx_eq!(Float, searched, gridded, epsilon = 0.1);
+        }
+
+        assert!(grid_env
+            .get_field_value(x, y, 12_500.0, EnvFields::Pressure)
+            .is_err());
+    }
+
+    #[test]
+    fn cached_search_consistency() {
+        let (domain, env) = analytic_env((5, 5));
+
+        let (x_0, y_0) = env.projection.project(domain.ref_lon, domain.ref_lat);
+        let cache = SearchCache::default();
+
+        // slanted path crossing horizontal and vertical cell boundaries
+        for i in 0..1000 {
+            let step = Float::from(i);
+            let (x, y, z) = (x_0 + 4.0 * step, y_0 + 3.0 * step, 10.0 * step);
+
+            let searched = env.get_field_value(x, y, z, EnvFields::Pressure).unwrap();
+            let cached = env
+                .get_field_value_cached(x, y, z, EnvFields::Pressure, &cache)
+                .unwrap();
+
+            assert_eq!(searched.to_bits(), cached.to_bits());
+        }
+    }
+
+    #[test]
+    fn missing_data_flagging() {
+        let (domain, mut env) = analytic_env((5, 5));
+
+        let (x, y) = env.projection.project(domain.ref_lon, domain.ref_lat);
+        assert!(env
+            .get_field_value(x, y, 200.0, EnvFields::Temperature)
+            .is_ok());
+
+        // every gridpoint at the lowest level is masked as missing
+        env.fields
+            .temperature
+            .index_axis_mut(Axis(0), 0)
+            .fill(FieldFloat::NAN);
+
+        let error = env
+            .get_field_value(x, y, 200.0, EnvFields::Temperature)
+            .unwrap_err();
+        assert!(matches!(error, EnvironmentError::MissingData));
+        assert_eq!(error.code(), ErrorCode::MissingData);
+
+        assert!(env
+            .get_field_value(x, y, 2200.0, EnvFields::Temperature)
+            .is_ok());
+    }
+
+    #[test]
+    fn pressure_level_height() {
+        let (domain, env) = analytic_env((1, 1));
+
+        let (x, y) = env.projection.project(domain.ref_lon, domain.ref_lat);
+
+        let height = env.height_of_pressure(x, y, 85_000.0, 0.0).unwrap();
+        let found_pressure = env
+            .get_field_value(x, y, height, EnvFields::Pressure)
+            .unwrap();
+        assert_approx_eq!(Float, found_pressure, 85_000.0, epsilon = 0.1);
+
+        // below the bottom and above the top of the column
+        assert!(env.height_of_pressure(x, y, 99_000.0, 500.0).is_err());
+        assert!(env.height_of_pressure(x, y, 10_000.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn time_interpolation() {
+        let (domain, env) = analytic_env((1, 1));
+        let (_, mut later_env) = analytic_env((1, 1));
+        later_env.fields.temperature.mapv_inplace(|temp| temp + 2.0);
+
+        let start = NaiveDate::from_ymd_opt(2021, 7, 14)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .unwrap();
+        let end = start + Duration::hours(1);
+
+        let env = env.with_later(later_env, (start, end)).unwrap();
+
+        let (x, y) = env.projection.project(domain.ref_lon, domain.ref_lat);
+        let temp_at = |datetime| {
+            env.get_field_value_at(x, y, 1250.0, EnvFields::Temperature, datetime)
+                .unwrap()
+        };
+
+        let initial = env
+            .get_field_value(x, y, 1250.0, EnvFields::Temperature)
+            .unwrap();
+
+        assert_approx_eq!(Float, temp_at(start), initial);
+        assert_approx_eq!(Float, temp_at(start + Duration::minutes(15)), initial + 0.5);
+        assert_approx_eq!(Float, temp_at(end), initial + 2.0);
+
+        // outside of valid times the nearest environment is used
+        assert_approx_eq!(Float, temp_at(start - Duration::hours(1)), initial);
+        assert_approx_eq!(Float, temp_at(end + Duration::hours(1)), initial + 2.0);
+
+        let (_, later_env) = analytic_env((1, 1));
+        assert!(matches!(
+            analytic_env((1, 1)).1.with_later(later_env, (end, start)),
+            Err(EnvironmentError::IncorrectLaterEnvironment(_))
+        ));
+    }
+
+    #[test]
+    fn later_height_grid() {
+        let start = NaiveDate::from_ymd_opt(2021, 7, 14)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .unwrap();
+        let end = start + Duration::hours(1);
+
+        // later levels are lower, so separate grids would have different sizes
+        let with_later = || {
+            let (domain, env) = analytic_env((1, 1));
+            let (_, mut later_env) = analytic_env((1, 1));
+            later_env.fields.height.mapv_inplace(|z| z * 0.96);
+
+            (domain, env.with_later(later_env, (start, end)).unwrap())
+        };
+
+        let (domain, column_env) = with_later();
+        let (_, mut grid_env) = with_later();
+        grid_env.resample_to_height_grid(100.0).unwrap();
+
+        let later = &grid_env.later.as_ref().unwrap().environment;
+        assert_eq!(later.height_grid, grid_env.height_grid);
+        assert_eq!(later.fields.height, grid_env.fields.height);
+
+        let (x, y) = grid_env.projection.project(domain.ref_lon, domain.ref_lat);
+        let datetime = start + Duration::minutes(30);
+
+        for z in [250.0, 1250.0, 7000.0, 11_000.0] {
+            let column_temp = column_env
+                .get_field_value_at(x, y, z, EnvFields::Temperature, datetime)
+                .unwrap();
+            let grid_temp = grid_env
+                .get_field_value_at(x, y, z, EnvFields::Temperature, datetime)
+                .unwrap();
+
+            assert_approx_eq!(Float, grid_temp, column_temp, epsilon = 0.05);
+        }
+
+        // grid does not reach above the lowest top of both environments
+        assert!(grid_env
+            .get_field_value_at(x, y, 11_900.0, EnvFields::Temperature, datetime)
+            .is_err());
+    }
+
+    #[test]
+    fn grid_node_access() {
+        let axis = [19.0, 19.25, 19.5];
+        assert_eq!(find_node_bracket(aview1(&axis), 19.25).unwrap(), (1, 2));
+        assert_eq!(find_node_bracket(aview1(&axis), 19.5).unwrap(), (1, 2));
+        assert_eq!(
+            find_node_bracket(aview1(&axis), 19.5 + 1e-12).unwrap(),
+            (1, 2)
+        );
+        assert_eq!(
+            find_node_bracket(aview1(&axis), 19.0 - 1e-12).unwrap(),
+            (0, 1)
+        );
+        assert!(find_node_bracket(aview1(&axis), 19.6).is_err());
+
+        let (_, env) = analytic_env((5, 5));
+
+        // north-east corner of the grid, which coordinates
+        // are moved slightly by the projection
+        let lon = env.fields.lons[[env.fields.lons.dim().0 - 1, 0]];
+        let lat = env.fields.lats[[0, 0]];
+        let (x, y) = env.projection.project(lon, lat);
+
+        let surface_temp = env
+            .get_surface_value(x, y, SurfaceFields::Temperature)
+            .unwrap();
+        assert_approx_eq!(Float, surface_temp, temperature(0.0), epsilon = 1e-3);
+
+        let temp = env
+            .get_field_value(x, y, 1000.0, EnvFields::Temperature)
+            .unwrap();
+        assert_approx_eq!(Float, temp, temperature(1000.0), epsilon = 1e-3);
+    }
 }
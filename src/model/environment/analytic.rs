@@ -0,0 +1,62 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module providing a synthetic environment
+//! defined by closed-form vertical profiles.
+//!
+//! It allows to test parcel computations deterministically
+//! without reading any GRIB input.
+
+use super::{column::Column, Environment};
+use crate::{errors::EnvironmentError, model::configuration::Domain, Float};
+
+/// Vertical profile of a horizontally homogeneous environment.
+///
+/// Each function takes height above mean sea level (in meters)
+/// and returns the value in SI units. Surface values are taken
+/// from the profile at the lowest level.
+#[derive(Copy, Clone, Debug)]
+pub struct AnalyticProfile {
+    pub temperature: fn(Float) -> Float,
+    pub dewpoint: fn(Float) -> Float,
+    pub pressure: fn(Float) -> Float,
+}
+
+impl Environment {
+    /// Creates an environment with fields computed from given
+    /// profile at `heights` levels, in extent of domain with margins.
+    ///
+    /// Winds and vertical velocity are set to zero.
+    pub fn from_analytic(
+        domain: &Domain,
+        heights: &[Float],
+        profile: &AnalyticProfile,
+    ) -> Result<Self, EnvironmentError> {
+        let level_values = |f: fn(Float) -> Float| heights.iter().map(|&z| f(z)).collect();
+
+        let column = Column {
+            heights: heights.to_vec(),
+            temperature: level_values(profile.temperature),
+            dewpoint: level_values(profile.dewpoint),
+            pressure: level_values(profile.pressure),
+        };
+
+        Environment::from_column(domain, &column)
+    }
+}
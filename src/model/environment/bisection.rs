@@ -22,6 +22,7 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! to searched values in datasets.
 
 use crate::errors::SearchError;
+use ndarray::ArrayView1;
 
 /// Core bisection function, simply an implementation
 /// of binary search algorithm adapted to searching values
@@ -30,14 +31,20 @@ use crate::errors::SearchError;
 /// Alternatively, `binary_search()` function for slice type could be used,
 /// but this function is highly customised to the model needs and there are no
 /// apparent advantages of using built-in `binary_search()` over custom one.
-fn binary_search<T: PartialOrd>(array: &[T], x: &T) -> Result<usize, SearchError> {
+///
+/// Searched array is an `ArrayView1`, so strided views (eg. columns of 3D fields)
+/// can be searched in place, without copying them to a contiguous slice.
+fn binary_search<T: PartialOrd>(array: ArrayView1<T>, x: &T) -> Result<usize, SearchError> {
     if array.is_empty() {
         return Err(SearchError::EmptyArray);
     }
 
-    if x < array.first().unwrap() && x < array.last().unwrap()
-        || x > array.first().unwrap() && x > array.last().unwrap()
-    {
+    let first = &array[0];
+    let last = &array[array.len() - 1];
+
+    // written with negation so that values incomparable
+    // with the array items (eg. NaN) are also rejected
+    if !(x >= first && x <= last || x <= first && x >= last) {
         return Err(SearchError::OutOfBounds);
     }
 
@@ -45,7 +52,7 @@ fn binary_search<T: PartialOrd>(array: &[T], x: &T) -> Result<usize, SearchError
     let mut hi = array.len() - 1;
 
     // if the array is sorted descendingly we use a function with reversed signs
-    if array.first().unwrap() < array.last().unwrap() {
+    if first < last {
         while lo < hi {
             let mid = (lo + hi) / 2;
 
@@ -72,36 +79,122 @@ fn binary_search<T: PartialOrd>(array: &[T], x: &T) -> Result<usize, SearchError
 
 /// Convienience public method to find a closest value
 /// to requested to the left of the searched item.
-pub fn find_left_closest<T: PartialOrd>(array: &[T], x: &T) -> Result<usize, SearchError> {
+pub fn find_left_closest<T: PartialOrd>(array: ArrayView1<T>, x: &T) -> Result<usize, SearchError> {
     let found_index = binary_search(array, x)?;
 
-    if array.first().unwrap() < array.last().unwrap() {
+    if array[0] < array[array.len() - 1] {
         if array[found_index] <= *x {
             Ok(found_index)
         } else {
-            Ok(found_index - 1)
+            previous_index(found_index)
         }
     } else if array[found_index] >= *x {
         Ok(found_index)
     } else {
-        Ok(found_index - 1)
+        previous_index(found_index)
     }
 }
 
 /// Convienience public method to find a closest value
 /// to requested to the right of the searched item.
-pub fn find_right_closest<T: PartialOrd>(array: &[T], x: &T) -> Result<usize, SearchError> {
+pub fn find_right_closest<T: PartialOrd>(
+    array: ArrayView1<T>,
+    x: &T,
+) -> Result<usize, SearchError> {
     let found_index = binary_search(array, x)?;
 
-    if array.first().unwrap() < array.last().unwrap() {
+    if array[0] < array[array.len() - 1] {
         if array[found_index] >= *x {
             Ok(found_index)
         } else {
-            Ok(found_index - 1)
+            previous_index(found_index)
         }
     } else if array[found_index] <= *x {
         Ok(found_index)
     } else {
-        Ok(found_index - 1)
+        previous_index(found_index)
+    }
+}
+
+/// Index preceding the found one. Bounds are checked before the search,
+/// so it always exists for sorted arrays, but unsorted arrays
+/// should not cause a panic in parcel simulation.
+fn previous_index(found_index: usize) -> Result<usize, SearchError> {
+    found_index.checked_sub(1).ok_or(SearchError::OutOfBounds)
+}
+
+/// Convienience public method to find closest values
+/// to the left and to the right of the searched item
+/// with a single search.
+///
+/// Returned indices are always consecutive, so when searched item
+/// is equal to the last item of the array, the pair ends at that item.
+pub fn find_bracket<T: PartialOrd>(
+    array: ArrayView1<T>,
+    x: &T,
+) -> Result<(usize, usize), SearchError> {
+    let left_index = find_left_closest(array, x)?;
+
+    // single item cannot bracket any value
+    if array.len() < 2 {
+        return Err(SearchError::OutOfBounds);
+    }
+
+    let left_index = left_index.min(array.len() - 2);
+
+    Ok((left_index, left_index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_bracket, find_left_closest, find_right_closest};
+    use crate::{errors::SearchError, Float};
+    use ndarray::aview1;
+
+    #[test]
+    fn bracket_search() {
+        let ascending = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(find_bracket(aview1(&ascending), &2.5).unwrap(), (1, 2));
+        assert_eq!(find_bracket(aview1(&ascending), &1.0).unwrap(), (0, 1));
+        assert_eq!(find_bracket(aview1(&ascending), &4.0).unwrap(), (2, 3));
+
+        let descending = [4.0, 3.0, 2.0, 1.0];
+        assert_eq!(find_bracket(aview1(&descending), &2.5).unwrap(), (1, 2));
+        assert_eq!(find_bracket(aview1(&descending), &1.0).unwrap(), (2, 3));
+
+        assert!(matches!(
+            find_bracket(aview1(&ascending), &5.0),
+            Err(SearchError::OutOfBounds)
+        ));
+        assert!(matches!(
+            find_bracket(aview1(&[1.0]), &1.0),
+            Err(SearchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn closest_search_boundaries() {
+        let ascending = [1.0, 2.0, 3.0, 4.0];
+        let descending = [4.0, 3.0, 2.0, 1.0];
+
+        // (searched value, expected left and right index)
+        let ascending_cases = [(1.0, 0, 0), (4.0, 3, 3), (2.5, 1, 2)];
+        let descending_cases = [(4.0, 0, 0), (1.0, 3, 3), (2.5, 1, 2)];
+
+        for (array, cases) in [(ascending, ascending_cases), (descending, descending_cases)] {
+            for (x, left, right) in cases {
+                assert_eq!(find_left_closest(aview1(&array), &x).unwrap(), left);
+                assert_eq!(find_right_closest(aview1(&array), &x).unwrap(), right);
+            }
+
+            assert!(find_left_closest(aview1(&array), &Float::NAN).is_err());
+            assert!(find_right_closest(aview1(&array), &Float::NAN).is_err());
+        }
+
+        let single = [1.0];
+        assert_eq!(find_left_closest(aview1(&single), &1.0).unwrap(), 0);
+        assert_eq!(find_right_closest(aview1(&single), &1.0).unwrap(), 0);
+        assert!(find_left_closest(aview1(&single), &Float::NAN).is_err());
+        assert!(find_right_closest(aview1(&single), &2.0).is_err());
     }
 }
@@ -0,0 +1,147 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module providing a horizontally homogeneous
+//! environment built from a single vertical column.
+//!
+//! It allows to release parcels in conditions given by
+//! a sounding or a closed-form profile, without any GRIB input.
+
+use super::{
+    domain_lonlat_extent, fields::Fields, generate_domain_projection, surfaces::Surfaces,
+    Environment,
+};
+use crate::{errors::EnvironmentError, model::configuration::Domain, Float};
+use floccus::{mixing_ratio, virtual_temperature};
+use ndarray::{s, stack, Array, Array1, Array2, Array3, Axis};
+
+/// Spacing (in degrees) of the lat-lon grid
+/// of homogeneous environment.
+const GRID_STEP: Float = 0.25;
+
+/// Environmental variables (in SI units) at subsequent
+/// levels of a vertical column, from the lowest level.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct Column {
+    /// Height above mean sea level (in meters).
+    pub heights: Vec<Float>,
+    pub temperature: Vec<Float>,
+    pub dewpoint: Vec<Float>,
+    pub pressure: Vec<Float>,
+}
+
+impl Environment {
+    /// Creates an environment with the same `column` at each gridpoint,
+    /// in extent of domain with margins.
+    ///
+    /// The lowest level of the column is used as the surface.
+    /// Winds and vertical velocity are set to zero.
+    pub fn from_column(domain: &Domain, column: &Column) -> Result<Self, EnvironmentError> {
+        let projection = generate_domain_projection(domain)?;
+
+        let extent = domain_lonlat_extent(domain, &projection);
+
+        // like in GRIB files, longitudes are ascending and latitudes descending
+        let lons = grid_axis(
+            extent.west - domain.margins.0,
+            extent.east + domain.margins.0,
+        );
+        let lats = grid_axis(
+            extent.south - domain.margins.1,
+            extent.north + domain.margins.1,
+        )
+        .slice(s![..;-1])
+        .to_owned();
+
+        let lons_view = vec![lons.view(); lats.len()];
+        let lats_view = vec![lats.view(); lons.len()];
+
+        let lons = stack(Axis(1), lons_view.as_slice()).unwrap();
+        let lats = stack(Axis(0), lats_view.as_slice()).unwrap();
+
+        let mut mxng_rto = Vec::with_capacity(column.heights.len());
+        let mut virtual_temp = Vec::with_capacity(column.heights.len());
+
+        for z in 0..column.heights.len() {
+            let level_mxng_rto = mixing_ratio::accuracy1(column.dewpoint[z], column.pressure[z])?;
+
+            mxng_rto.push(level_mxng_rto);
+            virtual_temp.push(virtual_temperature::general1(
+                column.temperature[z],
+                level_mxng_rto,
+            )?);
+        }
+
+        let shape = (column.heights.len(), lons.shape()[0], lons.shape()[1]);
+        let level_field =
+            |values: &[Float]| Array3::from_shape_fn(shape, |(z, _, _)| values[z] as _);
+
+        let spec_humidity: Vec<Float> = mxng_rto.iter().map(|r| r / (1.0 + r)).collect();
+
+        let fields = Fields {
+            lons: lons.clone(),
+            lats: lats.clone(),
+            levels: column
+                .pressure
+                .iter()
+                .map(|p| (p / 100.0).round() as i64)
+                .collect(),
+            level_scale: 100.0,
+            height: level_field(&column.heights),
+            temperature: level_field(&column.temperature),
+            pressure: level_field(&column.pressure),
+            u_wind: Array3::zeros(shape),
+            v_wind: Array3::zeros(shape),
+            spec_humidity: level_field(&spec_humidity),
+            virtual_temp: level_field(&virtual_temp),
+            vertical_vel: Array3::zeros(shape),
+        };
+
+        let surface_field = |values: &[Float]| Array2::from_elem(lons.dim(), values[0] as _);
+
+        let surfaces = Surfaces {
+            temperature: surface_field(&column.temperature),
+            dewpoint: surface_field(&column.dewpoint),
+            pressure: surface_field(&column.pressure),
+            height: surface_field(&column.heights),
+            u_wind: Array2::zeros(lons.dim()),
+            v_wind: Array2::zeros(lons.dim()),
+            lons,
+            lats,
+        };
+
+        Ok(Environment {
+            fields,
+            surfaces,
+            terrain: None,
+            height_grid: None,
+            later: None,
+            projection,
+        })
+    }
+}
+
+/// Creates ascending grid coordinates
+/// covering the given range.
+fn grid_axis(start: Float, end: Float) -> Array1<Float> {
+    let start = (start / GRID_STEP).floor() * GRID_STEP;
+    let end = (end / GRID_STEP).ceil() * GRID_STEP;
+
+    Array::range(start, end + GRID_STEP / 2.0, GRID_STEP)
+}
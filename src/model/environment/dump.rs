@@ -0,0 +1,181 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module responsible for writing buffered
+//! environment data for debugging.
+//!
+//! Arrays are written in [`.npy` format](https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html)
+//! (version 1.0) so they can be easily loaded with `numpy.load()`
+//! and compared with the input data.
+
+use super::Environment;
+use log::debug;
+use ndarray::{ArrayBase, Data, Dimension};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Floating-point types that can be written to `.npy` file.
+trait NpyElement: Copy {
+    /// Numpy type description (little-endian)
+    const DESCR: &'static str;
+
+    fn write_le<W: Write>(self, writer: &mut W) -> io::Result<()>;
+}
+
+impl NpyElement for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn write_le<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+}
+
+impl NpyElement for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn write_le<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Environment {
+    /// Writes all buffered fields and surfaces into
+    /// separate `.npy` files in the provided directory.
+    ///
+    /// Pressure level fields have `(level, lon, lat)` shape,
+    /// and surfaces and coordinates have `(lon, lat)` shape.
+    pub fn dump(&self, out_dir: &Path) -> Result<(), io::Error> {
+        debug!("Writing buffered environment to {}", out_dir.display());
+
+        fs::create_dir_all(out_dir)?;
+
+        let fields_arrays = [
+            ("height", &self.fields.height),
+            ("temperature", &self.fields.temperature),
+            ("pressure", &self.fields.pressure),
+            ("u_wind", &self.fields.u_wind),
+            ("v_wind", &self.fields.v_wind),
+            ("spec_humidity", &self.fields.spec_humidity),
+            ("virtual_temp", &self.fields.virtual_temp),
+            ("vertical_vel", &self.fields.vertical_vel),
+        ];
+
+        for (name, array) in fields_arrays {
+            write_npy(&out_dir.join(format!("fields_{}.npy", name)), array)?;
+        }
+
+        write_npy(&out_dir.join("fields_lons.npy"), &self.fields.lons)?;
+        write_npy(&out_dir.join("fields_lats.npy"), &self.fields.lats)?;
+
+        let surfaces_arrays = [
+            ("temperature", &self.surfaces.temperature),
+            ("dewpoint", &self.surfaces.dewpoint),
+            ("pressure", &self.surfaces.pressure),
+            ("height", &self.surfaces.height),
+            ("u_wind", &self.surfaces.u_wind),
+            ("v_wind", &self.surfaces.v_wind),
+        ];
+
+        for (name, array) in surfaces_arrays {
+            write_npy(&out_dir.join(format!("surfaces_{}.npy", name)), array)?;
+        }
+
+        write_npy(&out_dir.join("surfaces_lons.npy"), &self.surfaces.lons)?;
+        write_npy(&out_dir.join("surfaces_lats.npy"), &self.surfaces.lats)?;
+
+        Ok(())
+    }
+}
+
+/// Writes an array to `.npy` file in C (row-major) order.
+fn write_npy<A, S, D>(path: &Path, array: &ArrayBase<S, D>) -> Result<(), io::Error>
+where
+    A: NpyElement,
+    S: Data<Elem = A>,
+    D: Dimension,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(&npy_header::<A>(array.shape()))?;
+
+    // iterator always goes in logical (row-major) order
+    for &value in array.iter() {
+        value.write_le(&mut writer)?;
+    }
+
+    writer.flush()
+}
+
+/// Creates `.npy` (version 1.0) header for array
+/// of given element type and shape.
+fn npy_header<A: NpyElement>(shape: &[usize]) -> Vec<u8> {
+    let shape = match shape {
+        [single] => format!("({},)", single),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        A::DESCR,
+        shape
+    );
+
+    // magic string, version and header length take 10 bytes
+    // and the whole header must be aligned to 64 bytes
+    // with the newline at the end
+    let unpadded_len = 10 + dict.len() + 1;
+    let padding = (64 - unpadded_len % 64) % 64;
+    dict.push_str(&" ".repeat(padding));
+    dict.push('\n');
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::npy_header;
+
+    #[test]
+    fn header_alignment() {
+        let header = npy_header::<f32>(&[37, 120, 85]);
+
+        assert_eq!(header.len() % 64, 0);
+        assert_eq!(&header[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!(header.last(), Some(&b'\n'));
+
+        let dict = String::from_utf8(header[10..].to_vec()).unwrap();
+        assert!(
+            dict.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (37, 120, 85), }")
+        );
+    }
+}
@@ -22,71 +22,155 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 use crate::model::{configuration, LonLat};
 use crate::{
     errors::{EnvironmentError, InputError},
-    model::{configuration::Input, environment::DomainExtent},
+    model::{
+        configuration::Input,
+        environment::{
+            cast_to_storage, check_valid_time, read_message_values, DomainExtent, FieldFloat,
+        },
+    },
     Float,
 };
 use eccodes::{CodesHandle, FallibleIterator, ProductKind::GRIB};
 use eccodes::{
-    KeyType::{self, FloatArray, Int, Str},
+    KeyType::{self, Int, Str},
     KeyedMessage,
 };
-use floccus::constants::G;
-use log::debug;
+use floccus::constants::{C_P, G};
+use log::{debug, warn};
 use ndarray::{concatenate, s, stack, Array, Array2, Array3, Axis, Zip};
 use rustc_hash::FxHashSet;
 
+/// Smallest value of specific humidity accepted in the input data.
+const MIN_SPEC_HUMIDITY: Float = 1.0e-8;
+
 /// Struct for storing environmental variables
 /// from levels above ground (currently pressure levels).
 ///
 /// To limit IO operations and reduce performance overhead
 /// of the model boundary conditions data is stored in the
-/// memory as 3D arrays.
+/// memory as 3D arrays. To reduce memory usage the arrays
+/// are stored with [`FieldFloat`] precision.
 #[derive(Debug)]
 pub struct Fields {
     pub lons: Array2<Float>,
     pub lats: Array2<Float>,
-    pub height: Array3<Float>,
-
-    pub temperature: Array3<Float>,
-    pub pressure: Array3<Float>,
-    pub u_wind: Array3<Float>,
-    pub v_wind: Array3<Float>,
-    pub spec_humidity: Array3<Float>,
-    pub virtual_temp: Array3<Float>,
-    pub vertical_vel: Array3<Float>,
+    pub levels: Vec<i64>,
+
+    /// Pressure (in Pa) of the unit of level value
+    pub level_scale: Float,
+
+    pub height: Array3<FieldFloat>,
+
+    pub temperature: Array3<FieldFloat>,
+    pub pressure: Array3<FieldFloat>,
+    pub u_wind: Array3<FieldFloat>,
+    pub v_wind: Array3<FieldFloat>,
+    pub spec_humidity: Array3<FieldFloat>,
+    pub virtual_temp: Array3<FieldFloat>,
+    pub vertical_vel: Array3<FieldFloat>,
 }
 
 impl Fields {
+    /// Buffers fields from GRIB messages read with [`collect`].
     pub(super) fn new(
         input: &Input,
+        data: &[KeyedMessage],
         domain_edges: DomainExtent<usize>,
     ) -> Result<Self, EnvironmentError> {
-        let data = collect(input)?;
-        let fields = construct_fields(input, &data, domain_edges)?;
+        construct_fields(input, data, domain_edges)
+    }
 
-        Ok(fields)
+    /// Logs a warning with the count of superadiabatic layers
+    /// in buffered fields and location of the steepest one.
+    pub(super) fn warn_superadiabatic_layers(&self) {
+        debug!("Checking fields for superadiabatic layers");
+
+        let (count, steepest) = find_superadiabatic_layers(&self.height, &self.virtual_temp);
+
+        if let Some(SuperadiabaticLayer {
+            index: (z, x, y),
+            lapse_rate,
+        }) = steepest
+        {
+            let (first, second) = (self.height[[z, x, y]], self.height[[z + 1, x, y]]);
+
+            warn!(
+                "Found {} superadiabatic layers in input data, the steepest with lapse rate {:.1} K/km between {:.0} m and {:.0} m at ({:.2}, {:.2}), check your input data",
+                count,
+                lapse_rate * 1000.0,
+                first.min(second),
+                first.max(second),
+                self.lons[[x, y]],
+                self.lats[[x, y]]
+            );
+        }
     }
 }
 
+/// Layer between consecutive levels of buffered fields
+/// with superadiabatic lapse rate (in K/m).
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct SuperadiabaticLayer {
+    index: (usize, usize, usize),
+    lapse_rate: Float,
+}
+
+/// Finds layers between consecutive levels in which virtual temperature
+/// decreases with height faster than the dry-adiabatic lapse rate.
+///
+/// Returns the number of such layers and the steepest one,
+/// indexed with its lower-indexed level.
+fn find_superadiabatic_layers(
+    height: &Array3<FieldFloat>,
+    virtual_temp: &Array3<FieldFloat>,
+) -> (usize, Option<SuperadiabaticLayer>) {
+    let dry_lapse_rate = G / C_P;
+
+    let mut count = 0;
+    let mut steepest: Option<SuperadiabaticLayer> = None;
+
+    for ((z, x, y), &level_height) in height.slice(s![..-1, .., ..]).indexed_iter() {
+        let thickness = (height[[z + 1, x, y]] - level_height) as Float;
+        let temp_drop = (virtual_temp[[z, x, y]] - virtual_temp[[z + 1, x, y]]) as Float;
+
+        // signed thickness makes the lapse rate
+        // independent of the order of levels
+        let lapse_rate = temp_drop / thickness;
+
+        if lapse_rate > dry_lapse_rate {
+            count += 1;
+
+            if !matches!(steepest, Some(layer) if layer.lapse_rate >= lapse_rate) {
+                steepest = Some(SuperadiabaticLayer {
+                    index: (z, x, y),
+                    lapse_rate,
+                });
+            }
+        }
+    }
+
+    (count, steepest)
+}
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
 pub(super) fn collect(input: &configuration::Input) -> Result<Vec<KeyedMessage>, InputError> {
     let mut data_levels: Vec<KeyedMessage> = vec![];
+    let short_names = input.variable_names.fields_names();
 
     for file in &input.data_files {
         let handle = CodesHandle::new_from_file(file, GRIB)?;
 
         let mut data: Vec<KeyedMessage> = handle
             .filter(|msg| {
+                let short_name = msg.read_key("shortName")?.value;
+
                 Ok(
                     msg.read_key("typeOfLevel")?.value == Str(input.level_type.clone())
-                        && (msg.read_key("shortName")?.value == Str("z".to_string())
-                            || msg.read_key("shortName")?.value == Str("q".to_string())
-                            || msg.read_key("shortName")?.value == Str("t".to_string())
-                            || msg.read_key("shortName")?.value == Str("u".to_string())
-                            || msg.read_key("shortName")?.value == Str("v".to_string())
-                            || msg.read_key("shortName")?.value == Str("w".to_string())),
+                        && short_names
+                            .iter()
+                            .any(|name| short_name == Str((*name).to_string())),
                 )
             })
             .collect()?;
@@ -100,7 +184,71 @@ pub(super) fn collect(input: &configuration::Input) -> Result<Vec<KeyedMessage>,
         ));
     }
 
-    Ok(data_levels)
+    check_valid_time(&data_levels, input.valid_time)?;
+
+    deduplicate_levels(data_levels)
+}
+
+/// Finds the strongest horizontal wind in given extent,
+/// so that it is known before fields are buffered.
+#[cfg(feature = "3d")]
+pub(super) fn max_wind_speed(
+    input: &Input,
+    data: &[KeyedMessage],
+    domain_edges: DomainExtent<usize>,
+) -> Result<Float, InputError> {
+    let names = &input.variable_names;
+    let u_wind = read_truncated_field(&names.u_wind, input, data, domain_edges)?;
+    let v_wind = read_truncated_field(&names.v_wind, input, data, domain_edges)?;
+
+    let max_wind = u_wind
+        .iter()
+        .zip(v_wind.iter())
+        .map(|(u, v)| u.hypot(*v))
+        .fold(0.0, Float::max);
+
+    Ok(max_wind)
+}
+
+/// Removes messages with the same variable and level
+/// as one of previous messages, keeping the first one.
+///
+/// Levels of a variable can be provided in many files, and when
+/// the files overlap duplicated levels would be stacked as separate
+/// layers of the field, so they are removed with a warning.
+fn deduplicate_levels(data: Vec<KeyedMessage>) -> Result<Vec<KeyedMessage>, InputError> {
+    let mut unique_levels: FxHashSet<(String, i64)> = FxHashSet::default();
+    let mut unique_data = Vec::with_capacity(data.len());
+    let mut duplicates_count = 0;
+
+    for msg in data {
+        let short_name = if let Str(name) = msg.read_key("shortName")?.value {
+            name
+        } else {
+            return Err(InputError::IncorrectKeyType("shortName"));
+        };
+
+        let level = if let Int(id) = msg.read_key("level")?.value {
+            id
+        } else {
+            return Err(InputError::IncorrectKeyType("level"));
+        };
+
+        if unique_levels.insert((short_name, level)) {
+            unique_data.push(msg);
+        } else {
+            duplicates_count += 1;
+        }
+    }
+
+    if duplicates_count > 0 {
+        warn!(
+            "Input files contain {} duplicated levels of variables, only first occurrences are used, check your input data",
+            duplicates_count
+        );
+    }
+
+    Ok(unique_data)
 }
 
 /// Function to read pressure level data from GRIB input
@@ -160,76 +308,146 @@ fn assign_fields(
     data: &[KeyedMessage],
     coords: LonLat<Array2<Float>>,
 ) -> Result<Fields, InputError> {
-    let input_shape = input.shape;
-
-    let pressure = read_truncated_pressure(data, domain_edges)?;
-
-    let geopotential = read_raw_field("z", input_shape, data)?;
-    let height = truncate_field_to_extent(&geopotential, domain_edges).mapv(|v| v / G);
-
-    let temperature = read_raw_field("t", input_shape, data)?;
-    let temperature = truncate_field_to_extent(&temperature, domain_edges);
-
-    let u_wind = read_raw_field("u", input_shape, data)?;
-    let u_wind = truncate_field_to_extent(&u_wind, domain_edges);
+    let names = &input.variable_names;
+
+    let levels = list_levels(data)?;
+    let level_scale = input
+        .level_pressure_scale()
+        .ok_or(InputError::UnsupportedLevelType(
+            "level type must be isobaricInhPa or isobaricInPa",
+        ))?;
+    let pressure = read_truncated_pressure(&levels, level_scale, domain_edges);
+
+    // fields are cast to the storage precision right after they
+    // are computed, so that at most a few of them are buffered
+    // in full precision (together with raw fields) at once
+    let u_wind = cast_to_storage(read_truncated_field(
+        &names.u_wind,
+        input,
+        data,
+        domain_edges,
+    )?);
+    let v_wind = cast_to_storage(read_truncated_field(
+        &names.v_wind,
+        input,
+        data,
+        domain_edges,
+    )?);
+
+    let temperature = read_truncated_field(&names.temperature, input, data, domain_edges)?;
+
+    let mut spec_humidity = read_truncated_field(&names.spec_humidity, input, data, domain_edges)?;
+
+    if input.humidity_is_mixing_ratio {
+        spec_humidity.mapv_inplace(|r| r / (1.0 + r));
+    }
 
-    let v_wind = read_raw_field("v", input_shape, data)?;
-    let v_wind = truncate_field_to_extent(&v_wind, domain_edges);
+    let clamped_count = clamp_spec_humidity(&mut spec_humidity);
+    if clamped_count > 0 {
+        warn!(
+            "Specific humidity was below {:e} in {} of {} gridpoints and has been clamped, check your input data",
+            MIN_SPEC_HUMIDITY,
+            clamped_count,
+            spec_humidity.len()
+        );
+    }
 
-    let spec_humidity = read_raw_field("q", input_shape, data)?;
-    // check for negative values of specific humidity and replace them with the smallest positive value
-    let spec_humidity = truncate_field_to_extent(&spec_humidity, domain_edges).mapv(|v| {
-        if v < 1.0e-8 {
-            1.0e-8
-        } else {
-            v
+    let virtual_temp = cast_to_storage(compute_virtual_temperature(
+        &temperature,
+        &spec_humidity,
+        &pressure,
+        &coords,
+    )?);
+    let temperature = cast_to_storage(temperature);
+    let spec_humidity = cast_to_storage(spec_humidity);
+
+    let height =
+        read_truncated_field(&names.geopotential, input, data, domain_edges)?.mapv_into(|v| v / G);
+
+    // vertical velocity is only used for optional forcing
+    // of parcels, so the model can run without it
+    let vertical_vel = match read_truncated_field(&names.vertical_motion, input, data, domain_edges)
+    {
+        Ok(vertical_motion) => {
+            let vertical_vel = compute_vertical_velocity(&pressure, &height, &vertical_motion);
+
+            if input.smooth_vertical_velocity {
+                smooth_over_levels(&vertical_vel)
+            } else {
+                vertical_vel
+            }
         }
-    });
-
-    let virtual_temp = compute_virtual_temperature(&temperature, &spec_humidity);
-
-    let vertical_motion = read_raw_field("w", input_shape, data)?;
-    let vertical_motion = truncate_field_to_extent(&vertical_motion, domain_edges);
-    let vertical_vel = compute_vertical_velocity(&pressure, &height, &vertical_motion);
+        Err(InputError::DataNotSufficient(_)) => {
+            warn!(
+                "Vertical velocity ({}) not found in input data, it is assumed to be zero",
+                names.vertical_motion
+            );
+            Array3::zeros(height.dim())
+        }
+        Err(err) => return Err(err),
+    };
 
     Ok(Fields {
         lons: coords.0,
         lats: coords.1,
-        height,
+        levels,
+        level_scale,
+        height: cast_to_storage(height),
         temperature,
-        pressure,
+        pressure: cast_to_storage(pressure),
         u_wind,
         v_wind,
         spec_humidity,
         virtual_temp,
-        vertical_vel,
+        vertical_vel: cast_to_storage(vertical_vel),
     })
 }
 
+/// Replaces non-positive (and very small) values of specific humidity
+/// with the smallest positive value, as thermodynamic functions
+/// return an error for such input.
+///
+/// Returns the number of clamped gridpoints.
+fn clamp_spec_humidity(spec_humidity: &mut Array3<Float>) -> usize {
+    let mut clamped_count = 0;
+
+    spec_humidity.map_inplace(|v| {
+        if *v < MIN_SPEC_HUMIDITY {
+            *v = MIN_SPEC_HUMIDITY;
+            clamped_count += 1;
+        }
+    });
+
+    clamped_count
+}
+
 /// Creates a 3d array of pressure data of shape
 /// identical to other pressure level fields.
 ///
 /// When data in GRIB file is provided on pressure levels
 /// the information about pressure at each level is only
-/// stored in message metadata. Thus, information what
-/// pressure levels are available has to be extracted
-/// and then casted to the 3d array expected by the
+/// stored in message metadata. Thus, the list of available
+/// pressure levels has to be extracted and then
+/// casted to the 3d array expected by the
 /// [`accesser`](super::super::accesser).
+///
+/// Level values are multiplied by `level_scale` (pressure
+/// in Pa of the level unit), which depends on the level type.
 fn read_truncated_pressure(
-    levels_data: &[KeyedMessage],
+    levels_list: &[i64],
+    level_scale: Float,
     domain_edges: DomainExtent<usize>,
-) -> Result<Array3<Float>, InputError> {
+) -> Array3<Float> {
     let xy_shape = (
         (domain_edges.east as isize - domain_edges.west as isize).abs() as usize + 1,
         (domain_edges.south as isize - domain_edges.north as isize).abs() as usize + 1,
     );
 
-    let levels_list = list_levels(levels_data)?;
     let mut pressure_levels = vec![];
 
-    for level in levels_list {
+    for &level in levels_list {
         let pressure_level = Array2::from_elem(xy_shape, level);
-        let pressure_level = pressure_level.mapv(|v| (v as Float) * 100.0);
+        let pressure_level = pressure_level.mapv(|v| (v as Float) * level_scale);
         pressure_levels.push(pressure_level);
     }
 
@@ -239,9 +457,7 @@ fn read_truncated_pressure(
         pressure_views.push(level.view());
     }
 
-    let pressure_levels = ndarray::stack(Axis(0), pressure_views.as_slice()).unwrap();
-
-    Ok(pressure_levels)
+    ndarray::stack(Axis(0), pressure_views.as_slice()).unwrap()
 }
 
 /// Function to get the list of unique levels
@@ -280,15 +496,29 @@ fn list_levels(data: &[KeyedMessage]) -> Result<Vec<i64>, InputError> {
 /// converted into a 3d array.
 fn read_raw_field(
     short_name: &str,
-    shape: (usize, usize),
+    input: &Input,
     data: &[KeyedMessage],
 ) -> Result<Array3<Float>, InputError> {
     let data_levels = read_raw_messages(short_name, data)?;
-    let result_data = messages_to_array(data_levels, shape)?;
+    let result_data = messages_to_array(data_levels, input)?;
 
     Ok(result_data)
 }
 
+/// Reads variable with given `short_name` and truncates it
+/// to the domain + margins extent, so that the raw field
+/// covering the whole GRIB grid is dropped right away.
+fn read_truncated_field(
+    short_name: &str,
+    input: &Input,
+    data: &[KeyedMessage],
+    domain_edges: DomainExtent<usize>,
+) -> Result<Array3<Float>, InputError> {
+    let raw_field = read_raw_field(short_name, input, data)?;
+
+    Ok(truncate_field_to_extent(&raw_field, domain_edges))
+}
+
 /// Filters and read all GRIB messages that contain
 /// variable with given `short_name` on specified level type.
 fn read_raw_messages<'a>(
@@ -316,7 +546,7 @@ fn read_raw_messages<'a>(
 /// into a 3d array,
 fn messages_to_array(
     data_levels: Vec<&KeyedMessage>,
-    shape: (usize, usize),
+    input: &Input,
 ) -> Result<Array3<Float>, InputError> {
     let mut sorted_data_levels = vec![];
 
@@ -327,21 +557,7 @@ fn messages_to_array(
             return Err(InputError::IncorrectKeyType("level"));
         };
 
-        let lvl_vals = if let FloatArray(vals) = msg.read_key("values")?.value {
-            vals
-        } else {
-            return Err(InputError::IncorrectKeyType("values"));
-        };
-
-        // a bit of magic
-        // data values in GRIB are a vec of values row-by-row (x-axis is in WE direction)
-        // we want a Array2 of provided `shape` with x-axis in WE direction
-        // but from_shape_vec(final_shape, data) splits the data into final_shape.1 long chunks
-        // and puts them in columns
-        // so we need to correctly split the data in GRIB vector into Array2 and then transpose
-        // that array to get axes along expected geographical directions
-        let lvl_vals = Array2::from_shape_vec((shape.1, shape.0), lvl_vals)?;
-        let lvl_vals = lvl_vals.reversed_axes();
+        let lvl_vals = read_message_values(msg, input.shape, input.missing_value_fill)?;
 
         sorted_data_levels.push((lvl_id, lvl_vals));
     }
@@ -349,7 +565,7 @@ fn messages_to_array(
     sorted_data_levels.sort_unstable_by_key(|k| k.0);
     sorted_data_levels.reverse();
 
-    let sorted_data_levels: Vec<Array2<f64>> =
+    let sorted_data_levels: Vec<Array2<Float>> =
         sorted_data_levels.into_iter().map(|t| t.1).collect();
 
     let mut result_data = vec![];
@@ -358,7 +574,6 @@ fn messages_to_array(
     }
 
     let result_data = ndarray::stack(Axis(0), result_data.as_slice())?;
-    let result_data = result_data.mapv(|v| v as Float);
 
     Ok(result_data)
 }
@@ -386,22 +601,48 @@ fn truncate_field_to_extent(
 
 /// Computes and buffers additional pressure level data from
 /// values previously read from the GRIB file.
+///
+/// The computation is done independently for each gridpoint
+/// so it is run in parallel on the model threadpool.
+///
+/// When computation fails at any gridpoint, the error with
+/// location and values at the first such gridpoint is returned.
 fn compute_virtual_temperature(
     temperature: &Array3<Float>,
     spec_humidity: &Array3<Float>,
-) -> Array3<Float> {
+    pressure: &Array3<Float>,
+    coords: &LonLat<Array2<Float>>,
+) -> Result<Array3<Float>, InputError> {
     let mut virtual_temperature: Array3<Float> = Array3::zeros(temperature.raw_dim());
 
     Zip::from(&mut virtual_temperature)
         .and(temperature)
         .and(spec_humidity)
-        .for_each(|tv, &t, &q| {
-            *tv = floccus::virtual_temperature::general3(t, q).expect(
-                "Error while computing virtual temperature: variable out of reasonable bounds",
-            );
+        .par_for_each(|tv, &t, &q| {
+            // failed gridpoints are marked with NaN and reported below
+            *tv = floccus::virtual_temperature::general3(t, q).unwrap_or(Float::NAN);
         });
 
-    virtual_temperature
+    // gridpoints with missing input data are left as NaN
+    if let Some(((z, x, y), _)) = virtual_temperature.indexed_iter().find(|&((z, x, y), tv)| {
+        tv.is_nan() && !temperature[[z, x, y]].is_nan() && !spec_humidity[[z, x, y]].is_nan()
+    }) {
+        let t = temperature[[z, x, y]];
+        let q = spec_humidity[[z, x, y]];
+
+        if let Err(source) = floccus::virtual_temperature::general3(t, q) {
+            return Err(InputError::UnreasonableGridpoint {
+                lon: coords.0[[x, y]],
+                lat: coords.1[[x, y]],
+                pressure: pressure[[z, x, y]],
+                temperature: t,
+                spec_humidity: q,
+                source,
+            });
+        }
+    }
+
+    Ok(virtual_temperature)
 }
 
 /// What it is?
@@ -421,3 +662,338 @@ fn compute_vertical_velocity(
     // multiply vertical motion and thickness to get velocity
     vertical_motion * thickness
 }
+
+/// Smooths the field over levels with 1-2-1 filter.
+///
+/// Bottom and top levels have only one neighbour,
+/// so they are left intact and the shape is not changed.
+fn smooth_over_levels(field: &Array3<Float>) -> Array3<Float> {
+    let levels = field.shape()[0];
+    let mut smoothed = field.clone();
+
+    if levels > 2 {
+        let inner = s![1..levels - 1, .., ..];
+        let below = field.slice(s![..levels - 2, .., ..]);
+        let above = field.slice(s![2.., .., ..]);
+
+        let filtered = (&below + &field.slice(inner) * 2.0 + above) / 4.0;
+        smoothed.slice_mut(inner).assign(&filtered);
+    }
+
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clamp_spec_humidity, collect, compute_virtual_temperature, construct_fields,
+        find_superadiabatic_layers, messages_to_array, read_truncated_pressure, smooth_over_levels,
+        MIN_SPEC_HUMIDITY,
+    };
+    use crate::{
+        errors::InputError,
+        model::{
+            configuration::Input,
+            environment::{
+                grib_fixture::{ConstantField, GribFixture},
+                DomainExtent, FieldFloat,
+            },
+        },
+        Float,
+    };
+    use chrono::NaiveDate;
+    use eccodes::KeyType::Str;
+    use ndarray::{s, Array2, Array3, Zip};
+    use std::{env, fs, process};
+
+    /// Simple hash giving reproducible pseudo-random values in `[0, 1)`
+    fn pseudo_random(seed: usize) -> Float {
+        let x = ((seed as Float) * 12.9898).sin() * 43_758.545_3;
+        x - x.floor()
+    }
+
+    #[test]
+    fn pressure_level_units() {
+        let domain_edges = DomainExtent {
+            north: 2,
+            south: 4,
+            west: 5,
+            east: 7,
+        };
+
+        // the same levels in hPa and in Pa
+        let hpa_pressure = read_truncated_pressure(&[1000, 850, 500], 100.0, domain_edges);
+        let pa_pressure = read_truncated_pressure(&[100_000, 85_000, 50_000], 1.0, domain_edges);
+
+        assert_eq!(hpa_pressure.dim(), (3, 3, 3));
+        assert_eq!(hpa_pressure, pa_pressure);
+        assert_eq!(pa_pressure[[2, 1, 1]], 50_000.0);
+    }
+
+    #[test]
+    fn spec_humidity_clamping() {
+        let mut spec_humidity =
+            Array3::from_shape_vec((1, 2, 3), vec![0.01, -0.002, 0.0, 1.0e-9, 0.005, 1.0e-8])
+                .unwrap();
+
+        let clamped_count = clamp_spec_humidity(&mut spec_humidity);
+
+        assert_eq!(clamped_count, 3);
+        assert!(spec_humidity.iter().all(|&q| q >= MIN_SPEC_HUMIDITY));
+        assert_eq!(spec_humidity[[0, 0, 0]], 0.01);
+        assert_eq!(spec_humidity[[0, 1, 1]], 0.005);
+    }
+
+    #[test]
+    fn vertical_smoothing() {
+        // noise alternating between levels on top of linear profile
+        let field = Array3::from_shape_fn((6, 2, 3), |(z, x, y)| {
+            (z + x + y) as Float + if z % 2 == 0 { 1.0 } else { -1.0 }
+        });
+
+        let smoothed = smooth_over_levels(&field);
+
+        assert_eq!(smoothed.dim(), field.dim());
+        assert_eq!(smoothed.slice(s![0, .., ..]), field.slice(s![0, .., ..]));
+        assert_eq!(smoothed.slice(s![-1, .., ..]), field.slice(s![-1, .., ..]));
+
+        // noise is removed from inner levels
+        for ((z, x, y), &v) in smoothed.indexed_iter() {
+            if z > 0 && z < 5 {
+                assert!((v - (z + x + y) as Float).abs() < 1e-12);
+            }
+        }
+
+        let two_levels = Array3::from_elem((2, 2, 2), 1.0);
+        assert_eq!(smooth_over_levels(&two_levels), two_levels);
+    }
+
+    #[test]
+    fn parallel_virtual_temperature() {
+        let shape = (6, 11, 7);
+
+        let temperature = Array3::from_shape_fn(shape, |(z, x, y)| {
+            200.0 + 110.0 * pseudo_random(z * 97 + x * 13 + y)
+        });
+        let spec_humidity = Array3::from_shape_fn(shape, |(z, x, y)| {
+            1.0e-8 + 0.02 * pseudo_random(z * 89 + x * 11 + y + 7)
+        });
+
+        let pressure = Array3::from_elem(shape, 50_000.0);
+        let coords = (Array2::zeros((11, 7)), Array2::zeros((11, 7)));
+
+        let parallel =
+            compute_virtual_temperature(&temperature, &spec_humidity, &pressure, &coords).unwrap();
+
+        let mut serial: Array3<Float> = Array3::zeros(temperature.raw_dim());
+        Zip::from(&mut serial)
+            .and(&temperature)
+            .and(&spec_humidity)
+            .for_each(|tv, &t, &q| {
+                *tv = floccus::virtual_temperature::general3(t, q).unwrap();
+            });
+
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn virtual_temperature_error_location() {
+        let shape = (2, 3, 4);
+
+        let mut temperature = Array3::from_elem(shape, 280.0);
+        temperature[[1, 2, 1]] = 500.0;
+        temperature[[1, 2, 3]] = 600.0;
+
+        let spec_humidity = Array3::from_elem(shape, 0.005);
+        let pressure = Array3::from_shape_fn(shape, |(z, _, _)| 85_000.0 - 10_000.0 * z as Float);
+        let coords = (
+            Array2::from_shape_fn((3, 4), |(x, _)| 14.0 + x as Float),
+            Array2::from_shape_fn((3, 4), |(_, y)| 52.0 - y as Float),
+        );
+
+        let error = compute_virtual_temperature(&temperature, &spec_humidity, &pressure, &coords)
+            .unwrap_err();
+
+        if let InputError::UnreasonableGridpoint {
+            lon,
+            lat,
+            pressure,
+            temperature,
+            ..
+        } = error
+        {
+            assert_eq!(
+                (lon, lat, pressure, temperature),
+                (16.0, 51.0, 75_000.0, 500.0)
+            );
+        } else {
+            panic!("Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn superadiabatic_layers_detection() {
+        let shape = (4, 2, 3);
+
+        // levels ordered from the top, to check that the order does not matter
+        let height = Array3::from_shape_fn(shape, |(z, _, _)| 3000.0 - 1000.0 * z as FieldFloat);
+        let mut virtual_temp =
+            Array3::from_shape_fn(shape, |(z, _, _)| 270.0 + 6.5 * z as FieldFloat);
+
+        assert_eq!(
+            find_superadiabatic_layers(&height, &virtual_temp),
+            (0, None)
+        );
+
+        virtual_temp[[3, 0, 1]] = 295.0;
+        virtual_temp[[2, 1, 2]] = 290.0;
+
+        let (count, steepest) = find_superadiabatic_layers(&height, &virtual_temp);
+        let steepest = steepest.unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(steepest.index, (1, 1, 2));
+        assert!((steepest.lapse_rate - 0.0135).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn grib_fixture_reading() {
+        let out_dir = env::temp_dir().join(format!("pats_grib_fixture_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let fixture = GribFixture {
+            lon_west: 19.0,
+            lat_north: 51.0,
+            step: 0.25,
+            shape: (5, 4),
+            datetime: NaiveDate::from_ymd_opt(2021, 7, 14)
+                .and_then(|date| date.and_hms_opt(12, 0, 0))
+                .unwrap(),
+        };
+
+        let field = |param_id, level, value| ConstantField {
+            param_id,
+            type_of_level: "isobaricInhPa",
+            level,
+            value,
+        };
+
+        let path = out_dir.join("levels.grib");
+        fixture
+            .write(
+                &path,
+                &[
+                    field(130, 500, 250.0),
+                    field(130, 1000, 290.0),
+                    field(129, 1000, 980.0),
+                    // not an input variable
+                    field(157, 1000, 50.0),
+                ],
+            )
+            .unwrap();
+
+        let mut input = Input::new("isobaricInhPa", vec![path]);
+        input.init_shape_and_distinct_lonlats().unwrap();
+        input.init_valid_time().unwrap();
+
+        assert_eq!(input.shape, fixture.shape);
+        assert_eq!(input.distinct_lonlats.0, fixture.distinct_longitudes());
+        assert_eq!(input.distinct_lonlats.1, fixture.distinct_latitudes());
+        assert_eq!(input.valid_time, Some(fixture.datetime));
+
+        let messages = collect(&input).unwrap();
+        assert_eq!(messages.len(), 3);
+
+        let temperature = messages
+            .iter()
+            .filter(|msg| msg.read_key("shortName").unwrap().value == Str("t".to_string()))
+            .collect();
+        let temperature = messages_to_array(temperature, &input).unwrap();
+
+        // levels are ordered from the lowest one
+        assert_eq!(temperature.dim(), (2, 5, 4));
+        assert!(temperature
+            .slice(s![0, .., ..])
+            .iter()
+            .all(|&t| (t - 290.0).abs() < 1.0e-3));
+        assert!(temperature
+            .slice(s![1, .., ..])
+            .iter()
+            .all(|&t| (t - 250.0).abs() < 1.0e-3));
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn grib_fixture_pascal_levels() {
+        let out_dir = env::temp_dir().join(format!("pats_grib_pascal_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let fixture = GribFixture {
+            lon_west: 19.0,
+            lat_north: 51.0,
+            step: 0.25,
+            shape: (3, 2),
+            datetime: NaiveDate::from_ymd_opt(2021, 7, 14)
+                .and_then(|date| date.and_hms_opt(12, 0, 0))
+                .unwrap(),
+        };
+
+        let field = |param_id, level, value| ConstantField {
+            param_id,
+            type_of_level: "isobaricInPa",
+            level,
+            value,
+        };
+
+        // GRIB1 levels in Pa are encoded in two octets, so they are below 65535
+        let path = out_dir.join("pascal_levels.grib");
+        fixture
+            .write(
+                &path,
+                &[
+                    field(129, 50000, 55_000.0),
+                    field(129, 30000, 90_000.0),
+                    field(130, 50000, 255.0),
+                    field(130, 30000, 230.0),
+                    field(131, 50000, 10.0),
+                    field(131, 30000, 20.0),
+                    field(132, 50000, 0.0),
+                    field(132, 30000, 0.0),
+                    field(133, 50000, 0.001),
+                    field(133, 30000, 0.0001),
+                ],
+            )
+            .unwrap();
+
+        let mut input = Input::new("isobaricInPa", vec![path]);
+        input.init_shape_and_distinct_lonlats().unwrap();
+
+        let messages = collect(&input).unwrap();
+        assert_eq!(messages.len(), 10);
+
+        let domain_edges = DomainExtent {
+            north: 0,
+            south: 1,
+            west: 0,
+            east: 2,
+        };
+        let fields = construct_fields(&input, &messages, domain_edges).unwrap();
+
+        // levels in Pa are not scaled as levels in hPa
+        assert_eq!(fields.level_scale, 1.0);
+        assert_eq!(fields.levels, [50000, 30000]);
+        assert!(fields
+            .pressure
+            .slice(s![0, .., ..])
+            .iter()
+            .all(|&p| p == 50_000.0));
+        assert!(fields
+            .pressure
+            .slice(s![1, .., ..])
+            .iter()
+            .all(|&p| p == 30_000.0));
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Test-only sub-module writing small, deterministic GRIB files,
+//! so that reading of input data can be tested without external data.
+//!
+//! The eccodes crate does not expose GRIB sample templates, so a single
+//! GRIB1 message with the fixture grid and a constant field is encoded
+//! by hand. Each field is then written by setting keys of a copy of that
+//! template message with the ecCodes writing API.
+
+use crate::Float;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use eccodes::{
+    CodesHandle, FallibleIterator, Key,
+    KeyType::{self, FloatArray, Int, Str},
+    ProductKind::GRIB,
+};
+use std::{fs, path::Path};
+
+/// Value marking gridpoints masked by the bitmap.
+const MISSING_VALUE: Float = 9999.0;
+
+/// Regular lat-lon grid and valid time of the fixture,
+/// scanned from the north-west corner.
+#[derive(Copy, Clone, Debug)]
+pub struct GribFixture {
+    pub lon_west: Float,
+    pub lat_north: Float,
+    pub step: Float,
+    pub shape: (usize, usize),
+    pub datetime: NaiveDateTime,
+}
+
+/// Field on a single level with the same value at each gridpoint.
+#[derive(Copy, Clone, Debug)]
+pub struct ConstantField {
+    /// ECMWF parameter ID, eg. `130` for temperature.
+    pub param_id: i64,
+    pub type_of_level: &'static str,
+    pub level: i64,
+    pub value: Float,
+}
+
+impl GribFixture {
+    /// Longitudes of the fixture grid columns, from west to east.
+    pub fn distinct_longitudes(&self) -> Vec<Float> {
+        (0..self.shape.0)
+            .map(|i| self.lon_west + i as Float * self.step)
+            .collect()
+    }
+
+    /// Latitudes of the fixture grid rows, from north to south.
+    pub fn distinct_latitudes(&self) -> Vec<Float> {
+        (0..self.shape.1)
+            .map(|j| self.lat_north - j as Float * self.step)
+            .collect()
+    }
+
+    /// Writes messages with given fields into a new GRIB file at `path`.
+    ///
+    /// Grid coordinates are encoded in millidegrees,
+    /// so they should be multiples of `0.001`.
+    pub fn write(&self, path: &Path, fields: &[ConstantField]) -> Result<(), eccodes::CodesError> {
+        self.write_messages(path, fields, &[])
+    }
+
+    /// Writes a message with given field into a new GRIB file at `path`,
+    /// with gridpoints at `masked` indices (in scanning order) marked
+    /// as missing in the bitmap.
+    pub fn write_with_bitmap(
+        &self,
+        path: &Path,
+        field: ConstantField,
+        masked: &[usize],
+    ) -> Result<(), eccodes::CodesError> {
+        self.write_messages(path, &[field], masked)
+    }
+
+    fn write_messages(
+        &self,
+        path: &Path,
+        fields: &[ConstantField],
+        masked: &[usize],
+    ) -> Result<(), eccodes::CodesError> {
+        let template_path = path.with_extension("template.grib");
+        fs::write(&template_path, self.template_message())?;
+
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        let mut template = CodesHandle::new_from_file(&template_path, GRIB)?;
+        let template_message = template.next()?.expect("Template must contain a message");
+
+        for field in fields {
+            let mut message = template_message.clone();
+
+            message.write_key(Key {
+                name: "paramId".to_string(),
+                value: Int(field.param_id),
+            })?;
+            message.write_key(Key {
+                name: "typeOfLevel".to_string(),
+                value: Str(field.type_of_level.to_string()),
+            })?;
+            message.write_key(Key {
+                name: "level".to_string(),
+                value: Int(field.level),
+            })?;
+
+            let mut values = vec![field.value; self.shape.0 * self.shape.1];
+
+            if !masked.is_empty() {
+                message.write_key(Key {
+                    name: "bitmapPresent".to_string(),
+                    value: Int(1),
+                })?;
+                message.write_key(Key {
+                    name: "missingValue".to_string(),
+                    value: KeyType::Float(MISSING_VALUE),
+                })?;
+
+                for &index in masked {
+                    values[index] = MISSING_VALUE;
+                }
+            }
+
+            message.write_key(Key {
+                name: "values".to_string(),
+                value: FloatArray(values),
+            })?;
+
+            message.write_to_file(path, true)?;
+        }
+
+        fs::remove_file(&template_path)?;
+
+        Ok(())
+    }
+
+    /// Encodes GRIB1 message with fixture grid and valid time,
+    /// ECMWF local parameters table and a constant zero field
+    /// of temperature at 1000 hPa.
+    fn template_message(&self) -> Vec<u8> {
+        let (ni, nj) = self.shape;
+        let lon_east = self.lon_west + (ni - 1) as Float * self.step;
+        let lat_south = self.lat_north - (nj - 1) as Float * self.step;
+        let date = self.datetime.date();
+        let year = date.year() as u32;
+
+        let mut product = vec![0, 0, 28, 128, 98, 128, 255, 0b1000_0000, 130, 100];
+        product.extend_from_slice(&1000u16.to_be_bytes());
+        product.extend_from_slice(&[
+            ((year - 1) % 100 + 1) as u8,
+            date.month() as u8,
+            date.day() as u8,
+            self.datetime.hour() as u8,
+            self.datetime.minute() as u8,
+        ]);
+        // forecast time unit (hour), P1, P2 and time range indicator
+        product.extend_from_slice(&[1, 0, 0, 0]);
+        // number included in average, number missing,
+        // century, sub-centre and decimal scale factor
+        product.extend_from_slice(&[0, 0, 0, ((year - 1) / 100 + 1) as u8, 0, 0, 0]);
+
+        let mut grid = vec![0, 0, 32, 0, 255, 0];
+        grid.extend_from_slice(&(ni as u16).to_be_bytes());
+        grid.extend_from_slice(&(nj as u16).to_be_bytes());
+        grid.extend(millidegrees(self.lat_north));
+        grid.extend(millidegrees(self.lon_west));
+        grid.push(0b1000_0000);
+        grid.extend(millidegrees(lat_south));
+        grid.extend(millidegrees(lon_east));
+        grid.extend_from_slice(&(millidegrees_value(self.step) as u16).to_be_bytes());
+        grid.extend_from_slice(&(millidegrees_value(self.step) as u16).to_be_bytes());
+        grid.extend_from_slice(&[0, 0, 0, 0, 0]);
+
+        // constant field is packed with zero bits per value, so data section
+        // has only the header (padded to even length) with zero reference value
+        let data = [0, 0, 12, 8, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let length = 8 + product.len() + grid.len() + data.len() + 4;
+
+        let mut message = b"GRIB".to_vec();
+        message.extend_from_slice(&(length as u32).to_be_bytes()[1..]);
+        message.push(1);
+        message.extend(product);
+        message.extend(grid);
+        message.extend_from_slice(&data);
+        message.extend_from_slice(b"7777");
+
+        message
+    }
+}
+
+fn millidegrees_value(degrees: Float) -> i32 {
+    (degrees * 1000.0).round() as i32
+}
+
+/// Encodes coordinate in millidegrees as 3-octet
+/// integer in GRIB sign and magnitude convention.
+fn millidegrees(degrees: Float) -> [u8; 3] {
+    let value = millidegrees_value(degrees);
+    let mut magnitude = value.unsigned_abs();
+
+    if value < 0 {
+        magnitude |= 1 << 23;
+    }
+
+    let octets = magnitude.to_be_bytes();
+
+    [octets[1], octets[2], octets[3]]
+}
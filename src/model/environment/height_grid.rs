@@ -0,0 +1,161 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module responsible for interpolating fields
+//! onto regular height levels.
+//!
+//! Heights of pressure levels differ between columns, so levels
+//! surrounding the parcel are found by searching each column.
+//! On regular height levels the search is replaced with a simple
+//! division, at the cost of storing (usually) more levels.
+
+use super::{fields::Fields, FieldFloat};
+use crate::{
+    errors::{EnvironmentError, SearchError},
+    Float,
+};
+use ndarray::{s, Array3};
+
+/// Regular height levels (in meters above mean sea level)
+/// onto which fields are interpolated.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub(super) struct HeightGrid {
+    bottom: Float,
+    spacing: Float,
+    levels: usize,
+}
+
+impl HeightGrid {
+    /// Creates levels with given spacing from the lowest level
+    /// of all columns up to the lowest top of columns,
+    /// so that fields are never extrapolated upwards.
+    ///
+    /// Columns of all given height fields are covered, so that
+    /// fields valid at different times share the same levels.
+    pub fn covering(
+        heights: &[&Array3<FieldFloat>],
+        spacing: Float,
+    ) -> Result<Self, EnvironmentError> {
+        let mut bottom = Float::MAX;
+        let mut top = Float::MAX;
+
+        for heights in heights {
+            let top_level = heights.dim().0 - 1;
+
+            bottom = heights
+                .slice(s![0, .., ..])
+                .fold(bottom, |min, &z| min.min(z as Float));
+            top = heights
+                .slice(s![top_level, .., ..])
+                .fold(top, |min, &z| min.min(z as Float));
+        }
+
+        let bottom = (bottom / spacing).floor() * spacing;
+        let levels = ((top - bottom) / spacing).floor() as usize + 1;
+
+        if levels < 2 {
+            return Err(EnvironmentError::IncorrectHeightGrid(
+                "height grid spacing is larger than depth of the input data",
+            ));
+        }
+
+        Ok(HeightGrid {
+            bottom,
+            spacing,
+            levels,
+        })
+    }
+
+    /// Height of the level with given index.
+    fn height(&self, index: usize) -> Float {
+        self.bottom + index as Float * self.spacing
+    }
+
+    /// Finds indices of levels below and above the given height,
+    /// not lower than `lowest_level`.
+    ///
+    /// Same as when searching the column, heights below the lowest
+    /// level are extrapolated and heights above the top are out of bounds.
+    pub fn bracket(&self, z: Float, lowest_level: usize) -> Result<(usize, usize), SearchError> {
+        let position = (z - self.bottom) / self.spacing;
+
+        if position > (self.levels - 1) as Float {
+            return Err(SearchError::OutOfBounds);
+        }
+
+        let bottom_index = (position.max(0.0) as usize)
+            .max(lowest_level)
+            .min(self.levels - 2);
+
+        Ok((bottom_index, bottom_index + 1))
+    }
+}
+
+/// Interpolates (linearly in height) all fields onto given
+/// regular height levels.
+///
+/// Below the lowest level of the column fields are extrapolated,
+/// as it is done when accessing fields on pressure levels.
+pub(super) fn resample_fields(fields: &mut Fields, grid: HeightGrid) {
+    let (input_levels, x_len, y_len) = fields.height.dim();
+    let shape = (grid.levels, x_len, y_len);
+
+    // index of the input level below and interpolation weight
+    // are the same for all fields, so they are computed once
+    let mut brackets = Array3::from_elem(shape, (0, 0.0));
+
+    for i in 0..x_len {
+        for j in 0..y_len {
+            let column = fields.height.slice(s![.., i, j]);
+            let mut bottom_index = 0;
+
+            for k in 0..grid.levels {
+                let z = grid.height(k);
+
+                while bottom_index + 2 < input_levels && column[bottom_index + 1] as Float <= z {
+                    bottom_index += 1;
+                }
+
+                let bottom_z = column[bottom_index] as Float;
+                let upper_z = column[bottom_index + 1] as Float;
+
+                brackets[[k, i, j]] = (bottom_index, (z - bottom_z) / (upper_z - bottom_z));
+            }
+        }
+    }
+
+    let resample = |values: &Array3<FieldFloat>| {
+        Array3::from_shape_fn(shape, |(k, i, j)| {
+            let (bottom_index, weight) = brackets[[k, i, j]];
+            let bottom_value = values[[bottom_index, i, j]] as Float;
+            let upper_value = values[[bottom_index + 1, i, j]] as Float;
+
+            (bottom_value + weight * (upper_value - bottom_value)) as FieldFloat
+        })
+    };
+
+    fields.temperature = resample(&fields.temperature);
+    fields.pressure = resample(&fields.pressure);
+    fields.u_wind = resample(&fields.u_wind);
+    fields.v_wind = resample(&fields.v_wind);
+    fields.spec_humidity = resample(&fields.spec_humidity);
+    fields.virtual_temp = resample(&fields.virtual_temp);
+    fields.vertical_vel = resample(&fields.vertical_vel);
+    fields.height = Array3::from_shape_fn(shape, |(k, _, _)| grid.height(k) as FieldFloat);
+}
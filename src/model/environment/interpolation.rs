@@ -272,4 +272,71 @@ mod tests {
 
         assert_approx_eq!(Float, r, 4.5);
     }
+
+    fn bilinear_polynomial(x: Float, y: Float) -> Float {
+        1.5 - 0.2 * x + 0.7 * y + 0.03 * x * y
+    }
+
+    fn trilinear_polynomial(x: Float, y: Float, z: Float) -> Float {
+        -2.0 + 0.4 * x - 0.1 * y + 0.8 * z + 0.05 * x * y - 0.02 * x * z + 0.01 * y * z
+            - 0.003 * x * y * z
+    }
+
+    #[test]
+    fn bilinear_off_grid() {
+        // corners of a skewed cell, as projected gridpoints are
+        let corners = [(10.0, 20.0), (10.5, 31.0), (22.0, 19.0), (21.5, 30.5)];
+
+        let points = corners.map(|(x, y)| Point2D {
+            x,
+            y,
+            value: bilinear_polynomial(x, y),
+        });
+
+        for (x, y) in [(11.0, 21.0), (15.3, 24.7), (16.0, 25.0), (20.9, 29.8)] {
+            let r = interpolate_bilinear(x, y, points);
+
+            assert_approx_eq!(Float, r, bilinear_polynomial(x, y), epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn trilinear_off_grid() {
+        let corners = [(10.0, 20.0), (10.5, 31.0), (22.0, 19.0), (21.5, 30.5)];
+        let levels = [
+            (100.0, 350.0),
+            (110.0, 340.0),
+            (95.0, 360.0),
+            (105.0, 345.0),
+        ];
+
+        let mut points = [Point3D::default(); 8];
+
+        for (i, ((x, y), (bottom, top))) in corners.iter().zip(levels).enumerate() {
+            points[i] = Point3D {
+                x: *x,
+                y: *y,
+                z: bottom,
+                value: trilinear_polynomial(*x, *y, bottom),
+            };
+
+            points[i + 4] = Point3D {
+                x: *x,
+                y: *y,
+                z: top,
+                value: trilinear_polynomial(*x, *y, top),
+            };
+        }
+
+        for (x, y, z) in [
+            (11.0, 21.0, 120.0),
+            (15.3, 24.7, 200.0),
+            (16.0, 25.0, 333.3),
+            (20.9, 29.8, 150.0),
+        ] {
+            let r = interpolate_tilinear(x, y, z, points);
+
+            assert_approx_eq!(Float, r, trilinear_polynomial(x, y, z), epsilon = 1e-9);
+        }
+    }
 }
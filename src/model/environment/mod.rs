@@ -21,26 +21,80 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! (environment) data, and providing that data to parcels.
 
 mod accesser;
+#[cfg(any(test, feature = "bench"))]
+pub(super) mod analytic;
 mod bisection;
+mod column;
+mod dump;
 mod fields;
+#[cfg(test)]
+pub(super) mod grib_fixture;
+mod height_grid;
 mod interpolation;
+mod profile;
 mod projection;
 mod surfaces;
+mod terrain;
 
+pub use self::accesser::SearchCache;
+pub use self::column::Column;
 use self::fields::Fields;
+use self::height_grid::HeightGrid;
 use self::surfaces::Surfaces;
-use super::configuration::{Config, Domain};
-use crate::constants::{NS_C_EARTH, WE_C_EARTH};
+use self::terrain::TerrainBoundary;
+use super::configuration::{Config, Domain, EarthModel, Input};
+use crate::constants::{NS_C_EARTH, SPHERE_EARTH_RADIUS, WE_C_EARTH, WGS84_A, WGS84_E};
 use crate::model::environment::projection::LambertConicConformal;
-use crate::{errors::EnvironmentError, Float};
+use crate::{
+    errors::{EnvironmentError, InputError},
+    Float,
+};
+use chrono::NaiveDateTime;
+use eccodes::{
+    KeyType::{self, FloatArray, Int},
+    KeyedMessage,
+};
 use log::debug;
+#[cfg(feature = "3d")]
+use log::warn;
+use ndarray::{s, Array, Array2, ArrayView1, Dimension};
 
+/// Floating-point type used to store buffered environment data.
+///
+/// Buffered 3D fields are the main memory consumer of the model,
+/// so by default they are stored in single precision, which halves
+/// the memory needed for buffering. Values are converted to [`Float`]
+/// when accessed, so all parcel computations are still done in double
+/// precision.
+///
+/// Single precision keeps around 7 significant digits, which gives
+/// an error of order of `0.01 Pa` for pressure and `0.001 m` for height.
+/// That is well below the accuracy of GRIB input data (which is usually
+/// packed with fewer bits), but it can slightly change the results.
+/// When bit-exact results are needed, `high_precision` feature restores
+/// double precision storage.
+#[cfg(not(feature = "high_precision"))]
+pub type FieldFloat = f32;
+
+/// Floating-point type used to store buffered environment data.
+///
+/// With `high_precision` feature data is stored in double precision.
+#[cfg(feature = "high_precision")]
+pub type FieldFloat = f64;
+
+/// Minimal number of input data gridpoints buffered
+/// outside the domain on each side, so that environment
+/// can be interpolated at the whole domain.
+const MIN_HALO_GRIDPOINTS: usize = 2;
+
+/// Extent of the domain, either in geographic
+/// coordinates or in indices of the input grid.
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default)]
-struct DomainExtent<T> {
-    north: T,
-    south: T,
-    west: T,
-    east: T,
+pub struct DomainExtent<T> {
+    pub north: T,
+    pub south: T,
+    pub west: T,
+    pub east: T,
 }
 
 /// Enum containing fields on pressure
@@ -50,6 +104,7 @@ pub enum EnvFields {
     Pressure,
     Temperature,
     VirtualTemperature,
+    SpecHumidity,
     UWind,
     VWind,
     VerticalVel,
@@ -79,34 +134,256 @@ pub enum SurfaceFields {
 pub struct Environment {
     fields: Fields,
     surfaces: Surfaces,
+    terrain: Option<TerrainBoundary>,
+    height_grid: Option<HeightGrid>,
+    later: Option<LaterEnvironment>,
     pub projection: LambertConicConformal,
 }
 
+/// Environment valid at a later time, towards which
+/// fields are interpolated in time.
+#[derive(Debug)]
+struct LaterEnvironment {
+    environment: Box<Environment>,
+
+    /// Valid time of the earlier environment
+    start: NaiveDateTime,
+
+    /// Time (in seconds) between both valid times
+    interval: Float,
+}
+
+impl LaterEnvironment {
+    /// Weight of the later environment at given time,
+    /// which is kept constant outside of the valid times.
+    fn time_weight(&self, datetime: NaiveDateTime) -> Float {
+        let elapsed = (datetime - self.start).num_milliseconds() as Float / 1000.0;
+
+        (elapsed / self.interval).clamp(0.0, 1.0)
+    }
+}
+
 impl Environment {
     /// Environment struct constructor
     /// responsible for reading GRIB files
     /// and buffering data in domain extent.
+    ///
+    /// When later input data is set, both environments are buffered
+    /// in the same extent and resampled onto the same height grid.
     pub fn new(config: &Config) -> Result<Self, EnvironmentError> {
         debug!("Creating new enviroment");
 
         let projection = generate_domain_projection(&config.domain)?;
-        let domain_edges = compute_domain_edges(config, &projection);
+        let domain_edges = compute_domain_edges(config, &projection)?;
+
+        let data = fields::collect(&config.input)?;
+
+        let later_input = config.input.later_input();
+        let later_data = later_input.as_ref().map(fields::collect).transpose()?;
 
-        let fields = Fields::new(&config.input, domain_edges)?;
-        let surfaces = Surfaces::new(&config.input, domain_edges)?;
+        #[cfg(feature = "3d")]
+        let domain_edges = {
+            let mut inputs = vec![(&config.input, data.as_slice())];
+            inputs.extend(later_input.iter().zip(later_data.as_deref()));
+
+            expand_for_advection(config, &projection, &inputs, domain_edges)?
+        };
+
+        let mut environment = Environment::buffer(&config.input, data, projection, domain_edges)?;
+
+        if let (Some(later_input), Some(later_data)) = (later_input, later_data) {
+            debug!("Creating environment at later valid time");
+
+            let valid_times = config.input.valid_time.zip(later_input.valid_time).ok_or(
+                EnvironmentError::IncorrectLaterEnvironment("valid times of input are not known"),
+            )?;
+
+            let later = Environment::buffer(&later_input, later_data, projection, domain_edges)?;
+            environment = environment.with_later(later, valid_times)?;
+        }
+
+        if let Some(height_grid) = &config.interpolation.precompute_height_grid {
+            environment.resample_to_height_grid(height_grid.dz_m)?;
+        }
+
+        Ok(environment)
+    }
+
+    /// Buffers fields and surfaces from read GRIB messages
+    /// in given extent, without resampling them.
+    fn buffer(
+        input: &Input,
+        data: Vec<KeyedMessage>,
+        projection: LambertConicConformal,
+        domain_edges: DomainExtent<usize>,
+    ) -> Result<Self, EnvironmentError> {
+        let fields = Fields::new(input, &data, domain_edges)?;
+        drop(data);
+        check_buffered_shape(fields.height.shape())?;
+
+        if input.check_superadiabatic {
+            fields.warn_superadiabatic_layers();
+        }
+
+        let surfaces = Surfaces::new(input, domain_edges)?;
+
+        let terrain = if input.terrain_following {
+            Some(TerrainBoundary::new(&fields, &surfaces)?)
+        } else {
+            None
+        };
 
         Ok(Environment {
             fields,
             surfaces,
+            terrain,
+            height_grid: None,
+            later: None,
             projection,
         })
     }
+
+    /// Adds the environment valid at a later time, so that fields
+    /// accessed with [`Environment::get_field_value_at`] are linearly
+    /// interpolated in time between `valid_times` of both environments.
+    ///
+    /// Both environments must be buffered on the same grid.
+    pub fn with_later(
+        mut self,
+        later: Environment,
+        valid_times: (NaiveDateTime, NaiveDateTime),
+    ) -> Result<Self, EnvironmentError> {
+        let (start, end) = valid_times;
+
+        if end <= start {
+            return Err(EnvironmentError::IncorrectLaterEnvironment(
+                "later valid time must be after the earlier one",
+            ));
+        }
+
+        if later.fields.lons != self.fields.lons
+            || later.fields.lats != self.fields.lats
+            || later.fields.height.dim() != self.fields.height.dim()
+        {
+            return Err(EnvironmentError::IncorrectLaterEnvironment(
+                "both environments must have the same grid and levels",
+            ));
+        }
+
+        self.later = Some(LaterEnvironment {
+            environment: Box::new(later),
+            start,
+            interval: (end - start).num_milliseconds() as Float / 1000.0,
+        });
+
+        Ok(self)
+    }
+
+    /// Interpolates buffered fields onto regular height levels
+    /// with given spacing (in meters), so that accessing the fields
+    /// does not require searching the columns.
+    ///
+    /// Fields of the later environment are interpolated onto
+    /// the same levels, covering columns of both environments.
+    pub fn resample_to_height_grid(&mut self, spacing: Float) -> Result<(), EnvironmentError> {
+        debug!("Interpolating fields onto regular height levels");
+
+        let mut heights = vec![&self.fields.height];
+        if let Some(later) = &self.later {
+            heights.push(&later.environment.fields.height);
+        }
+
+        let grid = HeightGrid::covering(&heights, spacing)?;
+        self.apply_height_grid(grid)
+    }
+
+    /// Resamples fields of this and the later environment
+    /// onto given height grid.
+    fn apply_height_grid(&mut self, grid: HeightGrid) -> Result<(), EnvironmentError> {
+        height_grid::resample_fields(&mut self.fields, grid);
+        self.height_grid = Some(grid);
+
+        if let Some(later) = &mut self.later {
+            later.environment.apply_height_grid(grid)?;
+        }
+
+        // levels above the surface change with the grid
+        if self.terrain.is_some() {
+            self.terrain = Some(TerrainBoundary::new(&self.fields, &self.surfaces)?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns geographic extent (in degrees) of gridpoints
+    /// in which the environment has been buffered.
+    pub fn buffered_extent(&self) -> DomainExtent<Float> {
+        let lons = &self.fields.lons;
+        let lats = &self.fields.lats;
+
+        // fields are oriented with x-axis in WE direction
+        // and y-axis in NS direction, so edges are first
+        // and last gridpoints along each axis
+        DomainExtent {
+            north: lats[[0, 0]],
+            south: lats[[0, lats.ncols() - 1]],
+            west: convert_from_grib_longitudes(lons[[0, 0]]),
+            east: convert_from_grib_longitudes(lons[[lons.nrows() - 1, 0]]),
+        }
+    }
+
+    /// Returns the list of input levels read from GRIB (in units
+    /// of the input level type), sorted from the lowest one.
+    ///
+    /// When fields are resampled onto regular height levels
+    /// these are levels of the input data, not of buffered fields.
+    pub fn levels(&self) -> &[i64] {
+        &self.fields.levels
+    }
+
+    /// Pressure (in Pa) of the input level with given value.
+    pub fn level_pressure(&self, level: i64) -> Float {
+        level as Float * self.fields.level_scale
+    }
 }
 
 /// Function to create a geographic projection struct
 /// with parameters that allow for lowest distorion
 /// for a given domain.
+///
+/// When [`Domain::rotation_deg`] is set, the projection is rotated
+/// about the domain center, so that the reference point
+/// remains the south-west corner of the domain grid.
 fn generate_domain_projection(domain: &Domain) -> Result<LambertConicConformal, EnvironmentError> {
+    let (lon_0, lat_1, lat_2) = domain_projection_params(domain);
+
+    let (a, e) = match domain.earth_model {
+        EarthModel::Wgs84 => (WGS84_A, WGS84_E),
+        EarthModel::Sphere => (SPHERE_EARTH_RADIUS, 0.0),
+    };
+
+    let projection = LambertConicConformal::new_on_ellipsoid(lon_0, lat_1, lat_2, a, e)?;
+
+    let rotation = match domain.rotation_deg {
+        Some(rotation) => rotation,
+        None => return Ok(projection),
+    };
+
+    let sides = measure_domain_sides(domain);
+    let sw_xy = projection.project(domain.ref_lon, domain.ref_lat);
+    let (sin, cos) = rotation.to_radians().sin_cos();
+
+    let center = (
+        sw_xy.0 + 0.5 * (sides.0 * cos - sides.1 * sin),
+        sw_xy.1 + 0.5 * (sides.0 * sin + sides.1 * cos),
+    );
+
+    Ok(projection.with_rotation(rotation, center))
+}
+
+/// Function to compute central longitude and standard
+/// parallels of projection for a given domain.
+fn domain_projection_params(domain: &Domain) -> (Float, Float, Float) {
     let sides = measure_domain_sides(domain);
 
     // if there's only one parcel to release in some direction
@@ -128,9 +405,89 @@ fn generate_domain_projection(domain: &Domain) -> Result<LambertConicConformal,
         lat_2 = compute_top_lat(domain.ref_lat, sides.1);
     }
 
-    let projection = LambertConicConformal::new(lon_0, lat_1, lat_2)?;
+    (lon_0, lat_1, lat_2)
+}
+
+/// Describes projection generated for the domain
+/// and projected coordinates of domain corners.
+///
+/// Useful for debugging distorted domains without running the model.
+pub fn projection_info(domain: &Domain) -> Result<String, EnvironmentError> {
+    let (_, lat_1, lat_2) = domain_projection_params(domain);
+    let projection = generate_domain_projection(domain)?;
+
+    let mut info = format!(
+        "Lambert Conformal Conic projection\n\
+        lat_1: {:.6}\n\
+        lat_2: {:.6}\n\
+        {}\n\
+        Domain corners (x [m], y [m], lon, lat):",
+        lat_1, lat_2, projection
+    );
+
+    for (name, (x, y)) in domain_corners(domain, &projection) {
+        let (lon, lat) = projection.inverse_project(x, y);
+        info.push_str(&format!(
+            "\n{}: {:.3}, {:.3}, {:.6}, {:.6}",
+            name, x, y, lon, lat
+        ));
+    }
+
+    Ok(info)
+}
 
-    Ok(projection)
+/// Cartographic coordinates of domain grid corners.
+fn domain_corners(
+    domain: &Domain,
+    projection: &LambertConicConformal,
+) -> [(&'static str, (Float, Float)); 4] {
+    let sides = measure_domain_sides(domain);
+    let sw_xy = projection.project(domain.ref_lon, domain.ref_lat);
+
+    [
+        ("SW", sw_xy),
+        ("SE", (sw_xy.0 + sides.0, sw_xy.1)),
+        ("NW", (sw_xy.0, sw_xy.1 + sides.1)),
+        ("NE", (sw_xy.0 + sides.0, sw_xy.1 + sides.1)),
+    ]
+}
+
+/// Lat-lon extent of domain grid without margins.
+///
+/// Edges of domain without rotation are close to parallels
+/// and meridians, so the extent spans from the reference
+/// point to the north-east corner. Rotated domain is
+/// covered by the extent of all its corners.
+fn domain_lonlat_extent(
+    domain: &Domain,
+    projection: &LambertConicConformal,
+) -> DomainExtent<Float> {
+    let corners =
+        domain_corners(domain, projection).map(|(_, (x, y))| projection.inverse_project(x, y));
+
+    if domain.rotation_deg.is_none() {
+        return DomainExtent {
+            west: domain.ref_lon,
+            south: domain.ref_lat,
+            east: corners[3].0,
+            north: corners[3].1,
+        };
+    }
+
+    corners.iter().fold(
+        DomainExtent {
+            west: Float::MAX,
+            south: Float::MAX,
+            east: Float::MIN,
+            north: Float::MIN,
+        },
+        |extent, &(lon, lat)| DomainExtent {
+            west: extent.west.min(lon),
+            south: extent.south.min(lat),
+            east: extent.east.max(lon),
+            north: extent.north.max(lat),
+        },
+    )
 }
 
 /// Function to get domain sides length
@@ -161,24 +518,49 @@ fn approx_central_lon(lon_0: Float, lat_0: Float, distance: Float) -> Float {
 }
 
 /// Function to get a lat-lon extent of domain with margins.
+///
+/// When buffer extent is provided in the configuration
+/// it is used instead, after checking that it contains the domain.
+/// When margins are given in gridpoints, the extent is expanded
+/// by that number of gridpoints around the domain.
 fn compute_domain_edges(
     config: &Config,
     projection: &LambertConicConformal,
-) -> DomainExtent<usize> {
-    let sw_xy = projection.project(config.domain.ref_lon, config.domain.ref_lat);
+) -> Result<DomainExtent<usize>, EnvironmentError> {
+    let (domain_only_edges, lonlat_extent) = find_domain_only_edges(config, projection)?;
+    let distinct_lonlats = &config.input.distinct_lonlats;
 
-    let ne_xy = (
-        sw_xy.0 + (Float::from(config.domain.shape.0 - 1) * config.domain.spacing),
-        sw_xy.1 + (Float::from(config.domain.shape.1 - 1) * config.domain.spacing),
-    );
+    let domain_extent = if let Some(buffer_extent) = config.input.buffer_extent {
+        if buffer_extent.west > lonlat_extent.west
+            || buffer_extent.south > lonlat_extent.south
+            || buffer_extent.east < lonlat_extent.east
+            || buffer_extent.north < lonlat_extent.north
+        {
+            return Err(EnvironmentError::IncorrectBufferExtent(
+                "Buffer extent does not contain the whole domain",
+            ));
+        }
+
+        DomainExtent {
+            west: buffer_extent.west,
+            south: buffer_extent.south,
+            east: buffer_extent.east,
+            north: buffer_extent.north,
+        }
+    } else if let Some(margin_gridpoints) = config.domain.margin_gridpoints {
+        let domain_edges =
+            expand_edge_indices(distinct_lonlats, domain_only_edges, margin_gridpoints)?;
 
-    let ne_lonlat = projection.inverse_project(ne_xy.0, ne_xy.1);
+        debug!("Computed buffering extent: {:?}", domain_edges);
 
-    let domain_extent = DomainExtent {
-        west: config.domain.ref_lon - config.domain.margins.0,
-        south: config.domain.ref_lat - config.domain.margins.1,
-        east: ne_lonlat.0 + config.domain.margins.0,
-        north: ne_lonlat.1 + config.domain.margins.1,
+        return Ok(domain_edges);
+    } else {
+        DomainExtent {
+            west: lonlat_extent.west - config.domain.margins.0,
+            south: lonlat_extent.south - config.domain.margins.1,
+            east: lonlat_extent.east + config.domain.margins.0,
+            north: lonlat_extent.north + config.domain.margins.1,
+        }
     };
 
     debug!(
@@ -186,40 +568,405 @@ fn compute_domain_edges(
         domain_extent.south, domain_extent.north, domain_extent.west, domain_extent.east
     );
 
+    let domain_edges = find_extent_edge_indices(distinct_lonlats, domain_extent)?;
+    check_halo_size(distinct_lonlats, domain_only_edges, domain_edges)?;
+
+    Ok(domain_edges)
+}
+
+/// Finds indices of the input grid covering the domain without
+/// margins, and returns them with its lat-lon extent.
+fn find_domain_only_edges(
+    config: &Config,
+    projection: &LambertConicConformal,
+) -> Result<(DomainExtent<usize>, DomainExtent<Float>), EnvironmentError> {
+    let lonlat_extent = domain_lonlat_extent(&config.domain, projection);
+    let domain_only_edges =
+        find_extent_edge_indices(&config.input.distinct_lonlats, lonlat_extent)?;
+
+    Ok((domain_only_edges, lonlat_extent))
+}
+
+/// Expands buffering extent when margins are smaller than the distance
+/// parcels can be advected by the strongest wind in that extent
+/// during [`Domain::advection_time`], so that fields are buffered once.
+/// Wind of all given inputs (valid at different times) is checked.
+///
+/// The extent is expanded symmetrically, so that it still
+/// contains the extent computed from margins.
+#[cfg(feature = "3d")]
+fn expand_for_advection(
+    config: &Config,
+    projection: &LambertConicConformal,
+    inputs: &[(&Input, &[KeyedMessage])],
+    domain_edges: DomainExtent<usize>,
+) -> Result<DomainExtent<usize>, EnvironmentError> {
+    let advection_time = match config.domain.advection_time {
+        Some(advection_time) => advection_time,
+        None => return Ok(domain_edges),
+    };
+
     let distinct_lonlats = &config.input.distinct_lonlats;
-    find_extent_edge_indices(distinct_lonlats, domain_extent)
+    let (domain_only_edges, _) = find_domain_only_edges(config, projection)?;
+
+    let mut max_wind: Float = 0.0;
+    for (input, data) in inputs {
+        max_wind = max_wind.max(fields::max_wind_speed(input, data, domain_edges)?);
+    }
+
+    let distance = max_wind * advection_time;
+    let needed = advection_gridpoints(distinct_lonlats, domain_edges, distance);
+    let halo = halo_size(distinct_lonlats, domain_only_edges, domain_edges);
+
+    if halo.west.min(halo.east) >= needed.0 && halo.north.min(halo.south) >= needed.1 {
+        return Ok(domain_edges);
+    }
+
+    let expanded_halo = (
+        needed.0.max(halo.west).max(halo.east),
+        needed.1.max(halo.north).max(halo.south),
+    );
+
+    warn!(
+        "Margins are smaller than {:.0} km parcels can be advected by the strongest wind ({:.1} m/s), buffered extent is expanded to {} x {} gridpoints around the domain",
+        distance / 1000.0,
+        max_wind,
+        expanded_halo.0,
+        expanded_halo.1
+    );
+
+    expand_edge_indices(distinct_lonlats, domain_only_edges, expanded_halo)
+}
+
+/// Computes the number of input grid gridpoints along lon and lat axis
+/// covering given distance (in meters) everywhere in the buffered extent.
+#[cfg(feature = "3d")]
+fn advection_gridpoints(
+    distinct_lonlats: &(Vec<Float>, Vec<Float>),
+    buffered_edges: DomainExtent<usize>,
+    distance: Float,
+) -> (usize, usize) {
+    let (lons, lats) = distinct_lonlats;
+
+    let lon_step = (lons[1] - lons[0]).abs();
+    let lat_step = (lats[1] - lats[0]).abs();
+
+    // length of longitude degree is the smallest
+    // at the most poleward latitude of the extent
+    let max_lat = lats[buffered_edges.north]
+        .abs()
+        .max(lats[buffered_edges.south].abs());
+    let lon_degree_length = max_lat.to_radians().cos() * (WE_C_EARTH / 360.0);
+    let lat_degree_length = NS_C_EARTH / 360.0;
+
+    let lon_gridpoints = (distance / (lon_step * lon_degree_length)).ceil() as usize;
+    let lat_gridpoints = (distance / (lat_step * lat_degree_length)).ceil() as usize;
+
+    (
+        lon_gridpoints.max(MIN_HALO_GRIDPOINTS),
+        lat_gridpoints.max(MIN_HALO_GRIDPOINTS),
+    )
+}
+
+/// Expands indices of the domain edges by given
+/// number of gridpoints in lon and lat axis.
+///
+/// Returns an error when the expanded extent
+/// is not covered by input data.
+fn expand_edge_indices(
+    distinct_lonlats: &(Vec<Float>, Vec<Float>),
+    domain_edges: DomainExtent<usize>,
+    margin_gridpoints: (usize, usize),
+) -> Result<DomainExtent<usize>, EnvironmentError> {
+    let out_of_input =
+        || EnvironmentError::InsufficientMargins("Margins extend beyond the input data extent");
+
+    let expanded = DomainExtent {
+        north: domain_edges.north.checked_sub(margin_gridpoints.1),
+        south: Some(domain_edges.south + margin_gridpoints.1)
+            .filter(|&south| south < distinct_lonlats.1.len()),
+        west: domain_edges.west.checked_sub(margin_gridpoints.0),
+        east: Some(domain_edges.east + margin_gridpoints.0)
+            .filter(|&east| east < distinct_lonlats.0.len()),
+    };
+
+    Ok(DomainExtent {
+        north: expanded.north.ok_or_else(out_of_input)?,
+        south: expanded.south.ok_or_else(out_of_input)?,
+        west: expanded.west.ok_or_else(out_of_input)?,
+        east: expanded.east.ok_or_else(out_of_input)?,
+    })
+}
+
+/// Checks that buffered extent contains at least [`MIN_HALO_GRIDPOINTS`]
+/// gridpoints outside the domain on each side.
+fn check_halo_size(
+    distinct_lonlats: &(Vec<Float>, Vec<Float>),
+    domain_edges: DomainExtent<usize>,
+    buffered_edges: DomainExtent<usize>,
+) -> Result<(), EnvironmentError> {
+    let halo = halo_size(distinct_lonlats, domain_edges, buffered_edges);
+
+    if halo.north.min(halo.south).min(halo.west).min(halo.east) < MIN_HALO_GRIDPOINTS {
+        return Err(EnvironmentError::InsufficientMargins(
+            "At least 2 gridpoints are needed on each side of the domain",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Computes the number of input data gridpoints
+/// buffered outside the domain on each side.
+fn halo_size(
+    distinct_lonlats: &(Vec<Float>, Vec<Float>),
+    domain_edges: DomainExtent<usize>,
+    buffered_edges: DomainExtent<usize>,
+) -> DomainExtent<usize> {
+    // buffered longitudes can wrap around the input grid edge
+    let lons_count = distinct_lonlats.0.len();
+    let lon_distance = |from: usize, to: usize| (to + lons_count - from) % lons_count;
+
+    DomainExtent {
+        north: domain_edges.north.saturating_sub(buffered_edges.north),
+        south: buffered_edges.south.saturating_sub(domain_edges.south),
+        west: lon_distance(buffered_edges.west, domain_edges.west),
+        east: lon_distance(domain_edges.east, buffered_edges.east),
+    }
+}
+
+/// Checks that buffered fields (with `[level, lon, lat]` shape)
+/// have enough points along each axis to interpolate between them.
+fn check_buffered_shape(shape: &[usize]) -> Result<(), EnvironmentError> {
+    if shape[0] < 2 {
+        return Err(InputError::DataNotSufficient(
+            "At least 2 levels are needed to interpolate the environment vertically",
+        )
+        .into());
+    }
+
+    if shape[1] < 2 || shape[2] < 2 {
+        return Err(EnvironmentError::InsufficientMargins(
+            "Buffered data has less than 2 gridpoints along lon or lat axis, use larger margins or domain spacing",
+        ));
+    }
+
+    Ok(())
 }
 
 /// Finds closests indices in the GRIB input files
 /// grid that fully cover domain with margins (it is
 /// with some excess).
+///
+/// Returns an error when the extent is not covered by input data.
 fn find_extent_edge_indices(
     distinct_lonlats: &(Vec<Float>, Vec<Float>),
     domain_extent: DomainExtent<Float>,
-) -> DomainExtent<usize> {
+) -> Result<DomainExtent<usize>, EnvironmentError> {
+    let lons = ArrayView1::from(&distinct_lonlats.0);
+    let lats = ArrayView1::from(&distinct_lonlats.1);
+
     let edge_lats = (
-        bisection::find_left_closest(&distinct_lonlats.1, &domain_extent.north).unwrap(),
-        bisection::find_right_closest(&distinct_lonlats.1, &domain_extent.south).unwrap(),
+        bisection::find_left_closest(lats, &domain_extent.north)?,
+        bisection::find_right_closest(lats, &domain_extent.south)?,
     );
     let edge_lons = (
-        bisection::find_left_closest(
-            &distinct_lonlats.0,
-            &convert_to_grib_longitudes(domain_extent.west),
-        )
-        .unwrap(),
-        bisection::find_right_closest(
-            &distinct_lonlats.0,
-            &convert_to_grib_longitudes(domain_extent.east),
-        )
-        .unwrap(),
+        bisection::find_left_closest(lons, &convert_to_grib_longitudes(domain_extent.west))?,
+        bisection::find_right_closest(lons, &convert_to_grib_longitudes(domain_extent.east))?,
     );
 
-    DomainExtent {
+    Ok(DomainExtent {
         north: edge_lats.0,
         south: edge_lats.1,
         west: edge_lons.0,
         east: edge_lons.1,
+    })
+}
+
+/// Converts buffered array computed in [`Float`] to
+/// array stored in [`FieldFloat`].
+///
+/// The array is consumed, so it can be dropped right after
+/// it is computed (and its buffer is reused when types match).
+fn cast_to_storage<D: Dimension>(field: Array<Float, D>) -> Array<FieldFloat, D> {
+    field.mapv_into_any(|v| v as FieldFloat)
+}
+
+/// Scanning mode of GRIB message values,
+/// as defined by GRIB scanning mode flags.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+struct ScanningMode {
+    /// Points along parallel are scanned from east to west
+    i_negative: bool,
+
+    /// Points along meridian are scanned from south to north
+    j_positive: bool,
+
+    /// Points along meridian are consecutive in values array
+    j_consecutive: bool,
+}
+
+impl ScanningMode {
+    /// Reads scanning mode flags from GRIB message.
+    fn read_from_message(msg: &KeyedMessage) -> Result<Self, InputError> {
+        let read_flag = |key: &'static str| -> Result<bool, InputError> {
+            if let Int(flag) = msg.read_key(key)?.value {
+                Ok(flag != 0)
+            } else {
+                Err(InputError::IncorrectKeyType(key))
+            }
+        };
+
+        Ok(ScanningMode {
+            i_negative: read_flag("iScansNegatively")?,
+            j_positive: read_flag("jScansPositively")?,
+            j_consecutive: read_flag("jPointsAreConsecutive")?,
+        })
+    }
+}
+
+/// Reads values of GRIB message into 2D array of
+/// provided `(ni, nj)` shape in orientation used by the model.
+fn read_message_values(
+    msg: &KeyedMessage,
+    shape: (usize, usize),
+    missing_fill: Option<Float>,
+) -> Result<Array2<Float>, InputError> {
+    let scanning = ScanningMode::read_from_message(msg)?;
+
+    let mut values = if let FloatArray(vals) = msg.read_key("values")?.value {
+        vals
+    } else {
+        return Err(InputError::IncorrectKeyType("values"));
+    };
+
+    // points masked by the bitmap are set to the missing value
+    // indicator, which would be interpolated as regular data
+    if msg.read_key("bitmapPresent")?.value == Int(1) {
+        let bitmap = read_bitmap(msg)?;
+        mask_missing_values(&mut values, &bitmap, missing_fill);
     }
+
+    let values = orient_grid_values(values, shape, scanning)?;
+
+    Ok(values.mapv(|v| v as Float))
+}
+
+/// Reads the bitmap of GRIB message, in which
+/// gridpoints with values present are set.
+///
+/// The bitmap can be read as an array with a flag for each
+/// gridpoint, or as bytes with flags packed into bits.
+fn read_bitmap(msg: &KeyedMessage) -> Result<Vec<bool>, InputError> {
+    let bitmap = match msg.read_key("bitmap")?.value {
+        KeyType::IntArray(flags) => flags.iter().map(|&flag| flag != 0).collect(),
+        FloatArray(flags) => flags.iter().map(|&flag| flag != 0.0).collect(),
+        KeyType::Bytes(bytes) => bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+            .collect(),
+        _ => return Err(InputError::IncorrectKeyType("bitmap")),
+    };
+
+    Ok(bitmap)
+}
+
+/// Replaces values at gridpoints not set in the `bitmap`
+/// with the `fill` value, or NaN when it is not set.
+fn mask_missing_values(values: &mut [Float], bitmap: &[bool], fill: Option<Float>) {
+    let fill = fill.unwrap_or(Float::NAN);
+
+    for (value, _) in values
+        .iter_mut()
+        .zip(bitmap)
+        .filter(|(_, &present)| !present)
+    {
+        *value = fill;
+    }
+}
+
+/// Reads valid time of GRIB message
+/// from its `validityDate` and `validityTime` keys.
+pub(super) fn read_valid_time(msg: &KeyedMessage) -> Result<NaiveDateTime, InputError> {
+    let read_int = |key: &'static str| -> Result<i64, InputError> {
+        if let Int(val) = msg.read_key(key)?.value {
+            Ok(val)
+        } else {
+            Err(InputError::IncorrectKeyType(key))
+        }
+    };
+
+    compose_datetime(read_int("validityDate")?, read_int("validityTime")?)
+}
+
+/// Converts GRIB date (`YYYYMMDD`) and time (`hhmm`)
+/// integers into datetime.
+fn compose_datetime(date: i64, time: i64) -> Result<NaiveDateTime, InputError> {
+    let datetime = NaiveDateTime::parse_from_str(&format!("{date:08}{time:04}"), "%Y%m%d%H%M")?;
+
+    Ok(datetime)
+}
+
+/// Checks that all collected messages are valid at the same time,
+/// which is the `valid_time` of input (when it is known).
+///
+/// Messages with different valid times would be mixed
+/// into one environment without any other error.
+fn check_valid_time(
+    data: &[KeyedMessage],
+    valid_time: Option<NaiveDateTime>,
+) -> Result<(), InputError> {
+    let mut first_time = valid_time;
+
+    for msg in data {
+        let msg_time = read_valid_time(msg)?;
+
+        match first_time {
+            None => first_time = Some(msg_time),
+            Some(first) if first != msg_time => {
+                return Err(InputError::InconsistentValidTime {
+                    first,
+                    other: msg_time,
+                })
+            }
+            Some(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts values from GRIB message into 2D array
+/// with x-axis in WE direction and y-axis in NS direction
+/// (as distinct latitudes are sorted from north to south),
+/// regardless of the message scanning mode.
+fn orient_grid_values(
+    values: Vec<f64>,
+    shape: (usize, usize),
+    scanning: ScanningMode,
+) -> Result<Array2<f64>, ndarray::ShapeError> {
+    // a bit of magic
+    // when points along parallel are consecutive data values in GRIB
+    // are a vec of values row-by-row (x-axis is in WE direction)
+    // but from_shape_vec(final_shape, data) splits the data into final_shape.1 long chunks
+    // and puts them in columns
+    // so we need to correctly split the data in GRIB vector into Array2 and then transpose
+    // that array to get axes along expected geographical directions
+    let mut grid = if scanning.j_consecutive {
+        Array2::from_shape_vec(shape, values)?
+    } else {
+        Array2::from_shape_vec((shape.1, shape.0), values)?.reversed_axes()
+    };
+
+    if scanning.i_negative {
+        grid = grid.slice(s![..;-1, ..]).to_owned();
+    }
+
+    if scanning.j_positive {
+        grid = grid.slice(s![.., ..;-1]).to_owned();
+    }
+
+    Ok(grid)
 }
 
 /// Converts the longitude in convention used by model
@@ -232,3 +979,291 @@ fn convert_to_grib_longitudes(longitude: Float) -> Float {
 
     longitude
 }
+
+/// Converts longitude from GRIB convention (`0..360`)
+/// to the one used in configuration (`-180..180`).
+fn convert_from_grib_longitudes(longitude: Float) -> Float {
+    if longitude > 180.0 {
+        return longitude - 360.0;
+    }
+
+    longitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_buffered_shape, check_halo_size, compose_datetime, domain_corners,
+        domain_lonlat_extent, expand_edge_indices, generate_domain_projection,
+        grib_fixture::{ConstantField, GribFixture},
+        mask_missing_values, orient_grid_values, projection_info, read_message_values,
+        DomainExtent, ScanningMode,
+    };
+    use crate::model::configuration::Domain;
+    use chrono::NaiveDate;
+    use eccodes::{CodesHandle, FallibleIterator, ProductKind::GRIB};
+    use ndarray::{array, Array2, Array3};
+    use std::{env, fs, process};
+
+    /// Grid of shape `(3, 2)` in model orientation:
+    /// x-axis from west to east and y-axis from north to south.
+    fn expected_grid() -> Array2<f64> {
+        array![[1.0, 4.0], [2.0, 5.0], [3.0, 6.0]]
+    }
+
+    #[test]
+    fn default_scanning() {
+        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let grid = orient_grid_values(values, (3, 2), ScanningMode::default()).unwrap();
+
+        assert_eq!(grid, expected_grid());
+    }
+
+    #[test]
+    fn south_to_north_scanning() {
+        // southern row first
+        let values = vec![4.0, 5.0, 6.0, 1.0, 2.0, 3.0];
+        let scanning = ScanningMode {
+            j_positive: true,
+            ..ScanningMode::default()
+        };
+
+        let grid = orient_grid_values(values, (3, 2), scanning).unwrap();
+
+        assert_eq!(grid, expected_grid());
+    }
+
+    #[test]
+    fn east_to_west_scanning() {
+        let values = vec![3.0, 2.0, 1.0, 6.0, 5.0, 4.0];
+        let scanning = ScanningMode {
+            i_negative: true,
+            ..ScanningMode::default()
+        };
+
+        let grid = orient_grid_values(values, (3, 2), scanning).unwrap();
+
+        assert_eq!(grid, expected_grid());
+    }
+
+    #[test]
+    fn consecutive_meridians_scanning() {
+        // column-by-column, from south to north
+        let values = vec![4.0, 1.0, 5.0, 2.0, 6.0, 3.0];
+        let scanning = ScanningMode {
+            j_positive: true,
+            j_consecutive: true,
+            ..ScanningMode::default()
+        };
+
+        let grid = orient_grid_values(values, (3, 2), scanning).unwrap();
+
+        assert_eq!(grid, expected_grid());
+    }
+
+    #[test]
+    fn grib_datetime_composition() {
+        let datetime = compose_datetime(20210624, 600).unwrap();
+        assert_eq!(datetime.to_string(), "2021-06-24 06:00:00");
+
+        let datetime = compose_datetime(20211231, 2330).unwrap();
+        assert_eq!(datetime.to_string(), "2021-12-31 23:30:00");
+
+        assert!(compose_datetime(20210631, 0).is_err());
+        assert!(compose_datetime(20210624, 2400).is_err());
+    }
+
+    #[test]
+    fn margin_gridpoints_expansion() {
+        let distinct_lonlats = (vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0], vec![3.0, 2.0, 1.0, 0.0]);
+        let domain_edges = DomainExtent {
+            north: 1,
+            south: 2,
+            west: 2,
+            east: 3,
+        };
+
+        let expanded = expand_edge_indices(&distinct_lonlats, domain_edges, (2, 1)).unwrap();
+        assert_eq!(
+            expanded,
+            DomainExtent {
+                north: 0,
+                south: 3,
+                west: 0,
+                east: 5,
+            }
+        );
+
+        assert!(expand_edge_indices(&distinct_lonlats, domain_edges, (3, 1)).is_err());
+        assert!(expand_edge_indices(&distinct_lonlats, domain_edges, (2, 2)).is_err());
+    }
+
+    #[cfg(feature = "3d")]
+    #[test]
+    fn advection_margin_gridpoints() {
+        use super::advection_gridpoints;
+
+        // quarter-degree grid around the equator
+        let distinct_lonlats = (
+            (0..40).map(|i| f64::from(i) * 0.25).collect::<Vec<_>>(),
+            (0..40).rev().map(|i| f64::from(i) * 0.25 - 5.0).collect(),
+        );
+        let buffered_edges = DomainExtent {
+            north: 0,
+            south: 39,
+            west: 0,
+            east: 39,
+        };
+
+        // quarter of degree is around 28 km
+        assert_eq!(
+            advection_gridpoints(&distinct_lonlats, buffered_edges, 100_000.0),
+            (4, 4)
+        );
+        assert_eq!(
+            advection_gridpoints(&distinct_lonlats, buffered_edges, 0.0),
+            (2, 2)
+        );
+    }
+
+    #[test]
+    fn halo_size() {
+        let distinct_lonlats = (
+            (0..8).map(f64::from).collect::<Vec<_>>(),
+            (0..8).rev().map(f64::from).collect::<Vec<_>>(),
+        );
+        let domain_edges = DomainExtent {
+            north: 3,
+            south: 4,
+            west: 3,
+            east: 4,
+        };
+
+        let buffered_edges = DomainExtent {
+            north: 1,
+            south: 6,
+            west: 1,
+            east: 6,
+        };
+        assert!(check_halo_size(&distinct_lonlats, domain_edges, buffered_edges).is_ok());
+
+        let buffered_edges = DomainExtent {
+            north: 2,
+            ..buffered_edges
+        };
+        assert!(check_halo_size(&distinct_lonlats, domain_edges, buffered_edges).is_err());
+
+        // longitudes wrapping around the input grid edge
+        let domain_edges = DomainExtent {
+            west: 0,
+            east: 1,
+            ..domain_edges
+        };
+        let buffered_edges = DomainExtent {
+            north: 1,
+            south: 6,
+            west: 6,
+            east: 3,
+        };
+        assert!(check_halo_size(&distinct_lonlats, domain_edges, buffered_edges).is_ok());
+    }
+
+    #[test]
+    fn single_column_buffer() {
+        let height = Array3::<f32>::zeros((10, 1, 1));
+        assert!(check_buffered_shape(height.shape()).is_err());
+
+        let height = Array3::<f32>::zeros((1, 5, 5));
+        assert!(check_buffered_shape(height.shape()).is_err());
+
+        let height = Array3::<f32>::zeros((10, 2, 2));
+        assert!(check_buffered_shape(height.shape()).is_ok());
+    }
+
+    #[test]
+    fn projection_info_corners() {
+        let domain = Domain::new(20.0, 50.0, 1000.0, (101, 51)).unwrap();
+        let info = projection_info(&domain).unwrap();
+
+        // south-west corner is the reference point of the domain
+        let sw_corner = info.lines().find(|line| line.starts_with("SW:")).unwrap();
+        assert!(sw_corner.ends_with(", 20.000000, 50.000000"));
+        assert!(info.contains("lat_1: 50.000000"));
+    }
+
+    #[test]
+    fn bitmap_missing_values() {
+        // values at masked gridpoints are not compared with
+        // the missing value, as they can be any number
+        let mut values: Vec<f64> = vec![280.0, 9999.0, 281.5, 0.0];
+        mask_missing_values(&mut values, &[true, false, true, false], None);
+        assert!(values[1].is_nan() && values[3].is_nan());
+        assert_eq!((values[0], values[2]), (280.0, 281.5));
+
+        let mut values = vec![280.0, 9999.0];
+        mask_missing_values(&mut values, &[true, false], Some(0.0));
+        assert_eq!(values, [280.0, 0.0]);
+    }
+
+    #[test]
+    fn grib_fixture_bitmap() {
+        let out_dir = env::temp_dir().join(format!("pats_grib_bitmap_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let fixture = GribFixture {
+            lon_west: 19.0,
+            lat_north: 51.0,
+            step: 0.5,
+            shape: (3, 2),
+            datetime: NaiveDate::from_ymd_opt(2021, 7, 14)
+                .and_then(|date| date.and_hms_opt(12, 0, 0))
+                .unwrap(),
+        };
+
+        let field = ConstantField {
+            param_id: 167,
+            type_of_level: "surface",
+            level: 0,
+            value: 290.0,
+        };
+
+        // gridpoints are scanned from the north-west corner along rows
+        let path = out_dir.join("masked.grib");
+        fixture.write_with_bitmap(&path, field, &[1, 5]).unwrap();
+
+        let mut handle = CodesHandle::new_from_file(&path, GRIB).unwrap();
+        let msg = handle.next().unwrap().unwrap();
+
+        let values = read_message_values(&msg, fixture.shape, None).unwrap();
+        assert!(values[[1, 0]].is_nan() && values[[2, 1]].is_nan());
+        assert_eq!(values.iter().filter(|v| v.is_nan()).count(), 2);
+        assert!(values
+            .iter()
+            .filter(|v| !v.is_nan())
+            .all(|&v| (v - 290.0).abs() < 1.0e-3));
+
+        let values = read_message_values(&msg, fixture.shape, Some(0.0)).unwrap();
+        assert_eq!((values[[1, 0]], values[[2, 1]]), (0.0, 0.0));
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_domain_extent() {
+        let mut domain = Domain::new(20.0, 50.0, 1000.0, (101, 51)).unwrap();
+        domain.rotation_deg = Some(90.0);
+
+        let projection = generate_domain_projection(&domain).unwrap();
+        let corners = domain_corners(&domain, &projection)
+            .map(|(_, (x, y))| projection.inverse_project(x, y));
+
+        // reference point remains the SW corner, and the
+        // SE corner is north of it with x axis turned to the north
+        assert!((corners[0].0 - 20.0).abs() < 1e-6 && (corners[0].1 - 50.0).abs() < 1e-6);
+        assert!(corners[1].1 > 50.8 && (corners[1].0 - 20.0).abs() < 0.05);
+
+        let extent = domain_lonlat_extent(&domain, &projection);
+        assert!(extent.west < 19.4 && extent.north > 50.8);
+        assert!((extent.south - 50.0).abs() < 0.01 && (extent.east - 20.0).abs() < 0.01);
+    }
+}
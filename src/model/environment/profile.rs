@@ -0,0 +1,126 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module extracting the environmental profile
+//! at a single location, as seen by the model.
+//!
+//! Profile is sampled with the same interpolation which
+//! is used for parcels, so comparing it with the source sounding
+//! allows to verify buffering and interpolation of the fields.
+
+use super::{EnvFields, Environment, SurfaceFields};
+use crate::{
+    errors::{EnvironmentError, SearchError},
+    Float,
+};
+use serde::Serialize;
+
+/// Environment fields at a single level of the profile.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileLevel {
+    /// Input level (in units of the input level type), empty at the surface
+    pub level: Option<i64>,
+    pub height: Float,
+    pub pressure: Float,
+    pub temperature: Float,
+    pub virtual_temperature: Float,
+    pub specific_humidity: Float,
+    pub u_wind: Float,
+    pub v_wind: Float,
+}
+
+impl Environment {
+    /// Samples environment fields at given geographic coordinates,
+    /// at the surface and at heights of input levels above it.
+    ///
+    /// Levels below the surface and above the top
+    /// of surrounding columns are skipped.
+    pub fn column_profile(
+        &self,
+        lon: Float,
+        lat: Float,
+    ) -> Result<Vec<ProfileLevel>, EnvironmentError> {
+        let (x, y) = self.projection.try_project(lon, lat)?;
+        let surface_height = self.get_surface_value(x, y, SurfaceFields::Height)?;
+
+        let mut profile = vec![self.profile_level(x, y, surface_height, None)?];
+
+        for &level in self.levels() {
+            match self.height_of_pressure(x, y, self.level_pressure(level), surface_height) {
+                Ok(height) => profile.push(self.profile_level(x, y, height, Some(level))?),
+                Err(EnvironmentError::SearchUnable(SearchError::OutOfBounds)) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(profile)
+    }
+
+    /// Samples all profile fields at given (cartographic) coordinates.
+    fn profile_level(
+        &self,
+        x: Float,
+        y: Float,
+        z: Float,
+        level: Option<i64>,
+    ) -> Result<ProfileLevel, EnvironmentError> {
+        let field_at = |field| self.get_field_value(x, y, z, field);
+
+        Ok(ProfileLevel {
+            level,
+            height: z,
+            pressure: field_at(EnvFields::Pressure)?,
+            temperature: field_at(EnvFields::Temperature)?,
+            virtual_temperature: field_at(EnvFields::VirtualTemperature)?,
+            specific_humidity: field_at(EnvFields::SpecHumidity)?,
+            u_wind: field_at(EnvFields::UWind)?,
+            v_wind: field_at(EnvFields::VWind)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::accesser::tests::{analytic_env, temperature};
+    use crate::Float;
+
+    #[test]
+    fn analytic_column_profile() {
+        let (domain, env) = analytic_env((1, 1));
+
+        let column = env.column_profile(domain.ref_lon, domain.ref_lat).unwrap();
+
+        // surface and levels inside the column
+        assert!(column.len() > 20);
+        assert_eq!(column[0].level, None);
+        assert!(column[0].height.abs() < 1e-6);
+
+        for pair in column.windows(2) {
+            assert!(pair[1].height > pair[0].height);
+        }
+
+        for level in &column[1..] {
+            let level_pressure = level.level.unwrap() as Float * 100.0;
+
+            assert!((level.pressure - level_pressure).abs() < 1.0);
+            assert!((level.temperature - temperature(level.height)).abs() < 0.1);
+        }
+    }
+}
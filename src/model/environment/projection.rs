@@ -26,14 +26,25 @@ use crate::constants::{WGS84_A, WGS84_E};
 use crate::{errors::ProjectionError, Float};
 use float_cmp::approx_eq;
 use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::fmt;
 
 /// Front-facing struct of Lambert Conformal Conic projection.
+///
+/// Cartographic coordinates can be additionally rotated
+/// about a given point (see [`LambertConicConformal::with_rotation`]).
+///
+/// Projection is computed on WGS84 ellipsoid, or on any other
+/// ellipsoid or sphere (see [`LambertConicConformal::new_on_ellipsoid`]).
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default)]
 pub struct LambertConicConformal {
+    a: Float,
+    e: Float,
     lambda_0: Float,
     n: Float,
     big_f: Float,
     rho_0: Float,
+    rotation: Float,
+    center: (Float, Float),
 }
 
 impl LambertConicConformal {
@@ -41,6 +52,27 @@ impl LambertConicConformal {
     /// and two standard parallels.
     /// Defaults the reference latitude to 0.0
     pub fn new(lon_0: Float, lat_1: Float, lat_2: Float) -> Result<Self, ProjectionError> {
+        LambertConicConformal::new_on_ellipsoid(lon_0, lat_1, lat_2, WGS84_A, WGS84_E)
+    }
+
+    /// Same as [`LambertConicConformal::new`], but on the ellipsoid
+    /// with given semi-major axis `a` (in meters) and eccentricity `e`.
+    ///
+    /// With zero eccentricity the projection is computed on
+    /// the sphere with radius `a`.
+    pub fn new_on_ellipsoid(
+        lon_0: Float,
+        lat_1: Float,
+        lat_2: Float,
+        a: Float,
+        e: Float,
+    ) -> Result<Self, ProjectionError> {
+        if !(a > 0.0 && a.is_finite() && (0.0..1.0).contains(&e)) {
+            return Err(ProjectionError::IncorrectParams(
+                "ellipsoid parameters out of bounds",
+            ));
+        }
+
         if approx_eq!(Float, lat_1, lat_2) {
             return Err(ProjectionError::IncorrectParams(
                 "standard parallels cannot be equal",
@@ -67,34 +99,58 @@ impl LambertConicConformal {
         let phi_1 = lat_1.to_radians();
         let phi_2 = lat_2.to_radians();
 
-        let t_0 = t(phi_0);
-        let t_1 = t(phi_1);
-        let t_2 = t(phi_2);
-        let m_1 = m(phi_1);
-        let m_2 = m(phi_2);
+        let t_0 = t(phi_0, e);
+        let t_1 = t(phi_1, e);
+        let t_2 = t(phi_2, e);
+        let m_1 = m(phi_1, e);
+        let m_2 = m(phi_2, e);
 
         let n = n(m_1, m_2, t_1, t_2);
         let big_f = big_f(m_1, n, t_1);
-        let rho_0 = rho(big_f, t_0, n);
+        let rho_0 = rho(a, big_f, t_0, n);
 
         Ok(LambertConicConformal {
+            a,
+            e,
             lambda_0: lon_0.to_radians(),
             n,
             big_f,
             rho_0,
+            rotation: 0.0,
+            center: (0.0, 0.0),
         })
     }
 
+    /// Rotates cartographic coordinates by `rotation_deg` (in degrees)
+    /// counterclockwise about the `center` given in unrotated coordinates,
+    /// so that x axis of rotated coordinates is turned by that angle
+    /// from x axis of the projection.
+    pub fn with_rotation(mut self, rotation_deg: Float, center: (Float, Float)) -> Self {
+        self.rotation = rotation_deg.to_radians();
+        self.center = center;
+        self
+    }
+
     /// Function to project geographic coordinates
-    /// on WGS84 ellipsoid to cartographic coordinates
+    /// on the ellipsoid to cartographic coordinates
     /// with previously specified LCC projection.
     pub fn project(&self, lon: Float, lat: Float) -> (Float, Float) {
+        let (x, y) = self.project_unrotated(lon, lat);
+
+        self.rotate(x, y, -self.rotation)
+    }
+
+    /// Same as [`LambertConicConformal::project`], but ignores the rotation.
+    ///
+    /// Input gridpoints projected without rotation form nearly
+    /// rectangular cells, which is needed for interpolation.
+    pub fn project_unrotated(&self, lon: Float, lat: Float) -> (Float, Float) {
         let phi = lat.to_radians();
         let lambda = lon.to_radians();
 
-        let t = t(phi);
+        let t = t(phi, self.e);
         let theta = self.n * (lambda - self.lambda_0);
-        let rho = rho(self.big_f, t, self.n);
+        let rho = rho(self.a, self.big_f, t, self.n);
 
         let x = rho * theta.sin();
         let y = self.rho_0 - rho * theta.cos();
@@ -104,8 +160,9 @@ impl LambertConicConformal {
 
     /// Function to inversly project cartographic coordinates
     /// on specified LCC projection to geographic coordinates
-    /// on WGS84 ellipsoid.
+    /// on the ellipsoid.
     pub fn inverse_project(&self, x: Float, y: Float) -> (Float, Float) {
+        let (x, y) = self.unrotate(x, y);
         let rho = (self.n.signum()) * (x.powi(2) + (self.rho_0 - y).powi(2)).sqrt();
 
         let theta;
@@ -118,22 +175,137 @@ impl LambertConicConformal {
             theta = (x / (rho_0 - y)).atan();
         }
 
-        let t = (rho / (WGS84_A * self.big_f)).powf(1.0 / self.n);
+        let t = (rho / (self.a * self.big_f)).powf(1.0 / self.n);
 
         let lambda = (theta / self.n) + self.lambda_0;
-        let phi = phi_for_inverse(t);
+        let phi = phi_for_inverse(t, self.e);
 
         (lambda.to_degrees(), phi.to_degrees())
     }
+
+    /// Converts rotated cartographic coordinates to coordinates
+    /// returned by [`LambertConicConformal::project_unrotated`].
+    pub fn unrotate(&self, x: Float, y: Float) -> (Float, Float) {
+        self.rotate(x, y, self.rotation)
+    }
+
+    /// Rotates components of a vector (eg. wind) along axes
+    /// of the projection to components along rotated axes.
+    pub fn rotate_vector(&self, u: Float, v: Float) -> (Float, Float) {
+        if self.rotation == 0.0 {
+            return (u, v);
+        }
+
+        let (sin, cos) = self.rotation.sin_cos();
+
+        (u * cos + v * sin, v * cos - u * sin)
+    }
+
+    fn rotate(&self, x: Float, y: Float, angle: Float) -> (Float, Float) {
+        if angle == 0.0 {
+            return (x, y);
+        }
+
+        let (sin, cos) = angle.sin_cos();
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+
+        (
+            self.center.0 + dx * cos - dy * sin,
+            self.center.1 + dx * sin + dy * cos,
+        )
+    }
+
+    /// Computes the great-circle distance (in meters) on the Earth
+    /// between two points given in cartographic coordinates.
+    ///
+    /// Spherical Earth with the mean radius of the ellipsoid is assumed,
+    /// so the distance differs from the geodesic one by less than 0.5%.
+    pub fn ground_distance(&self, start: (Float, Float), end: (Float, Float)) -> Float {
+        let (lon_1, lat_1) = self.inverse_project(start.0, start.1);
+        let (lon_2, lat_2) = self.inverse_project(end.0, end.1);
+
+        let (phi_1, phi_2) = (lat_1.to_radians(), lat_2.to_radians());
+        let d_phi = phi_2 - phi_1;
+        let d_lambda = (lon_2 - lon_1).to_radians();
+
+        // haversine formula, accurate for small distances
+        let h = (d_phi / 2.0).sin().powi(2)
+            + phi_1.cos() * phi_2.cos() * (d_lambda / 2.0).sin().powi(2);
+
+        let mean_radius = self.a * (2.0 + (1.0 - self.e.powi(2)).sqrt()) / 3.0;
+
+        2.0 * mean_radius * h.sqrt().asin()
+    }
+
+    /// Same as [`LambertConicConformal::project`], but returns an error
+    /// instead of non-finite coordinates, eg. for points near the pole
+    /// on the opposite side of the projection cone apex.
+    pub fn try_project(&self, lon: Float, lat: Float) -> Result<(Float, Float), ProjectionError> {
+        if !lon.is_finite() || !(-90.0..=90.0).contains(&lat) {
+            return Err(ProjectionError::OutOfRange(
+                "geographic coordinates out of bounds",
+            ));
+        }
+
+        let (x, y) = self.project(lon, lat);
+
+        if !x.is_finite() || !y.is_finite() {
+            return Err(ProjectionError::OutOfRange(
+                "geographic coordinates cannot be projected",
+            ));
+        }
+
+        Ok((x, y))
+    }
+
+    /// Same as [`LambertConicConformal::inverse_project`], but returns
+    /// an error instead of non-finite coordinates, so that they do not
+    /// propagate into searches of the environment grid.
+    pub fn try_inverse_project(
+        &self,
+        x: Float,
+        y: Float,
+    ) -> Result<(Float, Float), ProjectionError> {
+        if !x.is_finite() || !y.is_finite() {
+            return Err(ProjectionError::OutOfRange(
+                "cartographic coordinates are not finite",
+            ));
+        }
+
+        let (lon, lat) = self.inverse_project(x, y);
+
+        if !lon.is_finite() || !lat.is_finite() {
+            return Err(ProjectionError::OutOfRange(
+                "cartographic coordinates cannot be inversely projected",
+            ));
+        }
+
+        Ok((lon, lat))
+    }
 }
 
-fn t(phi: Float) -> Float {
+impl fmt::Display for LambertConicConformal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "lon_0: {:.6}", self.lambda_0.to_degrees())?;
+        writeln!(f, "n: {:.9}", self.n)?;
+        writeln!(f, "big_f: {:.9}", self.big_f)?;
+        write!(f, "rho_0: {:.3}", self.rho_0)?;
+
+        if self.rotation != 0.0 {
+            write!(f, "\nrotation: {:.3}", self.rotation.to_degrees())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn t(phi: Float, e: Float) -> Float {
     ((FRAC_PI_4 - 0.5 * phi).tan())
-        / (((1.0 - WGS84_E * phi.sin()) / (1.0 + WGS84_E * phi.sin())).powf(WGS84_E / 2.0))
+        / (((1.0 - e * phi.sin()) / (1.0 + e * phi.sin())).powf(e / 2.0))
 }
 
-fn m(phi: Float) -> Float {
-    phi.cos() / (1.0 - (WGS84_E.powi(2) * (phi.sin()).powi(2))).sqrt()
+fn m(phi: Float, e: Float) -> Float {
+    phi.cos() / (1.0 - (e.powi(2) * (phi.sin()).powi(2))).sqrt()
 }
 
 fn n(m_1: Float, m_2: Float, t_1: Float, t_2: Float) -> Float {
@@ -144,29 +316,34 @@ fn big_f(m_1: Float, n: Float, t_1: Float) -> Float {
     m_1 / (n * t_1.powf(n))
 }
 
-fn rho(big_f: Float, t: Float, n: Float) -> Float {
-    WGS84_A * big_f * t.powf(n)
+fn rho(a: Float, big_f: Float, t: Float, n: Float) -> Float {
+    a * big_f * t.powf(n)
 }
 
 /// To compute the phi for inverse projection
 /// truncated infinite series is used with
 /// optimisations for reducing trigonometric
 /// functions calls.
-fn phi_for_inverse(t: Float) -> Float {
+///
+/// On the sphere the series vanishes and `phi` equals `chi`.
+fn phi_for_inverse(t: Float, e: Float) -> Float {
     let chi = FRAC_PI_2 - 2.0 * t.atan();
 
-    let big_a = (WGS84_E.powi(2) / 2.0)
-        + 5.0 * (WGS84_E.powi(4) / 24.0)
-        + (WGS84_E.powi(6) / 12.0)
-        + 13.0 * (WGS84_E.powi(8) / 360.0);
+    if e == 0.0 {
+        return chi;
+    }
 
-    let big_b = 7.0 * (WGS84_E.powi(4) / 48.0)
-        + 29.0 * (WGS84_E.powi(6) / 240.0)
-        + 811.0 * (WGS84_E.powi(8) / 11520.0);
+    let big_a = (e.powi(2) / 2.0)
+        + 5.0 * (e.powi(4) / 24.0)
+        + (e.powi(6) / 12.0)
+        + 13.0 * (e.powi(8) / 360.0);
 
-    let big_c = 7.0 * (WGS84_E.powi(6) / 120.0) + 81.0 * (WGS84_E.powi(8) / 1120.0);
+    let big_b =
+        7.0 * (e.powi(4) / 48.0) + 29.0 * (e.powi(6) / 240.0) + 811.0 * (e.powi(8) / 11520.0);
 
-    let big_d = 4279.0 * (WGS84_E.powi(8) / 161_280.0);
+    let big_c = 7.0 * (e.powi(6) / 120.0) + 81.0 * (e.powi(8) / 1120.0);
+
+    let big_d = 4279.0 * (e.powi(8) / 161_280.0);
 
     let a_prime = big_a - big_c;
     let b_prime = 2.0 * big_b - 4.0 * big_d;
@@ -183,6 +360,8 @@ fn phi_for_inverse(t: Float) -> Float {
 #[cfg(test)]
 mod tests {
     use super::LambertConicConformal;
+    use crate::Float;
+    use std::f64::consts::FRAC_PI_4;
 
     #[test]
     fn project() {
@@ -197,4 +376,91 @@ mod tests {
         assert!(xdiff < 0.000001);
         assert!(ydiff < 0.000001);
     }
+
+    #[test]
+    fn out_of_range_points() {
+        let proj = LambertConicConformal::new(18.0, 30.0, 60.0).unwrap();
+
+        let (x, y) = proj.try_project(18.5, 54.4).unwrap();
+        assert_eq!(
+            proj.try_inverse_project(x, y).unwrap(),
+            proj.inverse_project(x, y)
+        );
+
+        assert!(proj.try_project(18.0, 91.0).is_err());
+        assert!(proj.try_project(Float::NAN, 54.4).is_err());
+        assert!(proj.try_project(Float::INFINITY, 54.4).is_err());
+
+        assert!(proj.try_inverse_project(Float::NAN, y).is_err());
+        assert!(proj.try_inverse_project(x, Float::INFINITY).is_err());
+    }
+
+    #[test]
+    fn rotated_coordinates() {
+        let proj = LambertConicConformal::new(18.0, 30.0, 60.0).unwrap();
+        let center = proj.project(18.5, 54.4);
+        let rotated = proj.with_rotation(90.0, center);
+
+        // center is not moved by the rotation
+        let (x, y) = rotated.project(18.5, 54.4);
+        assert!((x - center.0).abs() < 1e-6 && (y - center.1).abs() < 1e-6);
+
+        // with x axis turned to the north, points east
+        // of the center are on the negative y axis
+        let (x_east, y_east) = proj.project(19.0, 54.4);
+        let (x, y) = rotated.project(19.0, 54.4);
+        assert!((x - (center.0 + (y_east - center.1))).abs() < 1e-6);
+        assert!((y - (center.1 - (x_east - center.0))).abs() < 1e-6);
+
+        let (lon, lat) = rotated.inverse_project(x, y);
+        assert!((lon - 19.0).abs() < 1e-6 && (lat - 54.4).abs() < 1e-6);
+
+        let (x, y) = rotated.unrotate(x, y);
+        assert!((x - x_east).abs() < 1e-6 && (y - y_east).abs() < 1e-6);
+
+        let (u, v) = rotated.rotate_vector(10.0, 0.0);
+        assert!(u.abs() < 1e-9 && (v + 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn spherical_projection() {
+        let radius = 6_371_229.0;
+        let proj = LambertConicConformal::new_on_ellipsoid(18.0, 30.0, 60.0, radius, 0.0).unwrap();
+
+        // closed-form spherical formulas (Snyder, eq. 15-1 to 15-3)
+        let (phi_1, phi_2) = (Float::to_radians(30.0), Float::to_radians(60.0));
+        let tan_half = |phi: Float| (FRAC_PI_4 + phi / 2.0).tan();
+        let n = (phi_1.cos() / phi_2.cos()).ln() / (tan_half(phi_2) / tan_half(phi_1)).ln();
+        let big_f = phi_1.cos() * tan_half(phi_1).powf(n) / n;
+        let rho_0 = radius * big_f;
+
+        let (lon, lat): (Float, Float) = (19.5, 54.4);
+        let rho = radius * big_f / tan_half(lat.to_radians()).powf(n);
+        let theta = n * Float::to_radians(lon - 18.0);
+
+        let (x, y) = proj.project(lon, lat);
+        assert!((x - rho * theta.sin()).abs() < 1e-6);
+        assert!((y - (rho_0 - rho * theta.cos())).abs() < 1e-6);
+
+        let (lon_inv, lat_inv) = proj.inverse_project(x, y);
+        assert!((lon_inv - lon).abs() < 1e-9 && (lat_inv - lat).abs() < 1e-9);
+
+        assert!(LambertConicConformal::new_on_ellipsoid(18.0, 30.0, 60.0, radius, 1.0).is_err());
+    }
+
+    #[test]
+    fn ground_distance() {
+        let proj = LambertConicConformal::new(18.0, 30.0, 60.0).unwrap();
+
+        // one degree along the meridian is about 111 km
+        let start = proj.project(18.0, 54.0);
+        let end = proj.project(18.0, 55.0);
+        assert!((proj.ground_distance(start, end) - 111_200.0).abs() < 500.0);
+
+        // between standard parallels distances on the projection
+        // plane are shorter than on the ground
+        let (x, y) = proj.project(18.0, 45.0);
+        let distance = proj.ground_distance((x, y), (x + 10_000.0, y));
+        assert!(distance > 10_000.0 && distance < 10_500.0);
+    }
 }
@@ -23,34 +23,38 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 use crate::model::{configuration, LonLat};
 use crate::{
     errors::{EnvironmentError, InputError},
-    model::{configuration::Input, environment::DomainExtent},
+    model::{
+        configuration::Input,
+        environment::{
+            cast_to_storage, check_valid_time, read_message_values, DomainExtent, FieldFloat,
+        },
+    },
     Float,
 };
 use eccodes::{CodesHandle, FallibleIterator, ProductKind::GRIB};
-use eccodes::{
-    KeyType::{FloatArray, Str},
-    KeyedMessage,
-};
+use eccodes::{KeyType::Str, KeyedMessage};
 use floccus::constants::G;
-use log::debug;
+use log::{debug, warn};
 use ndarray::{concatenate, s, stack, Array, Array2, Axis};
 
 /// Struct for storing environmental variables at/near surface.
 ///
 /// To limit IO operations and reduce performance overhead
 /// of the model surface data is stored in the
-/// memory as 2D arrays.
+/// memory as 2D arrays with [`FieldFloat`] precision.
+///
+/// Surface wind is zero when `3d` feature is disabled.
 #[derive(Debug)]
 pub struct Surfaces {
     pub lons: Array2<Float>,
     pub lats: Array2<Float>,
 
-    pub temperature: Array2<Float>,
-    pub dewpoint: Array2<Float>,
-    pub pressure: Array2<Float>,
-    pub height: Array2<Float>,
-    pub u_wind: Array2<Float>,
-    pub v_wind: Array2<Float>,
+    pub temperature: Array2<FieldFloat>,
+    pub dewpoint: Array2<FieldFloat>,
+    pub pressure: Array2<FieldFloat>,
+    pub height: Array2<FieldFloat>,
+    pub u_wind: Array2<FieldFloat>,
+    pub v_wind: Array2<FieldFloat>,
 }
 
 impl Surfaces {
@@ -70,20 +74,20 @@ impl Surfaces {
 /// (Why it is neccessary)
 fn collect(input: &configuration::Input) -> Result<Vec<KeyedMessage>, InputError> {
     let mut data_levels: Vec<KeyedMessage> = vec![];
+    let short_names = input.variable_names.surfaces_names();
 
     for file in &input.data_files {
         let handle = CodesHandle::new_from_file(file, GRIB)?;
 
         let mut data: Vec<KeyedMessage> = handle
             .filter(|msg| {
+                let short_name = msg.read_key("shortName")?.value;
+
                 Ok(
                     msg.read_key("typeOfLevel")?.value == Str("surface".to_string())
-                        && (msg.read_key("shortName")?.value == Str("10u".to_string())
-                            || msg.read_key("shortName")?.value == Str("10v".to_string())
-                            || msg.read_key("shortName")?.value == Str("2t".to_string())
-                            || msg.read_key("shortName")?.value == Str("2d".to_string())
-                            || msg.read_key("shortName")?.value == Str("sp".to_string())
-                            || msg.read_key("shortName")?.value == Str("z".to_string())),
+                        && short_names
+                            .iter()
+                            .any(|name| short_name == Str((*name).to_string())),
                 )
             })
             .collect()?;
@@ -97,6 +101,8 @@ fn collect(input: &configuration::Input) -> Result<Vec<KeyedMessage>, InputError
         ));
     }
 
+    check_valid_time(&data_levels, input.valid_time)?;
+
     Ok(data_levels)
 }
 
@@ -157,25 +163,65 @@ fn assign_surfaces(
     domain_edges: DomainExtent<usize>,
     coords: LonLat<Array2<Float>>,
 ) -> Result<Surfaces, InputError> {
-    let input_shape = input.shape;
-
-    let geopotential = read_raw_surface("z", input_shape, data)?;
-    let height = truncate_surface_to_extent(&geopotential, domain_edges).mapv(|v| v / G);
-
-    let pressure = read_raw_surface("sp", input_shape, data)?;
-    let pressure = truncate_surface_to_extent(&pressure, domain_edges);
-
-    let temperature = read_raw_surface("2t", input_shape, data)?;
-    let temperature = truncate_surface_to_extent(&temperature, domain_edges);
-
-    let dewpoint = read_raw_surface("2d", input_shape, data)?;
-    let dewpoint = truncate_surface_to_extent(&dewpoint, domain_edges);
-
-    let u_wind = read_raw_surface("10u", input_shape, data)?;
-    let u_wind = truncate_surface_to_extent(&u_wind, domain_edges);
-
-    let v_wind = read_raw_surface("10v", input_shape, data)?;
-    let v_wind = truncate_surface_to_extent(&v_wind, domain_edges);
+    let names = &input.variable_names;
+
+    // fields are cast to the storage precision right after they are
+    // read, so that raw fields are not buffered in full precision at once
+    let height = read_truncated_surface(&names.surface_geopotential, input, data, domain_edges)?
+        .mapv_into(|v| v / G);
+    let height = cast_to_storage(height);
+
+    let pressure = cast_to_storage(read_truncated_surface(
+        &names.surface_pressure,
+        input,
+        data,
+        domain_edges,
+    )?);
+    let temperature = cast_to_storage(read_truncated_surface(
+        &names.temperature_2m,
+        input,
+        data,
+        domain_edges,
+    )?);
+    let dewpoint = cast_to_storage(read_truncated_surface(
+        &names.dewpoint_2m,
+        input,
+        data,
+        domain_edges,
+    )?);
+
+    // without horizontal advection parcel does not move
+    // horizontally, so surface wind is not required in input
+    let (u_wind, v_wind) = if cfg!(feature = "3d") {
+        let u_wind = read_raw_surface(&names.u_wind_10m, input, data);
+        let v_wind = read_raw_surface(&names.v_wind_10m, input, data);
+
+        match (u_wind, v_wind) {
+            (Ok(u_wind), Ok(v_wind)) => (
+                truncate_surface_to_extent(&u_wind, domain_edges),
+                truncate_surface_to_extent(&v_wind, domain_edges),
+            ),
+            // parcels released from the surface can use wind from the lowest
+            // level instead, but terrain-following columns need surface wind
+            (Err(InputError::DataNotSufficient(_)), _)
+            | (_, Err(InputError::DataNotSufficient(_)))
+                if !input.terrain_following =>
+            {
+                warn!(
+                    "Surface wind ({}, {}) not found in input data, wind at the release height is used for parcels released from the surface",
+                    names.u_wind_10m, names.v_wind_10m
+                );
+
+                (
+                    Array2::from_elem(height.dim(), Float::NAN),
+                    Array2::from_elem(height.dim(), Float::NAN),
+                )
+            }
+            (Err(err), _) | (_, Err(err)) => return Err(err),
+        }
+    } else {
+        (Array2::zeros(height.dim()), Array2::zeros(height.dim()))
+    };
 
     Ok(Surfaces {
         lons: coords.0,
@@ -184,8 +230,8 @@ fn assign_surfaces(
         dewpoint,
         pressure,
         height,
-        u_wind,
-        v_wind,
+        u_wind: cast_to_storage(u_wind),
+        v_wind: cast_to_storage(v_wind),
     })
 }
 
@@ -193,7 +239,7 @@ fn assign_surfaces(
 /// of variable with given `short_name`.
 fn read_raw_surface(
     short_name: &str,
-    shape: (usize, usize),
+    input: &Input,
     data: &[KeyedMessage],
 ) -> Result<Array2<Float>, InputError> {
     let mut data_level = None;
@@ -211,28 +257,26 @@ fn read_raw_surface(
         ));
     }
 
-    let data_level = data_level.unwrap();
-    let data_level = data_level.read_key("values")?.value;
-    let data_level = if let FloatArray(v) = data_level {
-        v
-    } else {
-        return Err(InputError::IncorrectKeyType("values"));
-    };
-
-    // a bit of magic
-    // data values in GRIB are a vec of values row-by-row (x-axis is in WE direction)
-    // we want a Array2 of provided `shape` with x-axis in WE direction
-    // but from_shape_vec(final_shape, data) splits the data into final_shape.1 long chunks
-    // and puts them in columns
-    // so we need to correctly split the data in GRIB vector into Array2 and then transpose
-    // that array to get axes along expected geographical directions
-    let result_data = Array2::from_shape_vec((shape.1, shape.0), data_level)?;
-    let result_data = result_data.reversed_axes();
-    let result_data = result_data.mapv(|v| v as Float);
+    let result_data =
+        read_message_values(data_level.unwrap(), input.shape, input.missing_value_fill)?;
 
     Ok(result_data)
 }
 
+/// Reads surface variable with given `short_name` and truncates
+/// it to the domain + margins extent, so that the raw field
+/// covering the whole GRIB grid is dropped right away.
+fn read_truncated_surface(
+    short_name: &str,
+    input: &Input,
+    data: &[KeyedMessage],
+    domain_edges: DomainExtent<usize>,
+) -> Result<Array2<Float>, InputError> {
+    let raw_field = read_raw_surface(short_name, input, data)?;
+
+    Ok(truncate_surface_to_extent(&raw_field, domain_edges))
+}
+
 /// Truncates surface data array from GRIB file to
 /// cover only the domain + margins extent.
 fn truncate_surface_to_extent(
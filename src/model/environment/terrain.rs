@@ -0,0 +1,122 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module handling terrain-following
+//! lower boundary of the environment.
+
+use super::{cast_to_storage, fields::Fields, surfaces::Surfaces, FieldFloat};
+use crate::{errors::InputError, Float};
+use floccus::{mixing_ratio, virtual_temperature};
+use ndarray::{s, Array2};
+
+/// Struct storing additional data needed to use surface
+/// as the lower boundary of environment columns.
+///
+/// Levels below the surface are skipped when accessing fields,
+/// and fields between the surface and the lowest level above it
+/// are interpolated from surface values.
+#[derive(Debug)]
+pub struct TerrainBoundary {
+    /// Index of the lowest field level above the surface in each column.
+    pub lowest_levels: Array2<usize>,
+
+    /// Surface virtual temperature, as it is not provided in input.
+    pub virtual_temp: Array2<FieldFloat>,
+
+    /// Surface specific humidity, computed from 2 metre dewpoint.
+    pub spec_humidity: Array2<FieldFloat>,
+}
+
+impl TerrainBoundary {
+    pub(super) fn new(fields: &Fields, surfaces: &Surfaces) -> Result<Self, InputError> {
+        let lowest_levels = find_lowest_levels(fields, surfaces)?;
+        let (virtual_temp, spec_humidity) = compute_surface_moisture(surfaces)?;
+
+        Ok(TerrainBoundary {
+            lowest_levels,
+            virtual_temp: cast_to_storage(virtual_temp),
+            spec_humidity: cast_to_storage(spec_humidity),
+        })
+    }
+}
+
+/// Finds the lowest level above the surface in each column.
+///
+/// At least two levels above the surface are required,
+/// so that the vertical search in the column is possible.
+fn find_lowest_levels(fields: &Fields, surfaces: &Surfaces) -> Result<Array2<usize>, InputError> {
+    let levels_count = fields.height.shape()[0];
+    let mut lowest_levels = Array2::zeros(surfaces.height.raw_dim());
+
+    for ((x, y), lowest_level) in lowest_levels.indexed_iter_mut() {
+        let surface_height = surfaces.height[[x, y]];
+
+        *lowest_level = fields
+            .height
+            .slice(s![.., x, y])
+            .iter()
+            .position(|&z| z > surface_height)
+            .unwrap_or(levels_count);
+
+        if *lowest_level + 1 >= levels_count {
+            return Err(InputError::DataNotSufficient(
+                "Less than two levels above the surface, check your input data",
+            ));
+        }
+    }
+
+    Ok(lowest_levels)
+}
+
+/// Computes virtual temperature and specific humidity
+/// at the surface from 2 metre temperature and dewpoint.
+fn compute_surface_moisture(
+    surfaces: &Surfaces,
+) -> Result<(Array2<Float>, Array2<Float>), InputError> {
+    let mut virtual_temp = Array2::zeros(surfaces.height.raw_dim());
+    let mut spec_humidity = Array2::zeros(surfaces.height.raw_dim());
+
+    for ((x, y), tv) in virtual_temp.indexed_iter_mut() {
+        let temperature = surfaces.temperature[[x, y]] as Float;
+        let pressure = surfaces.pressure[[x, y]] as Float;
+        let dewpoint = surfaces.dewpoint[[x, y]] as Float;
+
+        // gridpoints with missing input data are left as NaN
+        if temperature.is_nan() || pressure.is_nan() || dewpoint.is_nan() {
+            *tv = Float::NAN;
+            spec_humidity[[x, y]] = Float::NAN;
+            continue;
+        }
+
+        let unreasonable = |source| InputError::UnreasonableGridpoint {
+            lon: surfaces.lons[[x, y]],
+            lat: surfaces.lats[[x, y]],
+            pressure,
+            temperature,
+            spec_humidity: Float::NAN,
+            source,
+        };
+
+        let mxng_rto = mixing_ratio::accuracy1(dewpoint, pressure).map_err(unreasonable)?;
+        *tv = virtual_temperature::general1(temperature, mxng_rto).map_err(unreasonable)?;
+        spec_humidity[[x, y]] = mxng_rto / (1.0 + mxng_rto);
+    }
+
+    Ok((virtual_temp, spec_humidity))
+}
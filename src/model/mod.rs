@@ -20,52 +20,170 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! Module containing the actual model code.
 //! Whole documentation of how the model works is provided here.
 
-mod configuration;
+mod aggregate;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+pub mod configuration;
+mod csv_format;
 mod environment;
+#[cfg(feature = "parquet")]
+mod output;
 mod parcel;
+pub mod sounding;
+mod summary;
 mod vec3;
 
 #[cfg(test)]
 mod super_tests;
 
-use crate::model::parcel::conv_params::ConvectiveParams;
+pub(crate) use self::environment::FieldFloat;
+pub use self::parcel::conv_params::ConvectiveParams;
+
+use crate::model::parcel::logger::{construct_parcel_id, TrajectoryWriter};
+use crate::model::summary::{save_run_summary, RunStats};
 use crate::{
-    errors::ModelError,
-    model::{configuration::Config, environment::Environment},
+    cli::Cli,
+    errors::{EnvironmentError, ModelError, ParcelError},
+    model::{
+        configuration::{Config, Output, OutputFormat, OutputMode},
+        environment::{Environment, SurfaceFields},
+    },
     Float, ALLOCATOR,
 };
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use ndarray::Array1;
-use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use std::{
-    fs,
-    io::Error,
+    any::Any,
+    fs, io,
+    panic::{self, AssertUnwindSafe},
     path::Path,
-    sync::{mpsc, Arc},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 /// Convenience type to store lon-lat coordinates.
 type LonLat<T> = (T, T);
 
+/// Grid indices and projected coordinates of parcel release point.
+type ReleasePoint = ((usize, usize), (Float, Float));
+
+/// Flag set when the user interrupts the model (eg. with Ctrl-C).
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of parcels above which saving trajectories
+/// is considered expensive and the user is warned.
+const LARGE_PARCELS_COUNT: usize = 10_000;
+
+/// Minimal number of parcels simulated by a worker as one piece of work,
+/// so that scheduling overhead stays small for large domains.
+const PARCELS_CHUNK_SIZE: usize = 16;
+
+/// Number of received parcels after which the fraction
+/// of failed parcels is checked against the configured maximum.
+const FAILURE_CHECK_INTERVAL: usize = 100;
+
+/// Relative difference between ground distance of adjacent release
+/// points and the configured spacing above which the user is warned.
+const SPACING_TOLERANCE: Float = 0.03;
+
+/// Reads the configuration and describes the projection
+/// generated for the domain, without running the model.
+pub fn projection_info(cli: &Cli) -> Result<String, ModelError> {
+    let config = Config::new_from_file(&cli.config)?;
+    let info = environment::projection_info(&config.domain)?;
+
+    Ok(info)
+}
+
+/// Reads the configuration and input data, and writes
+/// environmental profile at given location to standard output as CSV.
+///
+/// Useful for verifying buffering and interpolation
+/// of input data without running the model.
+pub fn column_profile(cli: &Cli, lon: Float, lat: Float) -> Result<(), ModelError> {
+    let model_core = Core::new(cli)?;
+    let profile = model_core.environ.column_profile(lon, lat)?;
+
+    let mut writer = csv::Writer::from_writer(io::stdout());
+
+    for level in profile {
+        writer.serialize(level)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
 /// Main model function, responsible for all simulation steps.
 ///
 /// It reads the provided configuration and input data
 /// and then deploys parcels within the domain onto the threadpool
 /// and checks for errors.
-pub fn main() -> Result<(), ModelError> {
+pub fn main(cli: &Cli) -> Result<(), ModelError> {
+    let start_time = Instant::now();
     info!("Preparing the model core");
 
     // prepare all prerequisites for running the model
-    prepare_output_dir()?;
+    let out_dir = Path::new("./output/");
+    prepare_output_dir(out_dir)?;
+    set_interrupt_handler()?;
 
-    let model_core = Core::new()?;
+    let model_core = Core::new(cli)?;
+
+    if cli.dump_environment {
+        info!("Writing buffered environment");
+        model_core.environ.dump(&out_dir.join("environment"))?;
+    }
+
+    run(model_core, out_dir, start_time)
+}
 
-    let parcels = prepare_parcels_list(&model_core);
+/// Deploys parcels within the domain onto the threadpool of
+/// prepared model core and writes the output into `out_dir`.
+///
+/// `start_time` is the start of the whole model run, reported
+/// in the run summary.
+fn run(model_core: Core, out_dir: &Path, start_time: Instant) -> Result<(), ModelError> {
+    // with perturbations each point releases
+    // a parcel for every ensemble member
+    let members: Vec<Option<usize>> = match &model_core.config.parcel.perturbation {
+        Some(perturbation) => (0..perturbation.members).map(Some).collect(),
+        None => vec![None],
+    };
+
+    let parcels: Vec<(ReleasePoint, Option<usize>)> = prepare_parcels_list(&model_core)?
+        .into_iter()
+        .flat_map(|point| members.iter().map(move |&member| (point, member)))
+        .collect();
     let parcels_count = parcels.len();
 
-    let mut parcels_params: Vec<ConvectiveParams> = Vec::with_capacity(parcels_count);
+    let output = &model_core.config.output;
+
+    if output.enabled && output.save_trajectories && parcels_count > LARGE_PARCELS_COUNT {
+        warn!(
+            "Saving trajectories is enabled for {} parcels, which will create as many output files and can take a lot of disk space and time",
+            parcels_count
+        );
+    }
+
+    // parameters are stored with parcel index in the list
+    // to write them in the same order regardless of threads scheduling
+    let mut parcels_params: Vec<(usize, ConvectiveParams)> = Vec::with_capacity(parcels_count);
+    let mut run_stats = RunStats::default();
 
+    // buffered environment usually takes most of the memory
+    run_stats.sample_memory();
+
+    let trajectory_writer = Arc::new(TrajectoryWriter::new(&model_core.config.output, out_dir)?);
     let config = Arc::new(model_core.config);
     let environment = Arc::new(model_core.environ);
 
@@ -80,43 +198,230 @@ pub fn main() -> Result<(), ModelError> {
     );
     parcels_bar.set_prefix("Simulated parcels");
 
-    // deploy parcels on to the threadpool
+    // deploy parcels on to the threadpool, in chunks split between
+    // workers by the parallel iterator; only the deploying task holds
+    // senders, so the channel disconnects if it stops without sending results
     let (tx, rx) = mpsc::channel();
 
-    for parcel_coords in parcels {
-        let tx = tx.clone();
+    // set when too many parcels failed, to skip parcels still waiting in the queue
+    let aborted = Arc::new(AtomicBool::new(false));
+
+    {
         let config = Arc::clone(&config);
         let environment = Arc::clone(&environment);
+        let trajectory_writer = Arc::clone(&trajectory_writer);
+        let aborted = Arc::clone(&aborted);
 
         model_core.threadpool.spawn(move || {
-            tx.send(parcel::deploy(parcel_coords, &config, &environment))
-                .unwrap();
+            parcels
+                .into_par_iter()
+                .enumerate()
+                .with_min_len(PARCELS_CHUNK_SIZE)
+                .for_each_with(
+                    tx,
+                    |tx, (parcel_index, ((grid_index, parcel_coords), member))| {
+                        // parcels still waiting in the queue are skipped
+                        // when the model is interrupted or aborted
+                        if INTERRUPTED.load(Ordering::SeqCst) || aborted.load(Ordering::SeqCst) {
+                            return;
+                        }
+
+                        // panic in a single parcel is reported as its error,
+                        // so that it does not stop simulation of the whole domain
+                        let parcel_result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            parcel::deploy(
+                                parcel_coords,
+                                grid_index,
+                                member,
+                                &config,
+                                &environment,
+                                Some(trajectory_writer.as_ref()),
+                            )
+                        }))
+                        .unwrap_or_else(|payload| {
+                            Err(ParcelError::Panicked(panic_message(payload.as_ref())))
+                        });
+
+                        // the receiver is dropped when model is interrupted
+                        // so results of parcels that finish later are discarded
+                        tx.send((
+                            parcel_index,
+                            grid_index,
+                            member,
+                            parcel_coords,
+                            parcel_result,
+                        ))
+                        .ok();
+                    },
+                );
         });
     }
 
     // receive parcels status and computed convective parameters
-    for _ in 0..parcels_count {
-        let parcel_result = rx.recv().expect("Receiving parcel result failed");
+    let mut received_count = 0;
+
+    while received_count < parcels_count {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // receiving with timeout to regularly check for interruption
+        let (parcel_index, grid_index, member, parcel_coords, parcel_result) =
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    error!(
+                        "Results of {} parcels were not received, as all workers stopped",
+                        parcels_count - received_count
+                    );
+                    break;
+                }
+            };
+
+        received_count += 1;
 
         match parcel_result {
             Ok(params) => {
-                parcels_params.push(params);
+                run_stats.add_succeeded(&params);
+                parcels_params.push((parcel_index, params));
             }
             Err(err) => {
                 error!("Parcel simulation handling failed due to an error, check the details and rerun the model: {}", err);
                 // this is neccessary to make sure that all error messages
                 // are fully written to stdout before the progress bar updates
                 println!();
+
+                // failed parcels are also written to the output
+                // so that the output grid is complete
+                let (lon, lat) = environment
+                    .projection
+                    .inverse_project(parcel_coords.0, parcel_coords.1);
+                run_stats.add_failed(err.code());
+                let mut failed_params = ConvectiveParams::new_failed(lon, lat, err.code());
+
+                if config.output.edge_distance {
+                    failed_params.set_edge_distance(config.domain.edge_distance(grid_index));
+                }
+
+                failed_params.set_member(member);
+
+                let parcel_id = construct_parcel_id(
+                    grid_index,
+                    member,
+                    parcel_coords,
+                    config.datetime.start,
+                    &environment,
+                );
+                trajectory_writer.save_untraced_params(&parcel_id, &failed_params)?;
+
+                parcels_params.push((parcel_index, failed_params));
             }
         }
         parcels_bar.inc(1);
+
+        // fundamentally broken configuration makes most parcels fail,
+        // so the run is stopped instead of producing mostly empty output
+        if received_count % FAILURE_CHECK_INTERVAL == 0
+            && run_stats.failed_fraction() > config.run.max_failure_fraction
+        {
+            aborted.store(true, Ordering::SeqCst);
+            break;
+        }
     }
 
-    parcels_bar.finish_with_message("All parcels finished");
-    info!("Writing output");
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+
+    // parcels received after the last periodic check (or all parcels
+    // of runs with fewer parcels than the interval) are also checked
+    if !interrupted && run_stats.failed_fraction() > config.run.max_failure_fraction {
+        aborted.store(true, Ordering::SeqCst);
+    }
+
+    let run_aborted = aborted.load(Ordering::SeqCst);
+    let finished_count = parcels_params.len();
+
+    if interrupted {
+        parcels_bar.abandon_with_message("Interrupted");
+        warn!("Model interrupted, writing output of finished parcels");
+    } else if run_aborted {
+        parcels_bar.abandon_with_message("Aborted");
+        error!(
+            "{:.0}% of received parcels failed, which is more than the configured maximum, writing output of finished parcels",
+            run_stats.failed_fraction() * 100.0
+        );
+    } else {
+        parcels_bar.finish_with_message("All parcels finished");
+        info!("Writing output");
+    }
+
+    // parcels finish in random order, so they are sorted
+    // to keep the output identical between runs
+    parcels_params.sort_unstable_by_key(|(parcel_index, _)| *parcel_index);
+    let parcels_params = parcels_params
+        .into_iter()
+        .map(|(_, params)| params)
+        .collect();
 
     //write convective parameters to file
-    save_conv_params(parcels_params)?;
+    if config.output.enabled {
+        save_conv_params(parcels_params, &config.output, out_dir)?;
+    }
+
+    trajectory_writer.finish()?;
+    run_stats.sample_memory();
+
+    save_run_summary(
+        &run_stats,
+        start_time.elapsed(),
+        interrupted,
+        run_aborted,
+        &config,
+        &out_dir.join("run_summary.json"),
+    )?;
+
+    if interrupted {
+        return Err(ModelError::Interrupted(finished_count));
+    }
+
+    if run_aborted {
+        return Err(ModelError::TooManyFailures {
+            failed: run_stats.failed(),
+            received: received_count,
+            dominant: run_stats
+                .dominant_failure()
+                .expect("Aborted run always has failed parcels"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Extracts the message of caught panic, which payload
+/// is a string when panic was raised with a message.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return (*message).to_string();
+    }
+
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown panic payload".to_string()
+}
+
+/// Sets the handler of interrupt signal (Ctrl-C), which
+/// stops the simulation so that output of already finished
+/// parcels can be saved.
+///
+/// Second interrupt stops the model immediately.
+fn set_interrupt_handler() -> Result<(), ModelError> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })?;
 
     Ok(())
 }
@@ -138,23 +443,61 @@ impl Core {
     /// Before the simulation can start (and to run it safely),
     /// configuration and input data provided by the user must be
     /// loaded and checked.
-    pub fn new() -> Result<Self, ModelError> {
-        debug!("Reading configuration from config.yaml");
-        let config = Config::new_from_file(Path::new("config.yaml"))?;
+    pub fn new(cli: &Cli) -> Result<Self, ModelError> {
+        debug!("Reading configuration from {}", cli.config.display());
+        let mut config = Config::new_from_file(&cli.config)?;
+
+        // resources set in command line take precedence over configuration
+        if let Some(threads) = cli.threads {
+            config.resources.threads = threads;
+        }
+
+        if let Some(memory) = cli.memory_mb {
+            config.resources.memory = Some(memory);
+        }
+
+        Core::from_config(config)
+    }
+
+    /// Model [`Core`] constructor from already read configuration.
+    ///
+    /// Sets the memory limit and reads input data.
+    fn from_config(mut config: Config) -> Result<Self, ModelError> {
+        config.resources.check_bounds()?;
+        let memory_limit = config.resources.memory_limit();
+
+        // resolved limit is kept in the configuration,
+        // so that the run summary reports the effective value
+        config.resources.memory = Some(memory_limit);
+
+        info!(
+            "Using {} threads ({} MB stack) with {} MB memory limit",
+            config.resources.threads, config.resources.stack_size_mb, memory_limit
+        );
 
         debug!("Setting memory limit");
-        ALLOCATOR
-            .set_limit(config.resources.memory * 1024 * 1024)
-            .unwrap();
+        ALLOCATOR.set_limit(memory_limit * 1024 * 1024).unwrap();
 
         debug!("Setting up ThreadPool");
         let threadpool = ThreadPoolBuilder::new()
             .num_threads(config.resources.threads as usize)
-            .stack_size(2 * 1024 * 1024)
+            .stack_size(config.resources.stack_size_mb * 1024 * 1024)
             .build()?;
 
         debug!("Reading environmental boundary conditions from GRIB");
-        let environ = Environment::new(&config)?;
+        // environment is constructed inside the threadpool so that
+        // parallel computations while buffering use the configured threads
+        let environ = threadpool.install(|| Environment::new(&config))?;
+
+        let extent = environ.buffered_extent();
+        info!(
+            "Buffered environment on {} levels in extent N{:.2}<->{:.2} E{:.2}<->{:.2}",
+            environ.levels().len(),
+            extent.south,
+            extent.north,
+            extent.west,
+            extent.east
+        );
 
         Ok(Core {
             config,
@@ -167,11 +510,9 @@ impl Core {
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
-fn prepare_output_dir() -> Result<(), ModelError> {
+fn prepare_output_dir(out_path: &Path) -> Result<(), ModelError> {
     debug!("Checking and setting output directory");
 
-    let out_path = Path::new("./output/");
-
     if out_path.is_dir() {
         if out_path.read_dir()?.next().is_none() {
             debug!("Output directory exists but is empty so continuing");
@@ -193,12 +534,21 @@ fn prepare_output_dir() -> Result<(), ModelError> {
 ///
 /// In configuration only south-west corner of the domain is provided.
 /// Thus it is neccessary to compute the starting position of each parcel.
-fn prepare_parcels_list(model_core: &Core) -> Vec<(Float, Float)> {
+///
+/// When release points are listed in configuration, they are used instead.
+fn prepare_parcels_list(model_core: &Core) -> Result<Vec<ReleasePoint>, ModelError> {
+    if let Some(points) = &model_core.config.domain.points {
+        return prepare_points_list(&model_core.environ, points);
+    }
+
     let domain_anchor = model_core.environ.projection.project(
         model_core.config.domain.ref_lon,
         model_core.config.domain.ref_lat,
     );
 
+    let stagger = model_core.config.domain.stagger;
+    let domain_anchor = (domain_anchor.0 + stagger.0, domain_anchor.1 + stagger.1);
+
     let x_coords = Array1::linspace(
         domain_anchor.0,
         domain_anchor.0
@@ -217,30 +567,222 @@ fn prepare_parcels_list(model_core: &Core) -> Vec<(Float, Float)> {
     )
     .to_vec();
 
+    check_release_spacing(
+        &model_core.environ,
+        &x_coords,
+        &y_coords,
+        model_core.config.domain.spacing,
+    );
+
+    if stagger != (0.0, 0.0) {
+        check_staggered_extent(&model_core.environ, &x_coords, &y_coords)?;
+    }
+
     let mut xy_coords = vec![];
 
-    for x in &x_coords {
-        for y in &y_coords {
-            xy_coords.push((*x, *y));
+    for (i, x) in x_coords.iter().enumerate() {
+        for (j, y) in y_coords.iter().enumerate() {
+            xy_coords.push(((i, j), (*x, *y)));
         }
     }
 
-    xy_coords
+    Ok(xy_coords)
 }
 
-/// (TODO: What it is)
+/// Compares ground distances between adjacent release points
+/// with the configured spacing and warns when they differ noticeably.
 ///
-/// (Why it is neccessary)
-fn save_conv_params(convective_params_list: Vec<ConvectiveParams>) -> Result<(), Error> {
-    let out_path = Path::new("./output/model_convective_params.csv");
+/// Points are spaced regularly on the projection plane, which preserves
+/// distances only near the standard parallels, so in large domains the
+/// effective resolution differs from the spacing. Distances are checked
+/// at the corners and in the centre of the domain.
+fn check_release_spacing(
+    environ: &Environment,
+    x_coords: &[Float],
+    y_coords: &[Float],
+    spacing: Float,
+) {
+    let sampled = |count: usize| [0, count / 2, count.saturating_sub(2)];
+    let mut distances = vec![];
+
+    for i in sampled(x_coords.len()) {
+        for j in sampled(y_coords.len()) {
+            let point = (x_coords[i], y_coords[j]);
+
+            if i + 1 < x_coords.len() {
+                let east = (x_coords[i + 1], y_coords[j]);
+                distances.push(environ.projection.ground_distance(point, east));
+            }
+
+            if j + 1 < y_coords.len() {
+                let north = (x_coords[i], y_coords[j + 1]);
+                distances.push(environ.projection.ground_distance(point, north));
+            }
+        }
+    }
+
+    if distances.is_empty() {
+        return;
+    }
+
+    let min = distances.iter().copied().fold(Float::MAX, Float::min);
+    let max = distances.iter().copied().fold(Float::MIN, Float::max);
+
+    debug!(
+        "Ground distance between release points is from {:.0} m to {:.0} m",
+        min, max
+    );
+
+    if (min / spacing - 1.0).abs() > SPACING_TOLERANCE
+        || (max / spacing - 1.0).abs() > SPACING_TOLERANCE
+    {
+        warn!(
+            "Ground distance between release points is from {:.0} m to {:.0} m, which differs from the configured spacing ({:.0} m) by more than {:.0}% due to the projection, so the effective resolution of results is different",
+            min,
+            max,
+            spacing,
+            SPACING_TOLERANCE * 100.0
+        );
+    }
+}
+
+/// Checks that corners of the staggered domain grid are within
+/// the buffered extent, as the extent is computed without the offset.
+fn check_staggered_extent(
+    environ: &Environment,
+    x_coords: &[Float],
+    y_coords: &[Float],
+) -> Result<(), ModelError> {
+    let (x_first, x_last) = (x_coords[0], x_coords[x_coords.len() - 1]);
+    let (y_first, y_last) = (y_coords[0], y_coords[y_coords.len() - 1]);
+
+    for (x, y) in [
+        (x_first, y_first),
+        (x_first, y_last),
+        (x_last, y_first),
+        (x_last, y_last),
+    ] {
+        // surface data can be interpolated only within the buffered extent
+        if environ
+            .get_surface_value(x, y, SurfaceFields::Height)
+            .is_err()
+        {
+            error!(
+                "Staggered release point ({}, {}) is outside of the buffered input data",
+                x, y
+            );
+
+            return Err(EnvironmentError::InsufficientMargins(
+                "staggered release point is outside of the buffered extent",
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Function projecting release points listed in configuration,
+/// checking that environment data is buffered around each of them.
+fn prepare_points_list(
+    environ: &Environment,
+    points: &[(Float, Float)],
+) -> Result<Vec<ReleasePoint>, ModelError> {
+    let mut xy_coords = Vec::with_capacity(points.len());
+
+    // listed points have no grid, so they are
+    // indexed by their position in the list
+    for (i, &(lon, lat)) in points.iter().enumerate() {
+        let (x, y) = environ
+            .projection
+            .try_project(lon, lat)
+            .map_err(EnvironmentError::from)?;
+
+        // surface data can be interpolated only within the buffered extent
+        if environ
+            .get_surface_value(x, y, SurfaceFields::Height)
+            .is_err()
+        {
+            error!(
+                "Release point ({}, {}) is outside of the buffered input data",
+                lon, lat
+            );
+
+            return Err(EnvironmentError::InsufficientMargins(
+                "release point is outside of the buffered extent",
+            )
+            .into());
+        }
+
+        xy_coords.push(((i, 0), (x, y)));
+    }
+
+    Ok(xy_coords)
+}
+
+/// Writes computed convective parameters in the format
+/// selected in configuration, or only their statistics
+/// when aggregated output is selected.
+fn save_conv_params(
+    convective_params_list: Vec<ConvectiveParams>,
+    output: &Output,
+    out_dir: &Path,
+) -> Result<(), ModelError> {
+    if output.mode == OutputMode::Aggregate {
+        return aggregate::save_aggregate(
+            &convective_params_list,
+            &output.lifted_index_levels,
+            &out_dir.join("model_aggregate_params.json"),
+        );
+    }
+
+    match output.conv_params_format {
+        OutputFormat::Csv => save_conv_params_csv(
+            convective_params_list,
+            &out_dir.join("model_convective_params.csv"),
+            output,
+        )?,
+
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => output::save_parquet(
+            &convective_params_list,
+            &output.lifted_index_levels,
+            &out_dir.join("model_convective_params.parquet"),
+        )?,
+
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => {
+            return Err(ModelError::FaultyOutput(
+                "Parquet output requires the model to be compiled with parquet feature",
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes convective parameters of all parcels
+/// into CSV file at `out_path`, with the configured format.
+fn save_conv_params_csv(
+    convective_params_list: Vec<ConvectiveParams>,
+    out_path: &Path,
+    output: &Output,
+) -> Result<(), ModelError> {
+    let format = &output.csv;
+    let mut out_file = csv_format::create_writer(out_path, format, output.compress)?;
+    let lifted_index_count = output.lifted_index_levels.len();
 
-    let mut out_file = csv::Writer::from_path(out_path)?;
+    out_file.write_record(ConvectiveParams::header(&output.lifted_index_levels))?;
 
     for conv_params in convective_params_list {
-        out_file.serialize(conv_params)?;
+        out_file.write_record(csv_format::params_fields(
+            &conv_params.values(lifted_index_count),
+            format,
+            output.coordinate_format,
+        ))?;
     }
 
-    out_file.flush()?;
+    csv_format::finish_writer(out_file)?;
 
     Ok(())
 }
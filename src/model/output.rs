@@ -0,0 +1,171 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module responsible for writing model
+//! output in formats other than CSV.
+//!
+//! Columns are built directly from typed values of convective
+//! parameters (with the same headers as CSV output), so the schema
+//! is fixed and does not depend on values of particular parcels.
+
+use super::parcel::conv_params::{ConvectiveParams, ParamValue};
+use crate::{errors::ModelError, Float};
+use arrow::{
+    array::{ArrayRef, BooleanArray, Float64Array, StringArray, UInt64Array},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use parquet::arrow::ArrowWriter;
+use std::{fs::File, path::Path, sync::Arc};
+
+/// Writes convective parameters (with Lifted Indices
+/// at given levels) into a parquet file.
+///
+/// Empty values (eg. `None`) are stored as nulls.
+pub fn save_parquet(
+    convective_params_list: &[ConvectiveParams],
+    lifted_index_levels: &[Float],
+    out_path: &Path,
+) -> Result<(), ModelError> {
+    let lifted_index_count = lifted_index_levels.len();
+    let rows: Vec<Vec<ParamValue>> = convective_params_list
+        .iter()
+        .map(|params| params.values(lifted_index_count))
+        .collect();
+
+    let schema = Arc::new(params_schema(lifted_index_levels));
+    let columns = (0..schema.fields().len())
+        .map(|i| build_column(schema.field(i).data_type(), &rows, i))
+        .collect();
+
+    let batch = RecordBatch::try_new(schema, columns)?;
+
+    let out_file = File::create(out_path)?;
+    let mut writer = ArrowWriter::try_new(out_file, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Schema of convective parameters, with column types
+/// following types of the parameter values.
+fn params_schema(lifted_index_levels: &[Float]) -> Schema {
+    let values = ConvectiveParams::default().values(lifted_index_levels.len());
+
+    let fields: Vec<Field> = ConvectiveParams::header(lifted_index_levels)
+        .into_iter()
+        .zip(values)
+        .map(|(name, value)| {
+            let data_type = match value {
+                ParamValue::Float(_) => DataType::Float64,
+                ParamValue::Integer(_) => DataType::UInt64,
+                ParamValue::Bool(_) => DataType::Boolean,
+                ParamValue::Text(_) => DataType::Utf8,
+            };
+
+            Field::new(name, data_type, true)
+        })
+        .collect();
+
+    Schema::new(fields)
+}
+
+/// Builds typed array from `column`-th values of rows.
+///
+/// Values of other types than `data_type` are stored as nulls,
+/// but they do not occur as each parameter has a single type.
+fn build_column(data_type: &DataType, rows: &[Vec<ParamValue>], column: usize) -> ArrayRef {
+    let values = rows.iter().map(|row| row[column]);
+
+    match data_type {
+        DataType::UInt64 => Arc::new(
+            values
+                .map(|value| match value {
+                    ParamValue::Integer(value) => value,
+                    _ => None,
+                })
+                .collect::<UInt64Array>(),
+        ),
+        DataType::Boolean => Arc::new(
+            values
+                .map(|value| match value {
+                    ParamValue::Bool(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Utf8 => Arc::new(
+            values
+                .map(|value| match value {
+                    ParamValue::Text(value) => value,
+                    _ => None,
+                })
+                .collect::<StringArray>(),
+        ),
+        _ => Arc::new(
+            values
+                .map(|value| match value {
+                    ParamValue::Float(value) => value,
+                    _ => None,
+                })
+                .collect::<Float64Array>(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_column, params_schema};
+    use crate::model::parcel::conv_params::ParamValue;
+    use arrow::{
+        array::{Array, Float64Array},
+        datatypes::DataType,
+    };
+
+    #[test]
+    fn nullable_float_column() {
+        let rows: Vec<Vec<ParamValue>> = [Some(1.5), None, Some(0.0), Some(-2e-3)]
+            .into_iter()
+            .map(|value| vec![ParamValue::Float(value)])
+            .collect();
+
+        let column = build_column(&DataType::Float64, &rows, 0);
+        let column = column.as_any().downcast_ref::<Float64Array>().unwrap();
+
+        assert!(column.is_null(1));
+        assert!(column.is_valid(2));
+        assert_eq!(column.value(2), 0.0);
+        assert_eq!(column.value(3), -2e-3);
+    }
+
+    #[test]
+    fn fixed_schema() {
+        let schema = params_schema(&[500.0]);
+        let data_type = |name| schema.field_with_name(name).unwrap().data_type().clone();
+
+        assert_eq!(data_type("start_lon"), DataType::Float64);
+        assert_eq!(data_type("cape"), DataType::Float64);
+        assert_eq!(data_type("step_count"), DataType::UInt64);
+        assert_eq!(data_type("edge_distance"), DataType::UInt64);
+        assert_eq!(data_type("overshoot"), DataType::Boolean);
+        assert_eq!(data_type("error_code"), DataType::Utf8);
+        assert_eq!(data_type("lifted_index_500"), DataType::Float64);
+    }
+}
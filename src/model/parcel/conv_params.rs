@@ -23,18 +23,28 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 
 use super::ParcelState;
 use crate::{
-    errors::ParcelError,
-    model::environment::{EnvFields::VirtualTemperature, Environment},
+    errors::{EnvironmentError, ErrorCode, ParcelError, SearchError},
+    model::{
+        configuration::{CapeIntegration, Output, VerticalCoordinate, MAX_LIFTED_INDEX_LEVELS},
+        environment::{
+            EnvFields::{Pressure, Temperature, UWind, VWind, VirtualTemperature},
+            Environment, SearchCache, SurfaceFields,
+        },
+    },
     Float,
 };
 use float_cmp::approx_eq;
-use floccus::constants::G;
+use floccus::{
+    constants::{C_P, G, R_D},
+    mixing_ratio,
+};
 use serde::Serialize;
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 
-/// (TODO: What it is)
+/// Convective parameters and ascent statistics of a single parcel,
+/// written as a row of the output file.
 ///
-/// (Why it is neccessary)
+/// Values can be also read with getters, when the model is used as a library.
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default, Serialize)]
 pub struct ConvectiveParams {
     start_lon: Float,
@@ -43,36 +53,201 @@ pub struct ConvectiveParams {
     /// Parcel Top Height
     parcel_top: Float,
 
+    /// Parcel pressure at the top of ascent
+    parcel_top_pressure: Float,
+
+    /// Environmental temperature at the top of ascent
+    /// (for analysis relative to the tropopause)
+    parcel_top_env_temp: Float,
+
     /// Parcel displacement from initial point
     x_displac: Float,
     y_displac: Float,
 
+    /// Horizontal distance (in meters) from initial point to parcel top
+    horizontal_distance_m: Float,
+
+    /// Direction (in degrees clockwise from grid north)
+    /// from initial point to parcel top, when parcel moved horizontally
+    displacement_bearing_deg: Option<Float>,
+
     /// Parcel Maximum Vertical Velocity
     max_vert_vel: Float,
 
+    /// Whether parcel continued the ascent
+    /// after its vertical velocity dropped to zero
+    overshoot: bool,
+
+    /// Whether parcel reached saturation during the ascent
+    /// (dry thermals do not, so they have no condensation
+    /// level, LFC and EL, but their top is still meaningful)
+    saturated: bool,
+
     /// Condensation Level
     /// (similar to Convective Condensation Level)
     condens_lvl: Option<Float>,
 
+    /// Convective Condensation Level, at which the environment
+    /// is saturated with surface mixing ratio
+    ccl: Option<Float>,
+
+    /// Convective Temperature, surface temperature needed
+    /// for the parcel to reach the CCL dry adiabatically
+    convective_temperature: Option<Float>,
+
     /// Level of Free Convection
     lfc: Option<Float>,
 
     /// Equilibrium Level
     el: Option<Float>,
 
+    /// Depth (in meters) of the ascent above the Equilibrium Level
+    /// (overshooting top depth), regardless of vertical coordinate
+    overshoot_depth: Option<Float>,
+
+    /// Environmental temperature at the Equilibrium Level
+    /// (a proxy of cloud-top brightness temperature)
+    el_temperature: Option<Float>,
+
     /// Convective Available Potential Energy
     cape: Option<Float>,
 
     /// Convective Inhibition
     cin: Option<Float>,
+
+    /// Storm-Relative Helicity in 0-1 km AGL layer
+    srh_0_1km: Option<Float>,
+
+    /// Storm-Relative Helicity in 0-3 km AGL layer
+    srh_0_3km: Option<Float>,
+
+    /// Bulk Wind Shear in 0-6 km AGL layer
+    /// (kinematic parameters are empty when wind profile
+    /// above release point is not covered by input data)
+    bulk_shear_0_6km: Option<Float>,
+
+    /// Distance (in domain gridpoints) of release point
+    /// to the nearest domain edge, when enabled in configuration
+    edge_distance: Option<usize>,
+
+    /// Index of the ensemble member, when initial
+    /// state of the parcel is perturbed
+    member: Option<usize>,
+
+    /// Number of Runge-Kutta steps of the parcel ascent
+    step_count: u32,
+
+    /// Number of times the ascent was repeated with halved timestep
+    /// after an error of thermodynamic computation
+    timestep_retries: u32,
+
+    /// Wall-time (in microseconds) of the ascent
+    /// integration, when enabled in configuration
+    solver_micros: Option<u64>,
+
+    /// Whether parcel was not simulated, as pre-screening
+    /// showed that it cannot become buoyant (has no CAPE)
+    prescreened: bool,
+
+    /// Code of the error category when parcel simulation failed
+    error_code: Option<ErrorCode>,
+
+    /// Lifted Indices at configured reference levels, written
+    /// after other parameters as their number is not fixed
+    #[serde(skip)]
+    lifted_indices: [Option<Float>; MAX_LIFTED_INDEX_LEVELS],
+
+    /// Number of computed Lifted Indices
+    #[serde(skip)]
+    lifted_index_count: usize,
 }
 
+/// Names of output columns of convective parameters (without
+/// Lifted Indices), in order of [`ConvectiveParams::values`].
+const PARAMS_HEADER: [&str; 31] = [
+    "start_lon",
+    "start_lat",
+    "parcel_top",
+    "parcel_top_pressure",
+    "parcel_top_env_temp",
+    "x_displac",
+    "y_displac",
+    "horizontal_distance_m",
+    "displacement_bearing_deg",
+    "max_vert_vel",
+    "overshoot",
+    "saturated",
+    "condens_lvl",
+    "ccl",
+    "convective_temperature",
+    "lfc",
+    "el",
+    "overshoot_depth",
+    "el_temperature",
+    "cape",
+    "cin",
+    "srh_0_1km",
+    "srh_0_3km",
+    "bulk_shear_0_6km",
+    "edge_distance",
+    "member",
+    "step_count",
+    "timestep_retries",
+    "solver_micros",
+    "prescreened",
+    "error_code",
+];
+
+/// Value of a single output column of convective parameters,
+/// which keeps its type so that it can be written in any format.
+///
+/// Empty values (`None`) are written as empty fields or nulls.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum ParamValue {
+    Float(Option<Float>),
+    Integer(Option<u64>),
+    Bool(bool),
+    Text(Option<&'static str>),
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamValue::Float(Some(value)) => write!(f, "{:?}", value),
+            ParamValue::Integer(Some(value)) => write!(f, "{}", value),
+            ParamValue::Bool(value) => write!(f, "{}", value),
+            ParamValue::Text(Some(value)) => write!(f, "{}", value),
+            ParamValue::Float(None) | ParamValue::Integer(None) | ParamValue::Text(None) => Ok(()),
+        }
+    }
+}
+
+/// Vertical spacing (in meters) of wind samples
+/// used for computing kinematic parameters.
+const WIND_PROFILE_STEP: Float = 100.0;
+
+/// Depth (in meters) of wind profile sampled
+/// above the parcel release point.
+const WIND_PROFILE_DEPTH: Float = 6000.0;
+
+/// Deviation (in m/s) of supercell motion
+/// from the mean wind in Bunkers method.
+const BUNKERS_DEVIATION: Float = 7.5;
+
+/// Vertical spacing (in meters) of environment samples
+/// searched for the Convective Condensation Level.
+const CCL_SEARCH_STEP: Float = 50.0;
+
+/// Wind components at equally spaced heights.
+type WindProfile = Vec<(Float, Float)>;
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
 pub(super) fn compute_conv_params(
     parcel_log: &[ParcelState],
     environment: &Arc<Environment>,
+    output: &Output,
 ) -> Result<ConvectiveParams, ParcelError> {
     let mut result_params = ConvectiveParams::default();
 
@@ -89,25 +264,414 @@ pub(super) fn compute_conv_params(
     // to avoid calls to Environment
     let env_vrt_tmp = get_env_vtemp(parcel_log, environment)?;
 
-    result_params.update_displacements(parcel_log);
-    result_params.update_levels(parcel_log, &env_vrt_tmp);
-    result_params.update_thermodynamic_vars(parcel_log, &env_vrt_tmp);
+    let release_point = parcel_log.first().unwrap().position;
+    let surface_height =
+        environment.get_surface_value(release_point.x, release_point.y, SurfaceFields::Height)?;
+
+    result_params.update_displacements(parcel_log, environment)?;
+    let level_indices = result_params.update_levels(parcel_log, &env_vrt_tmp);
+    result_params.update_thermodynamic_vars(parcel_log, &env_vrt_tmp, output.cape_integration);
+    result_params.update_el_temperature(parcel_log, level_indices.el, environment)?;
+    result_params.update_convective_temperature(
+        parcel_log,
+        environment,
+        surface_height,
+        output.vertical_coordinate,
+    )?;
+
+    // kinematic parameters are left empty instead of failing the parcel
+    // when wind profile cannot be sampled, eg. above the top of input data
+    if let Ok(wind_profile) = get_env_wind_profile(parcel_log, environment) {
+        result_params.update_kinematic_vars(&wind_profile);
+    }
+
+    // levels are heights during computations,
+    // so they can be converted only after all of them
+    result_params.convert_levels(
+        parcel_log,
+        level_indices,
+        surface_height,
+        output.vertical_coordinate,
+    );
 
     Ok(result_params)
 }
 
 impl ConvectiveParams {
+    /// Names of output columns, with Lifted Indices at
+    /// given reference levels (in hPa) after other parameters.
+    pub(crate) fn header(lifted_index_levels: &[Float]) -> Vec<String> {
+        PARAMS_HEADER
+            .iter()
+            .map(|name| name.to_string())
+            .chain(
+                lifted_index_levels
+                    .iter()
+                    .map(|level| format!("lifted_index_{}", level)),
+            )
+            .collect()
+    }
+
+    /// Values of output columns, named by [`ConvectiveParams::header`].
+    ///
+    /// Lifted Indices are padded with empty values to `lifted_index_count`,
+    /// as failed and pre-screened parcels have none computed.
+    pub(crate) fn values(&self, lifted_index_count: usize) -> Vec<ParamValue> {
+        let count = |value: Option<usize>| ParamValue::Integer(value.map(|value| value as u64));
+
+        let mut values = vec![
+            ParamValue::Float(Some(self.start_lon)),
+            ParamValue::Float(Some(self.start_lat)),
+            ParamValue::Float(Some(self.parcel_top)),
+            ParamValue::Float(Some(self.parcel_top_pressure)),
+            ParamValue::Float(Some(self.parcel_top_env_temp)),
+            ParamValue::Float(Some(self.x_displac)),
+            ParamValue::Float(Some(self.y_displac)),
+            ParamValue::Float(Some(self.horizontal_distance_m)),
+            ParamValue::Float(self.displacement_bearing_deg),
+            ParamValue::Float(Some(self.max_vert_vel)),
+            ParamValue::Bool(self.overshoot),
+            ParamValue::Bool(self.saturated),
+            ParamValue::Float(self.condens_lvl),
+            ParamValue::Float(self.ccl),
+            ParamValue::Float(self.convective_temperature),
+            ParamValue::Float(self.lfc),
+            ParamValue::Float(self.el),
+            ParamValue::Float(self.overshoot_depth),
+            ParamValue::Float(self.el_temperature),
+            ParamValue::Float(self.cape),
+            ParamValue::Float(self.cin),
+            ParamValue::Float(self.srh_0_1km),
+            ParamValue::Float(self.srh_0_3km),
+            ParamValue::Float(self.bulk_shear_0_6km),
+            count(self.edge_distance),
+            count(self.member),
+            ParamValue::Integer(Some(u64::from(self.step_count))),
+            ParamValue::Integer(Some(u64::from(self.timestep_retries))),
+            ParamValue::Integer(self.solver_micros),
+            ParamValue::Bool(self.prescreened),
+            ParamValue::Text(self.error_code.as_ref().map(ErrorCode::as_str)),
+        ];
+
+        values.extend(
+            (0..lifted_index_count)
+                .map(|i| ParamValue::Float(self.lifted_indices().get(i).copied().flatten())),
+        );
+
+        values
+    }
+
+    /// Creates parameters of a parcel which simulation failed,
+    /// so that the parcel is still present in the output.
+    pub fn new_failed(start_lon: Float, start_lat: Float, error_code: ErrorCode) -> Self {
+        ConvectiveParams {
+            start_lon,
+            start_lat,
+            parcel_top: Float::NAN,
+            parcel_top_pressure: Float::NAN,
+            parcel_top_env_temp: Float::NAN,
+            x_displac: Float::NAN,
+            y_displac: Float::NAN,
+            horizontal_distance_m: Float::NAN,
+            max_vert_vel: Float::NAN,
+            error_code: Some(error_code),
+            ..ConvectiveParams::default()
+        }
+    }
+
+    /// Creates parameters of a parcel which was not simulated,
+    /// as it cannot become buoyant, so its CAPE is zero.
+    pub(crate) fn new_prescreened(start_lon: Float, start_lat: Float) -> Self {
+        ConvectiveParams {
+            start_lon,
+            start_lat,
+            parcel_top: Float::NAN,
+            parcel_top_pressure: Float::NAN,
+            parcel_top_env_temp: Float::NAN,
+            x_displac: Float::NAN,
+            y_displac: Float::NAN,
+            horizontal_distance_m: Float::NAN,
+            max_vert_vel: Float::NAN,
+            cape: Some(0.0),
+            prescreened: true,
+            ..ConvectiveParams::default()
+        }
+    }
+
+    /// Sets distance (in domain gridpoints) of
+    /// release point to the nearest domain edge.
+    pub(crate) fn set_edge_distance(&mut self, edge_distance: Option<usize>) {
+        self.edge_distance = edge_distance;
+    }
+
+    /// Sets index of the ensemble member of perturbed parcel.
+    pub(crate) fn set_member(&mut self, member: Option<usize>) {
+        self.member = member;
+    }
+
+    /// Sets number of integration steps and wall-time
+    /// (in microseconds) of the parcel ascent.
+    pub(crate) fn set_solver_stats(&mut self, step_count: u32, solver_micros: Option<u64>) {
+        self.step_count = step_count;
+        self.solver_micros = solver_micros;
+    }
+
+    /// Sets number of retries with halved timestep
+    /// needed to complete the parcel ascent.
+    pub(crate) fn set_timestep_retries(&mut self, timestep_retries: u32) {
+        self.timestep_retries = timestep_retries;
+    }
+
+    /// Computes Lifted Indices at given reference levels (in hPa),
+    /// as the difference between environmental and parcel
+    /// temperature at parcel state nearest to each level.
+    ///
+    /// Lifted Index is empty at levels the parcel has not passed.
+    pub(super) fn update_lifted_indices(
+        &mut self,
+        parcel_log: &[ParcelState],
+        environment: &Arc<Environment>,
+        levels: &[Float],
+    ) -> Result<(), ParcelError> {
+        let (top_pres, bottom_pres) = parcel_log
+            .iter()
+            .fold((Float::MAX, Float::MIN), |(min, max), point| {
+                (min.min(point.pres), max.max(point.pres))
+            });
+
+        self.lifted_indices = Default::default();
+        self.lifted_index_count = levels.len();
+
+        for (i, &level) in levels.iter().enumerate() {
+            let level_pres = level * 100.0;
+
+            if level_pres < top_pres || level_pres > bottom_pres {
+                continue;
+            }
+
+            let nearest = parcel_log
+                .iter()
+                .min_by(|a, b| {
+                    (a.pres - level_pres)
+                        .abs()
+                        .total_cmp(&(b.pres - level_pres).abs())
+                })
+                .expect("Parcel log is never empty");
+
+            let env_temp = environment.get_field_value_at(
+                nearest.position.x,
+                nearest.position.y,
+                nearest.position.z,
+                Temperature,
+                nearest.datetime,
+            )?;
+
+            self.lifted_indices[i] = Some(env_temp - nearest.temp);
+        }
+
+        Ok(())
+    }
+
+    /// Longitude (in degrees) of the parcel release point.
+    pub fn start_lon(&self) -> Float {
+        self.start_lon
+    }
+
+    /// Latitude (in degrees) of the parcel release point.
+    pub fn start_lat(&self) -> Float {
+        self.start_lat
+    }
+
+    /// Top of the parcel ascent, in configured vertical coordinate.
+    ///
+    /// NaN when the parcel was not simulated.
+    pub fn parcel_top(&self) -> Float {
+        self.parcel_top
+    }
+
+    /// Parcel pressure (in Pa) at the top of ascent.
+    pub fn parcel_top_pressure(&self) -> Float {
+        self.parcel_top_pressure
+    }
+
+    /// Environmental temperature (in K) at the top of ascent.
+    pub fn parcel_top_env_temp(&self) -> Float {
+        self.parcel_top_env_temp
+    }
+
+    /// Parcel displacement (in meters) along x and y axes
+    /// of the projection from release point to parcel top.
+    pub fn displacement(&self) -> (Float, Float) {
+        (self.x_displac, self.y_displac)
+    }
+
+    /// Horizontal distance (in meters) from release point to parcel top.
+    pub fn horizontal_distance_m(&self) -> Float {
+        self.horizontal_distance_m
+    }
+
+    /// Direction (in degrees clockwise from grid north) from release
+    /// point to parcel top, empty when parcel did not move horizontally.
+    pub fn displacement_bearing_deg(&self) -> Option<Float> {
+        self.displacement_bearing_deg
+    }
+
+    /// Maximum vertical velocity (in m/s) of the parcel.
+    pub fn max_vert_vel(&self) -> Float {
+        self.max_vert_vel
+    }
+
+    /// Whether parcel continued the ascent after it stopped.
+    pub fn overshoot(&self) -> bool {
+        self.overshoot
+    }
+
+    /// Whether parcel reached saturation during the ascent.
+    pub fn saturated(&self) -> bool {
+        self.saturated
+    }
+
+    /// Condensation Level of the parcel, in configured vertical coordinate.
+    pub fn condens_lvl(&self) -> Option<Float> {
+        self.condens_lvl
+    }
+
+    /// Convective Condensation Level, in configured vertical coordinate.
+    pub fn ccl(&self) -> Option<Float> {
+        self.ccl
+    }
+
+    /// Convective Temperature (in K).
+    pub fn convective_temperature(&self) -> Option<Float> {
+        self.convective_temperature
+    }
+
+    /// Level of Free Convection of the parcel, in configured vertical coordinate.
+    pub fn lfc(&self) -> Option<Float> {
+        self.lfc
+    }
+
+    /// Equilibrium Level of the parcel, in configured vertical coordinate.
+    pub fn el(&self) -> Option<Float> {
+        self.el
+    }
+
+    /// Depth (in meters) of the ascent above the Equilibrium Level.
+    pub fn overshoot_depth(&self) -> Option<Float> {
+        self.overshoot_depth
+    }
+
+    /// Environmental temperature (in K) at the Equilibrium Level.
+    pub fn el_temperature(&self) -> Option<Float> {
+        self.el_temperature
+    }
+
+    /// Convective Available Potential Energy (in J/kg) of the parcel.
+    pub fn cape(&self) -> Option<Float> {
+        self.cape
+    }
+
+    /// Convective Inhibition (in J/kg) of the parcel.
+    pub fn cin(&self) -> Option<Float> {
+        self.cin
+    }
+
+    /// Storm-Relative Helicity (in m^2/s^2) in 0-1 km AGL layer.
+    pub fn srh_0_1km(&self) -> Option<Float> {
+        self.srh_0_1km
+    }
+
+    /// Storm-Relative Helicity (in m^2/s^2) in 0-3 km AGL layer.
+    pub fn srh_0_3km(&self) -> Option<Float> {
+        self.srh_0_3km
+    }
+
+    /// Bulk Wind Shear (in m/s) in 0-6 km AGL layer.
+    pub fn bulk_shear_0_6km(&self) -> Option<Float> {
+        self.bulk_shear_0_6km
+    }
+
+    /// Distance (in domain gridpoints) of release point
+    /// to the nearest domain edge, when enabled in configuration.
+    pub fn edge_distance(&self) -> Option<usize> {
+        self.edge_distance
+    }
+
+    /// Index of the ensemble member of perturbed parcel.
+    pub fn member(&self) -> Option<usize> {
+        self.member
+    }
+
+    /// Number of Runge-Kutta steps of the parcel ascent.
+    pub fn step_count(&self) -> u32 {
+        self.step_count
+    }
+
+    /// Number of retries with halved timestep of the parcel ascent.
+    pub fn timestep_retries(&self) -> u32 {
+        self.timestep_retries
+    }
+
+    /// Wall-time (in microseconds) of the ascent integration,
+    /// when enabled in configuration.
+    pub fn solver_micros(&self) -> Option<u64> {
+        self.solver_micros
+    }
+
+    /// Whether the parcel was skipped by pre-screening.
+    pub fn prescreened(&self) -> bool {
+        self.prescreened
+    }
+
+    /// Code of the error category when parcel simulation failed.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        self.error_code
+    }
+
+    /// Lifted Indices at configured reference levels.
+    pub fn lifted_indices(&self) -> &[Option<Float>] {
+        &self.lifted_indices[..self.lifted_index_count]
+    }
+
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    fn update_displacements(&mut self, parcel_log: &[ParcelState]) {
-        self.parcel_top = parcel_log.last().unwrap().position.z;
+    fn update_displacements(
+        &mut self,
+        parcel_log: &[ParcelState],
+        environment: &Arc<Environment>,
+    ) -> Result<(), ParcelError> {
+        let top_point = parcel_log.last().unwrap();
+
+        self.parcel_top = top_point.position.z;
+        self.parcel_top_pressure = top_point.pres;
+        self.parcel_top_env_temp = environment.get_field_value_at(
+            top_point.position.x,
+            top_point.position.y,
+            top_point.position.z,
+            Temperature,
+            top_point.datetime,
+        )?;
 
         self.x_displac =
             parcel_log.last().unwrap().position.x - parcel_log.first().unwrap().position.x;
         self.y_displac =
             parcel_log.last().unwrap().position.y - parcel_log.first().unwrap().position.y;
 
+        self.horizontal_distance_m = self.x_displac.hypot(self.y_displac);
+
+        // bearing is undefined without horizontal advection
+        self.displacement_bearing_deg = if self.horizontal_distance_m > 0.0 {
+            Some(
+                self.x_displac
+                    .atan2(self.y_displac)
+                    .to_degrees()
+                    .rem_euclid(360.0),
+            )
+        } else {
+            None
+        };
+
         self.max_vert_vel = parcel_log
             .iter()
             .max_by(|x, y| {
@@ -119,12 +683,23 @@ impl ConvectiveParams {
             .expect("Parcel log is empty")
             .velocity
             .z;
+
+        // ascent is only continued after the parcel stops
+        // when overshoot is allowed in configuration
+        self.overshoot = parcel_log
+            .iter()
+            .skip(1)
+            .any(|point| point.velocity.z <= 0.0);
+
+        Ok(())
     }
 
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    fn update_levels(&mut self, parcel_log: &[ParcelState], env_vrt_tmp: &[Float]) {
+    fn update_levels(&mut self, parcel_log: &[ParcelState], env_vrt_tmp: &[Float]) -> LevelIndices {
+        let mut level_indices = LevelIndices::default();
+
         // searched levels are subsequent and interdependent, so we look for them in loops
         // iterating from log beginning, thus from ascent bottom
         let mut ccl_index = 0;
@@ -133,11 +708,14 @@ impl ConvectiveParams {
             // first time this is true is condensation level
             if point.mxng_rto >= point.satr_mxng_rto {
                 self.condens_lvl = Some(point.position.z);
+                level_indices.condens_lvl = Some(i);
                 ccl_index = i;
                 break;
             }
         }
 
+        self.saturated = self.condens_lvl.is_some();
+
         let mut lfc_index = 0;
 
         if self.condens_lvl.is_some() {
@@ -148,6 +726,7 @@ impl ConvectiveParams {
                 // first time this is true is LFC
                 if point.vrt_temp > env_vrt_tmp[i] {
                     self.lfc = Some(point.position.z);
+                    level_indices.lfc = Some(i);
                     lfc_index = i;
                     break;
                 }
@@ -168,31 +747,55 @@ impl ConvectiveParams {
                 // level at which this is true is EL
                 if !negative_bouyancy_region && point.vrt_temp <= env_vrt_tmp[i] {
                     self.el = Some(point.position.z);
+                    level_indices.el = Some(i);
                     negative_bouyancy_region = true;
                 }
             }
         }
+
+        // levels are still heights, as they are converted after all computations
+        self.overshoot_depth = self.el.map(|el| self.parcel_top - el);
+
+        level_indices
     }
 
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    fn update_thermodynamic_vars(&mut self, parcel_log: &[ParcelState], env_vrt_tmp: &[Float]) {
+    fn update_thermodynamic_vars(
+        &mut self,
+        parcel_log: &[ParcelState],
+        env_vrt_tmp: &[Float],
+        integration: CapeIntegration,
+    ) {
+        // this is a trapezium rule of integral of bouyancy force, effectively an average
+        let buoyant_energy = |i: usize| match integration {
+            CapeIntegration::Height => {
+                let y_1 = (parcel_log[i].vrt_temp - env_vrt_tmp[i]) / env_vrt_tmp[i];
+                let y_0 = (parcel_log[i - 1].vrt_temp - env_vrt_tmp[i - 1]) / env_vrt_tmp[i - 1];
+
+                let delta_z = parcel_log[i].position.z - parcel_log[i - 1].position.z;
+
+                G * ((y_0 + y_1) / 2.0) * delta_z
+            }
+            CapeIntegration::LogPressure => {
+                let y_1 = parcel_log[i].vrt_temp - env_vrt_tmp[i];
+                let y_0 = parcel_log[i - 1].vrt_temp - env_vrt_tmp[i - 1];
+
+                let delta_ln_p = parcel_log[i].pres.ln() - parcel_log[i - 1].pres.ln();
+
+                -R_D * ((y_0 + y_1) / 2.0) * delta_ln_p
+            }
+        };
+
         let mut lfc_id = 0;
 
         // compute CIN if LFC is present
         let mut cin: Float = 0.0;
         if self.lfc.is_some() {
             //we start from the 2nd point of parcel log to not go out of bounds
-            for i in 1..parcel_log.len() {
-                let point = parcel_log[i];
-
-                let y_1 = (point.vrt_temp - env_vrt_tmp[i]) / env_vrt_tmp[i];
-                let y_0 = (parcel_log[i - 1].vrt_temp - env_vrt_tmp[i - 1]) / env_vrt_tmp[i - 1];
-
-                let delta_z = point.position.z - parcel_log[i - 1].position.z;
-
-                cin += ((y_0 + y_1) / 2.0) * delta_z;
+            for (i, point) in parcel_log.iter().enumerate().skip(1) {
+                cin += buoyant_energy(i);
 
                 if approx_eq!(Float, point.position.z, self.lfc.unwrap()) {
                     lfc_id = i;
@@ -201,22 +804,14 @@ impl ConvectiveParams {
             }
         }
 
-        self.cin = Some(-G * cin);
+        self.cin = Some(-cin);
 
         // compute CAPE if LFC and EL is present
         let mut cape: Float = 0.0;
         if self.lfc.is_some() && self.el.is_some() {
             // we start integration from LFC
-            for i in (lfc_id + 1)..parcel_log.len() {
-                let point = parcel_log[i];
-
-                // this is a trapezium rule of integral of bouyancy force, effectively an average
-                let y_1 = (point.vrt_temp - env_vrt_tmp[i]) / env_vrt_tmp[i];
-                let y_0 = (parcel_log[i - 1].vrt_temp - env_vrt_tmp[i - 1]) / env_vrt_tmp[i - 1];
-
-                let delta_z = point.position.z - parcel_log[i - 1].position.z;
-
-                cape += ((y_0 + y_1) / 2.0) * delta_z;
+            for (i, point) in parcel_log.iter().enumerate().skip(lfc_id + 1) {
+                cape += buoyant_energy(i);
 
                 if approx_eq!(Float, point.position.z, self.el.unwrap()) {
                     break;
@@ -224,8 +819,242 @@ impl ConvectiveParams {
             }
         }
 
-        self.cape = Some(G * cape);
+        self.cape = Some(cape);
+    }
+
+    /// Samples environmental temperature at the parcel
+    /// position at the Equilibrium Level, if it is present.
+    fn update_el_temperature(
+        &mut self,
+        parcel_log: &[ParcelState],
+        el_index: Option<usize>,
+        environment: &Arc<Environment>,
+    ) -> Result<(), ParcelError> {
+        if let Some(el_index) = el_index {
+            let el_point = parcel_log[el_index];
+
+            self.el_temperature = Some(environment.get_field_value_at(
+                el_point.position.x,
+                el_point.position.y,
+                el_point.position.z,
+                Temperature,
+                el_point.datetime,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the Convective Condensation Level above the surface at release point,
+    /// where saturation mixing ratio of the environment drops to the surface
+    /// mixing ratio, and the Convective Temperature of the dry adiabat
+    /// descending from the CCL to the surface pressure.
+    ///
+    /// Both are computed for a surface parcel, also when the parcel is
+    /// released above the surface. They are empty when the CCL is not found
+    /// below the top of buffered data or above the level where saturation
+    /// formula is valid (232 K).
+    /// The CCL is not reached by the parcel, so it is converted into
+    /// selected vertical coordinate with environmental pressure.
+    fn update_convective_temperature(
+        &mut self,
+        parcel_log: &[ParcelState],
+        environment: &Arc<Environment>,
+        surface_height: Float,
+        coordinate: VerticalCoordinate,
+    ) -> Result<(), ParcelError> {
+        let release = parcel_log.first().unwrap();
+        let (x, y) = (release.position.x, release.position.y);
+        let cache = SearchCache::default();
+
+        let surface_pres = environment.get_surface_value(x, y, SurfaceFields::Pressure)?;
+        let surface_dwpt = environment.get_surface_value(x, y, SurfaceFields::Dewpoint)?;
+        let surface_mxng_rto = match mixing_ratio::accuracy1(surface_dwpt, surface_pres) {
+            Ok(mxng_rto) => mxng_rto,
+            Err(_) => return Ok(()),
+        };
+
+        // temperature, pressure and excess of saturation mixing
+        // ratio over the surface mixing ratio at given height
+        let sample = |z| -> Result<Option<(Float, Float, Float)>, EnvironmentError> {
+            let temp = match environment.get_field_value_cached(x, y, z, Temperature, &cache) {
+                Ok(temp) => temp,
+                Err(EnvironmentError::SearchUnable(SearchError::OutOfBounds)) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let pres = environment.get_field_value_cached(x, y, z, Pressure, &cache)?;
+
+            Ok(mixing_ratio::accuracy1(temp, pres)
+                .ok()
+                .map(|satr_mxng_rto| (temp, pres, satr_mxng_rto - surface_mxng_rto)))
+        };
+
+        let mut below = match sample(surface_height)? {
+            Some(sample) => (surface_height, sample),
+            None => return Ok(()),
+        };
+
+        let ccl = loop {
+            let (z_below, (_, _, excess_below)) = below;
+
+            if excess_below <= 0.0 {
+                break z_below;
+            }
+
+            let z = z_below + CCL_SEARCH_STEP;
+
+            match sample(z)? {
+                Some((_, _, excess)) if excess <= 0.0 => {
+                    break z_below + CCL_SEARCH_STEP * excess_below / (excess_below - excess);
+                }
+                Some(sample) => below = (z, sample),
+                None => return Ok(()),
+            }
+        };
+
+        let ccl_temp = environment.get_field_value_cached(x, y, ccl, Temperature, &cache)?;
+        let ccl_pres = environment.get_field_value_cached(x, y, ccl, Pressure, &cache)?;
+
+        self.ccl = Some(convert_level(ccl, ccl_pres, surface_height, coordinate));
+        self.convective_temperature = Some(ccl_temp * (surface_pres / ccl_pres).powf(R_D / C_P));
+
+        Ok(())
+    }
+
+    /// Converts parcel top and levels from height MSL
+    /// into selected vertical coordinate.
+    ///
+    /// Height AGL is relative to the surface at the release point,
+    /// also when the parcel is released above the surface.
+    /// Pressure is taken from parcel state at the level.
+    fn convert_levels(
+        &mut self,
+        parcel_log: &[ParcelState],
+        level_indices: LevelIndices,
+        surface_height: Float,
+        coordinate: VerticalCoordinate,
+    ) {
+        let convert = |point: &ParcelState| {
+            convert_level(point.position.z, point.pres, surface_height, coordinate)
+        };
+        let convert_at = |index: usize| convert(&parcel_log[index]);
+
+        self.parcel_top = convert(parcel_log.last().unwrap());
+        self.condens_lvl = level_indices.condens_lvl.map(convert_at);
+        self.lfc = level_indices.lfc.map(convert_at);
+        self.el = level_indices.el.map(convert_at);
+    }
+
+    /// Computes storm-relative helicity (for right-moving supercell
+    /// motion estimated with Bunkers method) and bulk wind shear
+    /// from the environmental wind profile at release point.
+    fn update_kinematic_vars(&mut self, wind_profile: &[(Float, Float)]) {
+        let storm_motion = bunkers_storm_motion(wind_profile);
+
+        self.srh_0_1km = Some(storm_relative_helicity(wind_profile, storm_motion, 1000.0));
+        self.srh_0_3km = Some(storm_relative_helicity(wind_profile, storm_motion, 3000.0));
+
+        let bottom = wind_profile.first().unwrap();
+        let top = wind_profile.last().unwrap();
+        self.bulk_shear_0_6km = Some((top.0 - bottom.0).hypot(top.1 - bottom.1));
+    }
+}
+
+/// Indices in the parcel log of states at which levels were found.
+#[derive(Copy, Clone, Default, Debug)]
+struct LevelIndices {
+    condens_lvl: Option<usize>,
+    lfc: Option<usize>,
+    el: Option<usize>,
+}
+
+/// Converts level with given height MSL and pressure
+/// into given vertical coordinate.
+fn convert_level(
+    height: Float,
+    pressure: Float,
+    surface_height: Float,
+    coordinate: VerticalCoordinate,
+) -> Float {
+    match coordinate {
+        VerticalCoordinate::Msl => height,
+        VerticalCoordinate::Agl => height - surface_height,
+        VerticalCoordinate::Pressure => pressure,
+    }
+}
+
+/// Samples environmental wind in the column above the parcel
+/// release point, from surface up to [`WIND_PROFILE_DEPTH`].
+fn get_env_wind_profile(
+    parcel_log: &[ParcelState],
+    environment: &Arc<Environment>,
+) -> Result<WindProfile, ParcelError> {
+    let release_point = parcel_log.first().unwrap().position;
+    let samples_count = (WIND_PROFILE_DEPTH / WIND_PROFILE_STEP) as usize;
+
+    let wind_profile: Result<WindProfile, EnvironmentError> = (0..=samples_count)
+        .map(|i| {
+            let z = release_point.z + (i as Float * WIND_PROFILE_STEP);
+
+            Ok((
+                environment.get_field_value(release_point.x, release_point.y, z, UWind)?,
+                environment.get_field_value(release_point.x, release_point.y, z, VWind)?,
+            ))
+        })
+        .collect();
+
+    Ok(wind_profile?)
+}
+
+/// Estimates right-moving supercell motion with Bunkers (2000) method:
+/// 0-6 km mean wind deviated by [`BUNKERS_DEVIATION`] to the right
+/// of the 0-6 km shear vector (computed from 0.5 km deep layers means).
+fn bunkers_storm_motion(wind_profile: &[(Float, Float)]) -> (Float, Float) {
+    let layer_mean = |winds: &[(Float, Float)]| {
+        let sum = winds
+            .iter()
+            .fold((0.0, 0.0), |acc, wind| (acc.0 + wind.0, acc.1 + wind.1));
+
+        (sum.0 / winds.len() as Float, sum.1 / winds.len() as Float)
+    };
+
+    let layer_len = (500.0 / WIND_PROFILE_STEP) as usize + 1;
+
+    let mean_wind = layer_mean(wind_profile);
+    let bottom_wind = layer_mean(&wind_profile[..layer_len]);
+    let top_wind = layer_mean(&wind_profile[(wind_profile.len() - layer_len)..]);
+
+    let shear = (top_wind.0 - bottom_wind.0, top_wind.1 - bottom_wind.1);
+    let shear_magnitude = shear.0.hypot(shear.1);
+
+    if shear_magnitude == 0.0 {
+        return mean_wind;
     }
+
+    (
+        mean_wind.0 + BUNKERS_DEVIATION * (shear.1 / shear_magnitude),
+        mean_wind.1 - BUNKERS_DEVIATION * (shear.0 / shear_magnitude),
+    )
+}
+
+/// Computes storm-relative helicity from surface to given depth
+/// as a finite sum over the wind profile layers.
+fn storm_relative_helicity(
+    wind_profile: &[(Float, Float)],
+    storm_motion: (Float, Float),
+    depth: Float,
+) -> Float {
+    let layers_count = (depth / WIND_PROFILE_STEP) as usize;
+
+    wind_profile[..=layers_count]
+        .windows(2)
+        .map(|layer| {
+            let (u_0, v_0) = (layer[0].0 - storm_motion.0, layer[0].1 - storm_motion.1);
+            let (u_1, v_1) = (layer[1].0 - storm_motion.0, layer[1].1 - storm_motion.1);
+
+            u_1 * v_0 - u_0 * v_1
+        })
+        .sum()
 }
 
 /// (TODO: What it is)
@@ -238,14 +1067,507 @@ fn get_env_vtemp(
     let env_vtemp: Result<Vec<_>, _> = parcel_log
         .iter()
         .map(|pst| {
-            environment.get_field_value(
+            environment.get_field_value_at(
                 pst.position.x,
                 pst.position.y,
                 pst.position.z,
                 VirtualTemperature,
+                pst.datetime,
             )
         })
         .collect();
 
     Ok(env_vtemp?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bunkers_storm_motion, storm_relative_helicity, ConvectiveParams, ParamValue,
+        BUNKERS_DEVIATION, WIND_PROFILE_STEP,
+    };
+    use crate::{
+        model::{
+            configuration::{
+                BuoyancyFormulation, CapeIntegration, Config, Output, ReleaseLevel,
+                VerticalCoordinate,
+            },
+            environment::{analytic::AnalyticProfile, Environment},
+            parcel::{deploy, logger::TrajectoryWriter},
+        },
+        Float,
+    };
+    use float_cmp::assert_approx_eq;
+    use floccus::constants::{C_P, G, R_D};
+    use std::{env, fs, process, sync::Arc};
+
+    const SURFACE_PRESSURE: Float = 100_000.0;
+    const SURFACE_TEMPERATURE: Float = 300.0;
+    const SURFACE_DEWPOINT: Float = 290.0;
+
+    /// Dry adiabatic lapse rate
+    const DRY_LAPSE: Float = G / C_P;
+
+    /// Top of the dry adiabatic layer, above it the environment is isothermal
+    const INVERSION_HEIGHT: Float = 3000.0;
+
+    fn adiabatic_temperature(z: Float) -> Float {
+        SURFACE_TEMPERATURE - DRY_LAPSE * z.min(INVERSION_HEIGHT)
+    }
+
+    fn adiabatic_pressure(z: Float) -> Float {
+        let temp = adiabatic_temperature(z);
+        let pres = SURFACE_PRESSURE * (temp / SURFACE_TEMPERATURE).powf(C_P / R_D);
+
+        pres * (-G * (z - INVERSION_HEIGHT).max(0.0) / (R_D * temp)).exp()
+    }
+
+    fn drying_dewpoint(z: Float) -> Float {
+        (SURFACE_DEWPOINT - 0.05 * z).max(240.0)
+    }
+
+    fn isothermal_temperature(_: Float) -> Float {
+        SURFACE_TEMPERATURE
+    }
+
+    fn isothermal_pressure(z: Float) -> Float {
+        SURFACE_PRESSURE * (-G * z / (R_D * SURFACE_TEMPERATURE)).exp()
+    }
+
+    fn constant_dewpoint(_: Float) -> Float {
+        SURFACE_DEWPOINT
+    }
+
+    fn release_parcel(
+        profile: &AnalyticProfile,
+        buoyancy: BuoyancyFormulation,
+    ) -> ConvectiveParams {
+        release_parcel_with(profile, |config| config.parcel.buoyancy = buoyancy)
+    }
+
+    fn release_parcel_with(
+        profile: &AnalyticProfile,
+        configure: impl FnOnce(&mut Config),
+    ) -> ConvectiveParams {
+        release_parcel_logged(profile, configure, None)
+    }
+
+    fn release_parcel_logged(
+        profile: &AnalyticProfile,
+        configure: impl FnOnce(&mut Config),
+        trajectory_writer: Option<&TrajectoryWriter>,
+    ) -> ConvectiveParams {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+            domain:
+              ref_lat: 50.0
+              ref_lon: 20.0
+              spacing: 1000.0
+              shape: [1, 1]
+            datetime:
+              start: "2021-06-24T06:00:00"
+              timestep: 1.0
+            input:
+              level_type: "isobaricInhPa"
+              data_files: []
+            "#,
+        )
+        .unwrap();
+        configure(&mut config);
+
+        let heights: Vec<Float> = (0..=120).map(|i| Float::from(i) * 100.0).collect();
+        let env = Environment::from_analytic(&config.domain, &heights, profile).unwrap();
+
+        let start_coords = env
+            .projection
+            .project(config.domain.ref_lon, config.domain.ref_lat);
+
+        deploy(
+            start_coords,
+            (0, 0),
+            None,
+            &Arc::new(config),
+            &Arc::new(env),
+            trajectory_writer,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unidirectional_shear_helicity() {
+        // wind veering is absent, u grows by 10 m/s per km
+        let wind_profile: Vec<(Float, Float)> = (0..=60)
+            .map(|i| (Float::from(i) * WIND_PROFILE_STEP * 0.01, 0.0))
+            .collect();
+
+        let storm_motion = bunkers_storm_motion(&wind_profile);
+        assert_approx_eq!(Float, storm_motion.0, 30.0, epsilon = 1e-9);
+        assert_approx_eq!(Float, storm_motion.1, -BUNKERS_DEVIATION, epsilon = 1e-9);
+
+        // for straight hodograph SRH equals storm motion
+        // across the shear multiplied by wind change
+        let srh = storm_relative_helicity(&wind_profile, storm_motion, 1000.0);
+        assert_approx_eq!(Float, srh, BUNKERS_DEVIATION * 10.0, epsilon = 1e-9);
+
+        let srh = storm_relative_helicity(&wind_profile, storm_motion, 3000.0);
+        assert_approx_eq!(Float, srh, BUNKERS_DEVIATION * 30.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn conditionally_unstable_parcel() {
+        let params = release_parcel(
+            &AnalyticProfile {
+                temperature: adiabatic_temperature,
+                dewpoint: drying_dewpoint,
+                pressure: adiabatic_pressure,
+            },
+            BuoyancyFormulation::Virtual,
+        );
+
+        // Espy's approximation: 125 m per kelvin of dewpoint depression
+        let espy_lcl = 125.0 * (SURFACE_TEMPERATURE - SURFACE_DEWPOINT);
+        assert!((params.condens_lvl.unwrap() - espy_lcl).abs() < 100.0);
+
+        assert!(params.saturated);
+
+        // parcel is buoyant from the surface, so it is free to convect at LCL
+        assert_eq!(params.lfc, params.condens_lvl);
+
+        let el = params.el.unwrap();
+        assert!(el > INVERSION_HEIGHT && el < params.parcel_top);
+        assert_eq!(params.overshoot_depth, Some(params.parcel_top - el));
+
+        // environment is sampled at the parcel position at EL
+        let el_temperature = params.el_temperature.unwrap();
+        assert!((el_temperature - adiabatic_temperature(el)).abs() < 0.1);
+
+        let top = params.parcel_top;
+        assert!((params.parcel_top_env_temp - adiabatic_temperature(top)).abs() < 0.1);
+        assert!((params.parcel_top_pressure - adiabatic_pressure(top)).abs() < 50.0);
+
+        // parcel theory: kinetic energy at EL equals buoyant energy gained below
+        let buoyant_energy = params.cape.unwrap() - params.cin.unwrap();
+        let kinetic_energy = 0.5 * params.max_vert_vel.powi(2);
+        assert!(params.cape.unwrap() > 0.0);
+        assert!((kinetic_energy - buoyant_energy).abs() / buoyant_energy < 0.05);
+    }
+
+    #[test]
+    fn lifted_indices() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let params = release_parcel_with(&profile, |config| {
+            config.output.lifted_index_levels = vec![850.0, 50.0];
+        });
+
+        // above LCL saturated parcel is warmer than the dry adiabatic environment,
+        // while the top of environment is far below the second level
+        assert_eq!(params.lifted_indices().len(), 2);
+        assert!(params.lifted_indices()[0].unwrap() < 0.0);
+        assert_eq!(params.lifted_indices()[1], None);
+
+        let values = params.values(3);
+        assert_eq!(
+            values.len(),
+            ConvectiveParams::header(&[850.0, 50.0, 10.0]).len()
+        );
+        assert_eq!(
+            values[values.len() - 2..],
+            [ParamValue::Float(None), ParamValue::Float(None)]
+        );
+    }
+
+    #[test]
+    fn grid_node_release() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        // homogeneous environment has nodes every 0.25 degree,
+        // so the parcel is released exactly at a grid node
+        let params = release_parcel_with(&profile, |config| {
+            config.domain.ref_lon = 20.25;
+            config.domain.ref_lat = 50.5;
+        });
+
+        assert!(params.parcel_top > INVERSION_HEIGHT);
+        assert!(params.cape.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn pressure_levels() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let height_params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+        let pressure_params = release_parcel_with(&profile, |config| {
+            config.output.vertical_coordinate = VerticalCoordinate::Pressure;
+        });
+
+        // parcel pressure closely follows the environment
+        let el_pressure = adiabatic_pressure(height_params.el.unwrap());
+        assert!((pressure_params.el.unwrap() - el_pressure).abs() / el_pressure < 0.01);
+
+        assert!(pressure_params.condens_lvl.unwrap() < SURFACE_PRESSURE);
+        assert!(pressure_params.parcel_top < pressure_params.el.unwrap());
+        assert_eq!(pressure_params.cape, height_params.cape);
+    }
+
+    #[test]
+    fn elevated_release_agl_levels() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let release_with_coordinate = |coordinate| {
+            release_parcel_with(&profile, |config| {
+                config.parcel.release_level = ReleaseLevel::HeightAgl(100.0);
+                config.output.vertical_coordinate = coordinate;
+            })
+        };
+
+        let msl_params = release_with_coordinate(VerticalCoordinate::Msl);
+        let agl_params = release_with_coordinate(VerticalCoordinate::Agl);
+
+        // analytic surface is at the sea level, so heights AGL are
+        // not reduced by the height at which parcel was released
+        assert!(agl_params.el.is_some());
+        assert_eq!(agl_params.condens_lvl, msl_params.condens_lvl);
+        assert_eq!(agl_params.el, msl_params.el);
+        assert_eq!(agl_params.parcel_top, msl_params.parcel_top);
+    }
+
+    #[test]
+    fn wind_profile_above_data_top() {
+        // wind profile from release point reaches above the top of analytic data
+        let params = release_parcel_with(
+            &AnalyticProfile {
+                temperature: isothermal_temperature,
+                dewpoint: constant_dewpoint,
+                pressure: isothermal_pressure,
+            },
+            |config| config.parcel.release_level = ReleaseLevel::HeightAgl(7000.0),
+        );
+
+        assert!(params.parcel_top > 7000.0);
+        assert_eq!(params.srh_0_1km, None);
+        assert_eq!(params.srh_0_3km, None);
+        assert_eq!(params.bulk_shear_0_6km, None);
+    }
+
+    #[test]
+    fn log_pressure_integration() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let height_params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+        let pressure_params = release_parcel_with(&profile, |config| {
+            config.output.cape_integration = CapeIntegration::LogPressure;
+        });
+
+        // both formulations are equivalent in hydrostatic environment
+        let (height_cape, pressure_cape) =
+            (height_params.cape.unwrap(), pressure_params.cape.unwrap());
+        assert!(height_cape > 0.0);
+        assert!((pressure_cape - height_cape).abs() / height_cape < 0.01);
+
+        let (height_cin, pressure_cin) = (height_params.cin.unwrap(), pressure_params.cin.unwrap());
+        assert!((pressure_cin - height_cin).abs() / height_cin.abs() < 0.01);
+    }
+
+    #[test]
+    fn convective_temperature() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+
+        // in dry adiabatic layer the CCL is where the surface parcel
+        // condenses, and the dry adiabat from the CCL is the environment
+        let (ccl, condens_lvl) = (params.ccl.unwrap(), params.condens_lvl.unwrap());
+        assert!(ccl < INVERSION_HEIGHT);
+        assert!((ccl - condens_lvl).abs() < 100.0);
+        assert!((params.convective_temperature.unwrap() - SURFACE_TEMPERATURE).abs() < 0.5);
+
+        // both are found for the surface parcel regardless of release level
+        let elevated_params = release_parcel_with(&profile, |config| {
+            config.parcel.release_level = ReleaseLevel::HeightAgl(100.0);
+        });
+
+        assert_eq!(elevated_params.ccl, params.ccl);
+        assert_eq!(
+            elevated_params.convective_temperature,
+            params.convective_temperature
+        );
+    }
+
+    #[test]
+    fn overshooting_parcel() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+        assert!(!params.overshoot);
+
+        // above the EL parcel starts a saturated descent which does not
+        // recover, so steps of that descent are removed from the ascent
+        let overshoot_params = release_parcel_with(&profile, |config| {
+            config.parcel.overshoot_steps = 20;
+        });
+
+        assert!(!overshoot_params.overshoot);
+        assert_eq!(overshoot_params.el, params.el);
+        assert_eq!(overshoot_params.parcel_top, params.parcel_top);
+    }
+
+    #[test]
+    fn oscillating_parcel() {
+        // in isothermal layer parcel released above the surface oscillates
+        // around the release level with period of about 350 seconds
+        let profile = AnalyticProfile {
+            temperature: isothermal_temperature,
+            dewpoint: constant_dewpoint,
+            pressure: isothermal_pressure,
+        };
+
+        let release_above_surface = |config: &mut Config| {
+            config.parcel.release_level = ReleaseLevel::HeightAgl(1000.0);
+        };
+
+        let params = release_parcel_with(&profile, release_above_surface);
+        assert!(!params.overshoot);
+
+        // budget lasts for the descent, so the parcel ascends again
+        let overshoot_params = release_parcel_with(&profile, |config| {
+            release_above_surface(config);
+            config.parcel.overshoot_steps = 250;
+        });
+
+        assert!(overshoot_params.overshoot);
+        assert!(overshoot_params.step_count >= params.step_count + 250);
+        assert_approx_eq!(
+            Float,
+            overshoot_params.parcel_top,
+            params.parcel_top,
+            epsilon = 1.0
+        );
+    }
+
+    #[test]
+    fn stable_parcel() {
+        let params = release_parcel(
+            &AnalyticProfile {
+                temperature: isothermal_temperature,
+                dewpoint: constant_dewpoint,
+                pressure: isothermal_pressure,
+            },
+            BuoyancyFormulation::Virtual,
+        );
+
+        assert!(!params.saturated);
+        assert_eq!(params.condens_lvl, None);
+        assert_eq!(params.lfc, None);
+        assert_eq!(params.el, None);
+        assert_eq!(params.overshoot_depth, None);
+        assert_eq!(params.cape, Some(0.0));
+        assert_eq!(params.cin, Some(0.0));
+        assert!(params.parcel_top < 100.0);
+    }
+
+    #[test]
+    fn prescreened_parcels() {
+        let out_dir = env::temp_dir().join(format!("pats_prescreened_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let output = Output {
+            joined_output: true,
+            ..Output::default()
+        };
+        let writer = TrajectoryWriter::new(&output, &out_dir).unwrap();
+
+        let stable_params = release_parcel_logged(
+            &AnalyticProfile {
+                temperature: isothermal_temperature,
+                dewpoint: constant_dewpoint,
+                pressure: isothermal_pressure,
+            },
+            |config| config.run.prescreen = true,
+            Some(&writer),
+        );
+
+        assert!(stable_params.prescreened);
+        assert_eq!(stable_params.cape, Some(0.0));
+        assert_eq!(stable_params.step_count, 0);
+
+        // prescreened parcel is written to the joined output
+        writer.finish().unwrap();
+        let content = fs::read_to_string(out_dir.join("parcels_joined.csv")).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        fs::remove_dir_all(&out_dir).unwrap();
+
+        // parcel which becomes buoyant above the LCL is simulated
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+        let params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+        let prescreen_params = release_parcel_with(&profile, |config| {
+            config.run.prescreen = true;
+        });
+
+        assert!(!prescreen_params.prescreened);
+        assert_eq!(prescreen_params.cape, params.cape);
+    }
+
+    #[test]
+    fn water_loading() {
+        let profile = AnalyticProfile {
+            temperature: adiabatic_temperature,
+            dewpoint: drying_dewpoint,
+            pressure: adiabatic_pressure,
+        };
+
+        let virtual_params = release_parcel(&profile, BuoyancyFormulation::Virtual);
+        let density_params = release_parcel(&profile, BuoyancyFormulation::Density);
+
+        // condensate weight does not change the ascent below LCL
+        let lcl_difference =
+            virtual_params.condens_lvl.unwrap() - density_params.condens_lvl.unwrap();
+        assert!(lcl_difference.abs() < 1.0);
+        assert!(density_params.max_vert_vel < virtual_params.max_vert_vel);
+        assert!(density_params.parcel_top < virtual_params.parcel_top);
+    }
+
+    #[test]
+    fn params_header() {
+        // header is listed separately, so it is checked against serialized fields
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(ConvectiveParams::default()).unwrap();
+        let serialized = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let header = ConvectiveParams::header(&[]);
+        assert_eq!(serialized.lines().next().unwrap(), header.join(","));
+        assert_eq!(ConvectiveParams::default().values(0).len(), header.len());
+    }
+}
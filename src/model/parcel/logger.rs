@@ -21,12 +21,14 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //!
 //! (Why it is neccessary)
 
-use super::ParcelState;
+use super::{conv_params::ConvectiveParams, ParcelState};
 use crate::{
     errors::{EnvironmentError, ParcelError},
     model::{
+        configuration::{Compression, CoordinateFormat, CsvOutput, Output, TrajectoriesLayout},
+        csv_format::{self, CsvWriter},
         environment::{
-            EnvFields::{Temperature, VirtualTemperature},
+            EnvFields::{Temperature, UWind, VWind, VerticalVel, VirtualTemperature},
             Environment,
         },
         vec3::Vec3,
@@ -34,7 +36,195 @@ use crate::{
     Float,
 };
 use chrono::NaiveDateTime;
-use std::{path::Path, sync::Arc};
+use floccus::constants::{C_P, EPSILON, L_V};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// Header of the trajectory CSV file.
+const LOG_HEADER: [&str; 20] = [
+    "dateTime",
+    "longitude",
+    "latitude",
+    "height",
+    "velocityX",
+    "velocityY",
+    "velocityZ",
+    "pressure",
+    "temperature",
+    "mixingRatio",
+    "saturationMixingRatio",
+    "virtualTemperature",
+    "equivalentPotentialTemperature",
+    "wetBulbTemperature",
+    "buoyancy",
+    "envTemperature",
+    "envVirtualTemperature",
+    "envUWind",
+    "envVWind",
+    "envVerticalVelocity",
+];
+
+/// Output file shared by all parcels,
+/// which is taken out when it is finished.
+type SharedWriter = Mutex<Option<CsvWriter>>;
+
+/// Writer of parcels trajectories shared by all parcels.
+///
+/// In consolidated layout all trajectories are appended to one
+/// file, so the writer is guarded by a mutex and parcels
+/// write their whole trajectory at once. The same applies
+/// to the joined output file.
+#[derive(Debug)]
+pub struct TrajectoryWriter {
+    consolidated: Option<SharedWriter>,
+    joined: Option<SharedWriter>,
+    save_trajectories: bool,
+    out_dir: PathBuf,
+    csv_format: CsvOutput,
+    coordinate_format: CoordinateFormat,
+    compress: Compression,
+    lifted_index_count: usize,
+}
+
+impl TrajectoryWriter {
+    /// Creates the writer and, in consolidated layout or
+    /// with joined output, the output files with their headers
+    /// in `out_dir`.
+    pub fn new(output: &Output, out_dir: &Path) -> Result<Self, csv::Error> {
+        // with disabled output no files are created
+        let save_trajectories = output.enabled && output.save_trajectories;
+
+        let consolidated = if save_trajectories
+            && output.trajectories_layout == TrajectoriesLayout::Consolidated
+        {
+            let mut header = vec!["parcelId"];
+            header.extend_from_slice(&LOG_HEADER);
+
+            Some(Mutex::new(Some(create_output_file(
+                &out_dir.join("trajectories.csv"),
+                &header,
+                output,
+            )?)))
+        } else {
+            None
+        };
+
+        let joined = if output.enabled && output.joined_output {
+            let params_header = ConvectiveParams::header(&output.lifted_index_levels);
+
+            let mut header = vec!["parcelId"];
+            header.extend(params_header.iter().map(String::as_str));
+
+            if save_trajectories {
+                header.extend_from_slice(&LOG_HEADER);
+            }
+
+            Some(Mutex::new(Some(create_output_file(
+                &out_dir.join("parcels_joined.csv"),
+                &header,
+                output,
+            )?)))
+        } else {
+            None
+        };
+
+        Ok(TrajectoryWriter {
+            consolidated,
+            joined,
+            save_trajectories,
+            out_dir: out_dir.to_path_buf(),
+            csv_format: output.csv.clone(),
+            coordinate_format: output.coordinate_format,
+            compress: output.compress,
+            lifted_index_count: output.lifted_index_levels.len(),
+        })
+    }
+
+    /// Whether the parcel log needs to be written by any output.
+    pub fn is_enabled(&self) -> bool {
+        self.save_trajectories || self.joined.is_some()
+    }
+
+    /// Converts convective parameters (with Lifted Indices)
+    /// into fields formatted as configured.
+    fn params_fields(&self, params: &ConvectiveParams) -> Vec<String> {
+        csv_format::params_fields(
+            &params.values(self.lifted_index_count),
+            &self.csv_format,
+            self.coordinate_format,
+        )
+    }
+
+    /// Writes convective parameters of a parcel without simulated
+    /// trajectory (failed or prescreened) to the joined output,
+    /// with empty trajectory fields.
+    pub fn save_untraced_params(
+        &self,
+        parcel_id: &str,
+        params: &ConvectiveParams,
+    ) -> Result<(), csv::Error> {
+        if let Some(out_file) = &self.joined {
+            let params_record = self.params_fields(params);
+            let mut out_file = lock_writer(out_file);
+            let out_file = unfinished(&mut out_file)?;
+
+            // trajectory fields are left empty to match the header
+            let empty_record = if self.save_trajectories {
+                vec![String::new(); LOG_HEADER.len()]
+            } else {
+                vec![]
+            };
+
+            write_joined_record(out_file, parcel_id, &params_record, &empty_record)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the consolidated trajectories file and the joined
+    /// output file, so that parcels saved later fail to write.
+    pub fn finish(&self) -> Result<(), csv::Error> {
+        for out_file in [&self.consolidated, &self.joined].into_iter().flatten() {
+            if let Some(out_file) = lock_writer(out_file).take() {
+                csv_format::finish_writer(out_file)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Locks the shared output file.
+///
+/// Parcels are simulated with panics caught, so the mutex can be poisoned
+/// by a parcel that panicked. Records are written whole while locked,
+/// so the writer can still be used by the following parcels.
+fn lock_writer(out_file: &SharedWriter) -> MutexGuard<'_, Option<CsvWriter>> {
+    out_file.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Gets the locked output file, which is empty after it was finished.
+fn unfinished(out_file: &mut Option<CsvWriter>) -> Result<&mut CsvWriter, csv::Error> {
+    out_file
+        .as_mut()
+        .ok_or_else(|| io::Error::other("output file is already finished").into())
+}
+
+/// Creates CSV output file and writes its header.
+fn create_output_file(
+    out_path: &Path,
+    header: &[&str],
+    output: &Output,
+) -> Result<CsvWriter, csv::Error> {
+    let mut out_file = csv_format::create_writer(out_path, &output.csv, output.compress)?;
+
+    out_file.write_record(header)?;
+
+    Ok(out_file)
+}
 
 /// (TODO: What it is)
 ///
@@ -51,67 +241,145 @@ struct AnnotatedParcelState {
     mxng_rto: Float,
     satr_mxng_rto: Float,
     vrt_temp: Float,
+    theta_e: Float,
+    wet_bulb: Float,
+    buoyancy: Float,
     env_temp: Float,
     env_vrt_temp: Float,
+    env_u: Float,
+    env_v: Float,
+    env_w: Float,
 }
 
-/// (TODO: What it is)
-///
-/// (Why it is neccessary)
+/// Writes the parcel log to trajectories output
+/// and, together with its convective parameters,
+/// to the joined output.
 pub(super) fn save_parcel_log(
     parcel_log: &[ParcelState],
+    grid_index: (usize, usize),
+    member: Option<usize>,
+    params: &ConvectiveParams,
     environment: &Arc<Environment>,
+    trajectory_writer: &TrajectoryWriter,
 ) -> Result<(), ParcelError> {
-    let parcel_id = construct_parcel_id(parcel_log.first().unwrap(), environment);
-
-    let parcel_log = annotate_parcel_log(parcel_log, environment)?;
-
-    let out_path = format!("./output/{}.csv", parcel_id);
-    let out_path = Path::new(&out_path);
-
-    let mut out_file = csv::Writer::from_path(out_path)?;
-
-    out_file.write_record(&[
-        "dateTime",
-        "longitude",
-        "latitude",
-        "height",
-        "velocityX",
-        "velocityY",
-        "velocityZ",
-        "pressure",
-        "temperature",
-        "mixingRatio",
-        "saturationMixingRatio",
-        "virtualTemperature",
-        "envTemperature",
-        "envVirtualTemperature",
-    ])?;
+    let initial_state = parcel_log.first().unwrap();
+    let parcel_id = construct_parcel_id(
+        grid_index,
+        member,
+        (initial_state.position.x, initial_state.position.y),
+        initial_state.datetime,
+        environment,
+    );
 
-    for parcel in parcel_log {
-        out_file.write_record(&[
-            parcel.datetime.to_string(),
-            parcel.lon.to_string(),
-            parcel.lat.to_string(),
-            parcel.height.to_string(),
-            parcel.velocity.x.to_string(),
-            parcel.velocity.y.to_string(),
-            parcel.velocity.z.to_string(),
-            parcel.pres.to_string(),
-            parcel.temp.to_string(),
-            parcel.mxng_rto.to_string(),
-            parcel.satr_mxng_rto.to_string(),
-            parcel.vrt_temp.to_string(),
-            parcel.env_temp.to_string(),
-            parcel.env_vrt_temp.to_string(),
-        ])?;
-    }
-
-    out_file.flush()?;
+    let records: Vec<[String; 20]> = if trajectory_writer.save_trajectories {
+        let parcel_log = annotate_parcel_log(parcel_log, environment)?;
+        parcel_log
+            .iter()
+            .map(|parcel| parcel_record(parcel, &trajectory_writer.csv_format))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    if let Some(out_file) = &trajectory_writer.joined {
+        let params_record = trajectory_writer.params_fields(params);
+        let mut out_file = lock_writer(out_file);
+        let out_file = unfinished(&mut out_file)?;
+
+        if records.is_empty() {
+            write_joined_record(out_file, &parcel_id, &params_record, &[])?;
+        }
+
+        for record in &records {
+            write_joined_record(out_file, &parcel_id, &params_record, record)?;
+        }
+    }
+
+    if !trajectory_writer.save_trajectories {
+        return Ok(());
+    }
+
+    if let Some(out_file) = &trajectory_writer.consolidated {
+        // whole trajectory is written at once
+        // so that rows of different parcels don't interleave
+        let mut out_file = lock_writer(out_file);
+        let out_file = unfinished(&mut out_file)?;
+
+        for record in records {
+            out_file.write_field(&parcel_id)?;
+            out_file.write_record(&record)?;
+        }
+
+        return Ok(());
+    }
+
+    let out_path = trajectory_writer.out_dir.join(format!("{}.csv", parcel_id));
+
+    let mut out_file = csv_format::create_writer(
+        &out_path,
+        &trajectory_writer.csv_format,
+        trajectory_writer.compress,
+    )?;
+
+    out_file.write_record(LOG_HEADER)?;
+
+    for record in records {
+        out_file.write_record(&record)?;
+    }
+
+    csv_format::finish_writer(out_file)?;
 
     Ok(())
 }
 
+/// Writes one record of joined output, consisting of
+/// parcel id, its convective parameters and trajectory step.
+fn write_joined_record(
+    out_file: &mut CsvWriter,
+    parcel_id: &str,
+    params_record: &[String],
+    trajectory_record: &[String],
+) -> Result<(), csv::Error> {
+    out_file.write_field(parcel_id)?;
+
+    for field in params_record {
+        out_file.write_field(field)?;
+    }
+
+    out_file.write_record(trajectory_record)
+}
+
+/// Converts annotated parcel state into the CSV record,
+/// with floats rounded as configured.
+fn parcel_record(parcel: &AnnotatedParcelState, format: &CsvOutput) -> [String; 20] {
+    let format_float = |value: Float| {
+        csv_format::round_float(value, format.float_digits).unwrap_or(value.to_string())
+    };
+
+    [
+        parcel.datetime.to_string(),
+        format_float(parcel.lon),
+        format_float(parcel.lat),
+        format_float(parcel.height),
+        format_float(parcel.velocity.x),
+        format_float(parcel.velocity.y),
+        format_float(parcel.velocity.z),
+        format_float(parcel.pres),
+        format_float(parcel.temp),
+        format_float(parcel.mxng_rto),
+        format_float(parcel.satr_mxng_rto),
+        format_float(parcel.vrt_temp),
+        format_float(parcel.theta_e),
+        format_float(parcel.wet_bulb),
+        format_float(parcel.buoyancy),
+        format_float(parcel.env_temp),
+        format_float(parcel.env_vrt_temp),
+        format_float(parcel.env_u),
+        format_float(parcel.env_v),
+        format_float(parcel.env_w),
+    ]
+}
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
@@ -126,18 +394,46 @@ fn annotate_parcel_log(
             .projection
             .inverse_project(parcel.position.x, parcel.position.y);
 
-        let env_temp = environment.get_field_value(
+        let env_temp = environment.get_field_value_at(
             parcel.position.x,
             parcel.position.y,
             parcel.position.z,
             Temperature,
+            parcel.datetime,
         )?;
 
-        let env_vrt_temp = environment.get_field_value(
+        let env_vrt_temp = environment.get_field_value_at(
             parcel.position.x,
             parcel.position.y,
             parcel.position.z,
             VirtualTemperature,
+            parcel.datetime,
+        )?;
+
+        let env_u = environment.get_field_value_at(
+            parcel.position.x,
+            parcel.position.y,
+            parcel.position.z,
+            UWind,
+            parcel.datetime,
+        )?;
+
+        let env_v = environment.get_field_value_at(
+            parcel.position.x,
+            parcel.position.y,
+            parcel.position.z,
+            VWind,
+            parcel.datetime,
+        )?;
+
+        // vertical velocity is always buffered from input
+        // (as zeros when missing in input) so it can be sampled directly
+        let env_w = environment.get_field_value_at(
+            parcel.position.x,
+            parcel.position.y,
+            parcel.position.z,
+            VerticalVel,
+            parcel.datetime,
         )?;
 
         result_log.push(AnnotatedParcelState {
@@ -151,24 +447,204 @@ fn annotate_parcel_log(
             mxng_rto: parcel.mxng_rto,
             satr_mxng_rto: parcel.satr_mxng_rto,
             vrt_temp: parcel.vrt_temp,
+            theta_e: equivalent_potential_temperature(parcel.pres, parcel.temp, parcel.mxng_rto),
+            wet_bulb: wet_bulb_temperature(parcel.pres, parcel.temp, parcel.mxng_rto),
+            buoyancy: parcel.buoyancy,
             env_temp,
             env_vrt_temp,
+            env_u,
+            env_v,
+            env_w,
         });
     }
 
     Ok(result_log)
 }
 
-/// (TODO: What it is)
+/// Computes equivalent potential temperature
+/// with formula from Bolton (1980, eq. 43).
 ///
-/// (Why it is neccessary)
-fn construct_parcel_id(initial_state: &ParcelState, environment: &Arc<Environment>) -> String {
-    let time_stamp = initial_state.datetime.format("%Y-%m-%dT%H%M%S").to_string();
+/// Formulas from floccus are valid only above -20°C, while
+/// this value is needed along the whole parcel path to check
+/// that it is conserved during pseudoadiabatic ascent.
+fn equivalent_potential_temperature(pres: Float, temp: Float, mxng_rto: Float) -> Float {
+    let vap_pres = pres * mxng_rto / (EPSILON + mxng_rto);
+
+    // temperature at condensation level (Bolton, 1980, eq. 21)
+    let lcl_temp = 2840.0 / (3.5 * temp.ln() - (vap_pres / 100.0).ln() - 4.805) + 55.0;
+
+    // Bolton's formula uses mixing ratio in g/kg
+    let mxng_rto = mxng_rto * 1000.0;
+
+    temp * (100_000.0 / pres).powf(0.2854 * (1.0 - 0.28e-3 * mxng_rto))
+        * ((3.376 / lcl_temp - 0.00254) * mxng_rto * (1.0 + 0.81e-3 * mxng_rto)).exp()
+}
+
+/// Computes isobaric wet-bulb temperature by solving
+/// the psychrometric equation `c_p (T - T_w) = L_v (r_s(T_w) - r)`
+/// with bisection.
+///
+/// Saturation vapour pressure is computed with Bolton (1980) formula,
+/// which (unlike floccus formulas) is defined at all temperatures
+/// reached by parcels.
+fn wet_bulb_temperature(pres: Float, temp: Float, mxng_rto: Float) -> Float {
+    let imbalance = |wet_bulb: Float| {
+        let satr_vap_pres = 611.2 * (17.67 * (wet_bulb - 273.15) / (wet_bulb - 29.65)).exp();
+        let satr_mxng_rto = EPSILON * satr_vap_pres / (pres - satr_vap_pres);
+
+        C_P * (temp - wet_bulb) - L_V * (satr_mxng_rto - mxng_rto)
+    };
+
+    // wet-bulb temperature is always below the temperature
+    // and 100 K lower bound is far below any realistic value
+    let mut lower = temp - 100.0;
+    let mut upper = temp;
+
+    for _ in 0..50 {
+        let middle = 0.5 * (lower + upper);
+
+        if imbalance(middle) > 0.0 {
+            lower = middle;
+        } else {
+            upper = middle;
+        }
+    }
+
+    0.5 * (lower + upper)
+}
+
+/// Constructs the identifier of the parcel from its release
+/// position and time, used as a key in all output files.
+///
+/// Grid indices of the release point guarantee that ids are unique
+/// even when parcels are closer than the precision of coordinates.
+/// Perturbed parcels are additionally identified by the member index.
+pub fn construct_parcel_id(
+    grid_index: (usize, usize),
+    member: Option<usize>,
+    start_coords: (Float, Float),
+    start_time: NaiveDateTime,
+    environment: &Environment,
+) -> String {
+    let time_stamp = start_time.format("%Y-%m-%dT%H%M%S").to_string();
     let (lon, lat) = environment
         .projection
-        .inverse_project(initial_state.position.x, initial_state.position.y);
+        .inverse_project(start_coords.0, start_coords.1);
+
+    let position_stamp = format!(
+        "i{}_j{}_E{:.4}_N{:.4}",
+        grid_index.0, grid_index.1, lon, lat
+    );
+
+    match member {
+        Some(member) => format!("parcel_{}_m{}_{}", position_stamp, member, time_stamp),
+        None => format!("parcel_{}_{}", position_stamp, time_stamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{equivalent_potential_temperature, wet_bulb_temperature, TrajectoryWriter};
+    use crate::{
+        errors::ErrorCode,
+        model::{
+            configuration::{CoordinateFormat, CsvOutput, Output, TrajectoriesLayout},
+            csv_format,
+            parcel::conv_params::ConvectiveParams,
+        },
+        Float,
+    };
+    use floccus::mixing_ratio;
+    use std::{env, fs, panic, process};
+
+    #[test]
+    fn joined_params_fields() {
+        let params = ConvectiveParams::new_failed(20.0, 50.0, ErrorCode::Panic);
+        let header = ConvectiveParams::header(&[500.0]);
+        let record = csv_format::params_fields(
+            &params.values(1),
+            &CsvOutput::default(),
+            CoordinateFormat::default(),
+        );
+
+        assert_eq!(header.len(), record.len());
+        assert_eq!(header[0], "start_lon");
+        assert_eq!(record[0], "20.0");
+        assert_eq!(header[header.len() - 2], "error_code");
+        assert_eq!(record[header.len() - 2], "panic");
+        assert_eq!(record[header.len() - 1], "");
+    }
+
+    #[test]
+    fn disabled_output_files() {
+        let out_dir = env::temp_dir().join(format!("pats_disabled_output_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let output = Output {
+            enabled: false,
+            save_trajectories: true,
+            trajectories_layout: TrajectoriesLayout::Consolidated,
+            joined_output: true,
+            ..Output::default()
+        };
+
+        let writer = TrajectoryWriter::new(&output, &out_dir).unwrap();
 
-    let position_stamp = format!("N{:.4}_E{:.4}", lon, lat);
+        assert!(!writer.is_enabled());
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
 
-    format!("parcel_{}_{}", position_stamp, time_stamp)
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn poisoned_writer_recovery() {
+        let out_dir = env::temp_dir().join(format!("pats_poisoned_writer_{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let output = Output {
+            joined_output: true,
+            ..Output::default()
+        };
+
+        let writer = TrajectoryWriter::new(&output, &out_dir).unwrap();
+
+        // parcel panicking while holding the lock
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _out_file = writer.joined.as_ref().unwrap().lock().unwrap();
+            panic!("parcel panicked");
+        }));
+        assert!(panicked.is_err());
+
+        let params = ConvectiveParams::new_failed(20.0, 50.0, ErrorCode::Panic);
+        writer.save_untraced_params("parcel", &params).unwrap();
+        writer.finish().unwrap();
+
+        let content = fs::read_to_string(out_dir.join("parcels_joined.csv")).unwrap();
+        assert_eq!(content.lines().count(), 2);
+
+        // parcels saved after the file is finished are not written
+        assert!(writer.save_untraced_params("parcel", &params).is_err());
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn moist_diagnostics() {
+        let (pres, temp): (Float, Float) = (90_000.0, 295.0);
+
+        // saturated air has wet-bulb temperature equal to temperature
+        let satr_vap_pres: Float = 611.2 * (17.67 * 21.85 / (temp - 29.65)).exp();
+        let satr_mxng_rto = 0.622 * satr_vap_pres / (pres - satr_vap_pres);
+        assert!((wet_bulb_temperature(pres, temp, satr_mxng_rto) - temp).abs() < 0.05);
+
+        // unsaturated air has wet-bulb temperature between dewpoint and temperature
+        let mxng_rto = mixing_ratio::accuracy1(280.0, pres).unwrap();
+        let wet_bulb = wet_bulb_temperature(pres, temp, mxng_rto);
+        assert!(280.0 < wet_bulb && wet_bulb < temp);
+
+        // moisture increases equivalent potential temperature
+        let dry_theta_e = equivalent_potential_temperature(pres, temp, 1e-6);
+        let moist_theta_e = equivalent_potential_temperature(pres, temp, mxng_rto);
+        assert!(moist_theta_e - dry_theta_e > 20.0);
+    }
 }
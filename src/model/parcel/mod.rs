@@ -22,29 +22,40 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! (Why it is neccessary)
 
 pub(super) mod conv_params;
-mod logger;
+pub(super) mod logger;
+mod perturbation;
 mod runge_kutta;
 
-use self::conv_params::ConvectiveParams;
+use self::{
+    conv_params::ConvectiveParams, logger::TrajectoryWriter, perturbation::PerturbationRng,
+};
 use super::{
-    configuration::Config,
+    configuration::{Config, Perturbation, ReleaseLevel},
     environment::{
-        EnvFields::VerticalVel,
+        EnvFields::{self, VerticalVel},
         Environment,
         SurfaceFields::{Dewpoint, Height, Pressure, Temperature},
     },
     vec3::Vec3,
 };
-use crate::{errors::ParcelError, model::parcel::conv_params::compute_conv_params, Float};
+use crate::{
+    errors::{EnvironmentError, ParcelError, ParcelSimulationError, SearchError},
+    model::parcel::conv_params::compute_conv_params,
+    Float,
+};
 use chrono::NaiveDateTime;
 use floccus::{mixing_ratio, virtual_temperature};
 use log::debug;
-use runge_kutta::RungeKuttaDynamics;
-use std::sync::Arc;
+use runge_kutta::{buoyancy_possible, RungeKuttaDynamics};
+use std::{sync::Arc, time::Instant};
 
 #[cfg(feature = "3d")]
 use super::environment::SurfaceFields::{UWind, VWind};
 
+/// Smallest mixing ratio of perturbed parcel, as
+/// thermodynamic functions return an error for zero.
+const MIN_MXNG_RTO: Float = 1.0e-8;
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
@@ -57,44 +68,169 @@ struct ParcelState {
     temp: Float,
     mxng_rto: Float,
     satr_mxng_rto: Float,
+    cndns_mxng_rto: Float,
     vrt_temp: Float,
+
+    /// Buoyancy force (vertical acceleration, in m/s^2) acting
+    /// on the parcel in this state, NaN until it is computed.
+    buoyancy: Float,
 }
 
-/// (TODO: What it is)
+/// Simulates the ascent of parcel released at `start_coords`
+/// and computes its convective parameters.
 ///
-/// (Why it is neccessary)
+/// `grid_index` identifies the release point in output files,
+/// together with `member` index when the parcel is perturbed.
+/// The parcel log (or only parameters of prescreened parcel)
+/// is written only when `trajectory_writer` is given.
 pub fn deploy(
     start_coords: (Float, Float),
+    grid_index: (usize, usize),
+    member: Option<usize>,
     config: &Arc<Config>,
     environment: &Arc<Environment>,
+    trajectory_writer: Option<&TrajectoryWriter>,
 ) -> Result<ConvectiveParams, ParcelError> {
-    let initial_state = prepare_parcel(start_coords, config, environment)?;
+    let mut initial_state = prepare_parcel(start_coords, config, environment)?;
+
+    if let (Some(perturbation), Some(member)) = (&config.parcel.perturbation, member) {
+        let mut rng = PerturbationRng::new(perturbation.seed, grid_index, member);
+        perturb_parcel(&mut initial_state, &mut rng, perturbation)?;
+    }
+
+    if config.run.prescreen && !buoyancy_possible(&initial_state, config.thermo, environment) {
+        let (lon, lat) = environment
+            .projection
+            .inverse_project(start_coords.0, start_coords.1);
+
+        let mut parcel_params = ConvectiveParams::new_prescreened(lon, lat);
+        if config.output.edge_distance {
+            parcel_params.set_edge_distance(config.domain.edge_distance(grid_index));
+        }
+        parcel_params.set_member(member);
+
+        // prescreened parcels are written like failed ones
+        // so that the joined output grid is complete
+        if let Some(trajectory_writer) = trajectory_writer {
+            let parcel_id = logger::construct_parcel_id(
+                grid_index,
+                member,
+                start_coords,
+                config.datetime.start,
+                environment,
+            );
+            trajectory_writer.save_untraced_params(&parcel_id, &parcel_params)?;
+        }
+
+        return Ok(parcel_params);
+    }
 
-    let mut dynamic_scheme =
-        RungeKuttaDynamics::new(initial_state, config.datetime.timestep, environment);
+    let solver_start = Instant::now();
 
-    let parcel_result = dynamic_scheme.run_simulation();
+    let simulation = simulate_with_retries(
+        config.datetime.timestep,
+        config.parcel.timestep_retries,
+        |timestep, retries| {
+            let mut dynamic_scheme = RungeKuttaDynamics::new(
+                initial_state,
+                timestep,
+                config.parcel.buoyancy,
+                // overshoot is allowed for the same time as with configured timestep
+                config
+                    .parcel
+                    .overshoot_steps
+                    .saturating_mul(2u32.saturating_pow(retries)),
+                config.thermo,
+                environment,
+            );
+
+            dynamic_scheme.run_simulation()?;
+
+            Ok(dynamic_scheme)
+        },
+    );
 
     // if the parcel simulation stops with error
     // we report compute parcel's initial geographic
     // coords and return the error with that additional info
-    if let Err(err) = parcel_result {
+    let (dynamic_scheme, timestep_retries) = simulation.map_err(|err| {
         let (lon, lat) = environment
             .projection
             .inverse_project(start_coords.0, start_coords.1);
 
-        return Err(ParcelError::AscentStopped(lat, lon, err));
-    }
+        ParcelError::AscentStopped(lat, lon, err)
+    })?;
 
-    if cfg!(feature = "raw_output") {
-        logger::save_parcel_log(&dynamic_scheme.parcel_log, environment)?;
+    let solver_time = solver_start.elapsed();
+
+    let mut parcel_params =
+        compute_conv_params(&dynamic_scheme.parcel_log, environment, &config.output)?;
+
+    if config.output.edge_distance {
+        parcel_params.set_edge_distance(config.domain.edge_distance(grid_index));
     }
 
-    let parcel_params = compute_conv_params(&dynamic_scheme.parcel_log, environment)?;
+    parcel_params.set_member(member);
+    parcel_params.update_lifted_indices(
+        &dynamic_scheme.parcel_log,
+        environment,
+        &config.output.lifted_index_levels,
+    )?;
+    parcel_params.set_solver_stats(
+        dynamic_scheme.step_count,
+        config
+            .output
+            .solver_timing
+            .then_some(solver_time.as_micros() as u64),
+    );
+    parcel_params.set_timestep_retries(timestep_retries);
+
+    if let Some(trajectory_writer) = trajectory_writer.filter(|writer| writer.is_enabled()) {
+        logger::save_parcel_log(
+            &dynamic_scheme.parcel_log,
+            grid_index,
+            member,
+            &parcel_params,
+            environment,
+            trajectory_writer,
+        )?;
+    }
 
     Ok(parcel_params)
 }
 
+/// Runs the parcel simulation with given timestep and, when it stops
+/// with an error of thermodynamic computation, repeats it with halved
+/// timestep at most `max_retries` times.
+///
+/// Such errors are often caused by too large step near saturation.
+/// The simulation gets the timestep and the number of previous retries,
+/// and its result is returned together with the number of retries.
+fn simulate_with_retries<T>(
+    timestep: Float,
+    max_retries: u32,
+    mut simulate: impl FnMut(Float, u32) -> Result<T, ParcelSimulationError>,
+) -> Result<(T, u32), ParcelSimulationError> {
+    let mut timestep = timestep;
+    let mut retries = 0;
+
+    loop {
+        match simulate(timestep, retries) {
+            Ok(result) => return Ok((result, retries)),
+            Err(ParcelSimulationError::UnreasonableVariable(err)) if retries < max_retries => {
+                retries += 1;
+                timestep *= 0.5;
+
+                debug!(
+                    "Retrying parcel with timestep {} s after error: {}",
+                    timestep, err
+                );
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
@@ -104,38 +240,66 @@ fn prepare_parcel(
     environment: &Arc<Environment>,
 ) -> Result<ParcelState, ParcelError> {
     debug!("Preparing parcel at: {:?}", start_coords);
-    // currently, parcel deployed directly from surface
+    // parcel is deployed from surface or from the configured level
     // but then (configurable) mixed parcel
     let initial_time = config.datetime.start;
 
     let x_pos = start_coords.0;
     let y_pos = start_coords.1;
-    let z_pos = environment.get_surface_value(x_pos, y_pos, Height)?;
+    let surface_height = environment.get_surface_value(x_pos, y_pos, Height)?;
+
+    let (z_pos, pres, temp, mxng_rto) = match config.parcel.release_level {
+        ReleaseLevel::Surface => {
+            let pres = environment.get_surface_value(x_pos, y_pos, Pressure)?;
+            let temp = environment.get_surface_value(x_pos, y_pos, Temperature)?;
+            let dwpt = environment.get_surface_value(x_pos, y_pos, Dewpoint)?;
+
+            (
+                surface_height,
+                pres,
+                temp,
+                mixing_ratio::accuracy1(dwpt, pres)?,
+            )
+        }
+        ReleaseLevel::PressureHpa(level) => {
+            let z_pos = environment
+                .height_of_pressure(x_pos, y_pos, 100.0 * level, surface_height)
+                .map_err(|err| {
+                    out_of_column(
+                        err,
+                        "release pressure is not between the surface and the top of the column",
+                    )
+                })?;
+
+            level_thermodynamics(environment, x_pos, y_pos, z_pos)?
+        }
+        ReleaseLevel::HeightAgl(height) => {
+            level_thermodynamics(environment, x_pos, y_pos, surface_height + height)?
+        }
+    };
 
     #[cfg(feature = "3d")]
-    let x_vel = environment.get_surface_value(x_pos, y_pos, UWind)?;
-    #[cfg(feature = "3d")]
-    let y_vel = environment.get_surface_value(x_pos, y_pos, VWind)?;
+    let (x_vel, y_vel) = {
+        let (u_wind, v_wind) = release_wind(config, environment, x_pos, y_pos, z_pos)?;
+        environment.projection.rotate_vector(u_wind, v_wind)
+    };
 
     #[cfg(not(feature = "3d"))]
     let x_vel = 0.0;
     #[cfg(not(feature = "3d"))]
     let y_vel = 0.0;
 
-    // currently, constant initial vertical velocity (0.2 m/s)
-    // but then lifiting can be taken into account
-    // also as initial acceleration
-    let mut z_vel = 0.2;
-
-    if cfg!(feature = "env_vertical_motion") {
-        z_vel += environment.get_field_value(x_pos, y_pos, z_pos, VerticalVel)?;
-    }
-
-    let pres = environment.get_surface_value(x_pos, y_pos, Pressure)?;
-    let temp = environment.get_surface_value(x_pos, y_pos, Temperature)?;
-    let dwpt = environment.get_surface_value(x_pos, y_pos, Dewpoint)?;
+    // by default, constant initial vertical velocity (0.2 m/s)
+    // or environmental vertical velocity when configured
+    // lifiting can be also taken into account as initial acceleration
+    let z_vel = if config.parcel.use_environmental_w {
+        environment.get_field_value(x_pos, y_pos, z_pos, VerticalVel)?
+    } else if cfg!(feature = "env_vertical_motion") {
+        0.2 + environment.get_field_value(x_pos, y_pos, z_pos, VerticalVel)?
+    } else {
+        0.2
+    };
 
-    let mxng_rto = mixing_ratio::accuracy1(dwpt, pres)?;
     let satr_mxng_rto = mixing_ratio::accuracy1(temp, pres)?;
     let vrt_temp = virtual_temperature::general1(temp, mxng_rto)?;
 
@@ -155,6 +319,119 @@ fn prepare_parcel(
         temp,
         mxng_rto,
         satr_mxng_rto,
+        cndns_mxng_rto: 0.0,
         vrt_temp,
+        buoyancy: Float::NAN,
     })
 }
+
+/// Environmental wind at the release point, from surface fields
+/// when the parcel is released from the surface.
+///
+/// When surface wind is missing in input data, wind
+/// interpolated at the release height is used instead.
+#[cfg(feature = "3d")]
+fn release_wind(
+    config: &Arc<Config>,
+    environment: &Arc<Environment>,
+    x_pos: Float,
+    y_pos: Float,
+    z_pos: Float,
+) -> Result<(Float, Float), EnvironmentError> {
+    if config.parcel.release_level == ReleaseLevel::Surface {
+        match (
+            environment.get_surface_value(x_pos, y_pos, UWind),
+            environment.get_surface_value(x_pos, y_pos, VWind),
+        ) {
+            (Ok(u_wind), Ok(v_wind)) => return Ok((u_wind, v_wind)),
+            (Err(EnvironmentError::MissingData), _) | (_, Err(EnvironmentError::MissingData)) => (),
+            (Err(err), _) | (_, Err(err)) => return Err(err),
+        }
+    }
+
+    Ok((
+        environment.get_field_value(x_pos, y_pos, z_pos, EnvFields::UWind)?,
+        environment.get_field_value(x_pos, y_pos, z_pos, EnvFields::VWind)?,
+    ))
+}
+
+/// Adds random perturbations to temperature and mixing ratio
+/// of the parcel, and updates variables derived from them.
+///
+/// Mixing ratio is kept positive and not larger than saturation
+/// mixing ratio, so the parcel is not supersaturated.
+fn perturb_parcel(
+    parcel: &mut ParcelState,
+    rng: &mut PerturbationRng,
+    perturbation: &Perturbation,
+) -> Result<(), ParcelError> {
+    parcel.temp += rng.next_normal(perturbation.temp_sigma);
+    parcel.satr_mxng_rto = mixing_ratio::accuracy1(parcel.temp, parcel.pres)?;
+
+    parcel.mxng_rto = (parcel.mxng_rto + rng.next_normal(perturbation.q_sigma))
+        .max(MIN_MXNG_RTO)
+        .min(parcel.satr_mxng_rto);
+    parcel.vrt_temp = virtual_temperature::general1(parcel.temp, parcel.mxng_rto)?;
+
+    Ok(())
+}
+
+/// Reads pressure, temperature and mixing ratio of the environment
+/// at given height, to initialize parcel released above the surface.
+fn level_thermodynamics(
+    environment: &Environment,
+    x_pos: Float,
+    y_pos: Float,
+    z_pos: Float,
+) -> Result<(Float, Float, Float, Float), ParcelError> {
+    let field_value = |field| {
+        environment
+            .get_field_value(x_pos, y_pos, z_pos, field)
+            .map_err(|err| out_of_column(err, "release height is above the top of the column"))
+    };
+
+    let pres = field_value(EnvFields::Pressure)?;
+    let temp = field_value(EnvFields::Temperature)?;
+    let spec_humidity = field_value(EnvFields::SpecHumidity)?;
+
+    Ok((z_pos, pres, temp, spec_humidity / (1.0 - spec_humidity)))
+}
+
+/// Reports search outside of the column as
+/// the release level outside of the column.
+fn out_of_column(err: EnvironmentError, reason: &'static str) -> ParcelError {
+    match err {
+        EnvironmentError::SearchUnable(SearchError::OutOfBounds) => {
+            ParcelError::ReleaseLevelOutOfColumn(reason)
+        }
+        err => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::simulate_with_retries;
+    use crate::{errors::ParcelSimulationError, Float};
+    use floccus::errors::InputError;
+
+    #[test]
+    fn timestep_retries() {
+        // simulation fails with timestep larger than 0.3 s
+        let simulate = |timestep: Float, _| {
+            if timestep > 0.3 {
+                return Err(ParcelSimulationError::UnreasonableVariable(
+                    InputError::OutOfRange(String::from("temperature")),
+                ));
+            }
+
+            Ok(timestep)
+        };
+
+        assert_eq!(simulate_with_retries(1.0, 3, simulate).unwrap(), (0.25, 2));
+        assert_eq!(simulate_with_retries(0.2, 3, simulate).unwrap(), (0.2, 0));
+        assert!(matches!(
+            simulate_with_retries(1.0, 1, simulate),
+            Err(ParcelSimulationError::UnreasonableVariable(_))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Sub-module generating random perturbations
+//! of initial state of ensemble members.
+//!
+//! Parcels are simulated in random order on the threadpool,
+//! so each parcel has its own generator seeded from the configured
+//! seed, release point and member index. This makes perturbations
+//! reproducible regardless of threads scheduling.
+
+use crate::Float;
+use std::f64::consts::TAU;
+
+/// Small, seedable pseudo-random number generator
+/// (SplitMix64) drawing normally distributed values.
+#[derive(Clone, Debug)]
+pub(super) struct PerturbationRng {
+    state: u64,
+}
+
+impl PerturbationRng {
+    /// Creates the generator of perturbations of
+    /// given member released from `grid_index`.
+    pub fn new(seed: u64, grid_index: (usize, usize), member: usize) -> Self {
+        let mut rng = PerturbationRng { state: seed };
+
+        // each value is mixed separately, so that
+        // similar indices give unrelated states
+        for value in [grid_index.0, grid_index.1, member] {
+            rng.state ^= value as u64;
+            rng.state = rng.next_u64();
+        }
+
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    /// Draws value uniformly distributed in `(0, 1]`.
+    fn next_uniform(&mut self) -> Float {
+        ((self.next_u64() >> 11) + 1) as Float / (1u64 << 53) as Float
+    }
+
+    /// Draws value from normal distribution with zero mean
+    /// and given standard deviation, using Box-Muller transform.
+    pub fn next_normal(&mut self, sigma: Float) -> Float {
+        let radius = (-2.0 * self.next_uniform().ln()).sqrt();
+        let angle = TAU * self.next_uniform();
+
+        sigma * radius * angle.cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PerturbationRng;
+    use crate::Float;
+
+    #[test]
+    fn reproducible_normal_draws() {
+        let draws = |seed, grid_index, member| {
+            let mut rng = PerturbationRng::new(seed, grid_index, member);
+            (0..10_000)
+                .map(|_| rng.next_normal(2.0))
+                .collect::<Vec<Float>>()
+        };
+
+        let sample = draws(42, (3, 7), 1);
+
+        assert_eq!(sample, draws(42, (3, 7), 1));
+        assert_ne!(sample, draws(42, (3, 7), 2));
+        assert_ne!(sample, draws(42, (7, 3), 1));
+        assert_ne!(sample, draws(43, (3, 7), 1));
+
+        let count = sample.len() as Float;
+        let mean = sample.iter().sum::<Float>() / count;
+        let variance = sample.iter().map(|v| (v - mean).powi(2)).sum::<Float>() / count;
+
+        assert!(mean.abs() < 0.1);
+        assert!((variance.sqrt() - 2.0).abs() < 0.1);
+    }
+}
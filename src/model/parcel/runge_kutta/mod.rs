@@ -24,8 +24,12 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 mod schemes;
 
 use super::{ParcelState, Vec3};
-use crate::errors::ParcelSimulationError;
-use crate::model::environment::EnvFields::{UWind, VWind, VerticalVel, VirtualTemperature};
+use crate::errors::{EnvironmentError, ParcelSimulationError, SearchError};
+use crate::model::configuration::{BuoyancyFormulation, Thermo};
+use crate::model::environment::{
+    EnvFields::{UWind, VWind, VerticalVel, VirtualTemperature},
+    SearchCache,
+};
 use crate::{model::environment::Environment, Float};
 use chrono::Duration;
 use floccus::constants::G;
@@ -33,28 +37,48 @@ use log::debug;
 use schemes::{AdiabaticScheme, PseudoAdiabaticScheme};
 use std::sync::Arc;
 
+/// Vertical spacing (in meters) of levels at which
+/// the parcel is lifted in the column when pre-screening.
+const PRESCREEN_STEP: Float = 100.0;
+
 /// (TODO: What it is)
 ///
 /// (Why it is neccessary)
 #[derive(Clone, Debug)]
 pub(super) struct RungeKuttaDynamics<'a> {
     timestep: Float,
+    buoyancy: BuoyancyFormulation,
+    overshoot_budget: u32,
+    thermo: Thermo,
     env: &'a Arc<Environment>,
+    search_cache: SearchCache,
     pub parcel_log: Vec<ParcelState>,
+
+    /// Number of integration steps computed in all ascent
+    /// phases, including steps trimmed from the log.
+    pub step_count: u32,
 }
 
 impl<'a> RungeKuttaDynamics<'a> {
     pub fn new(
         initial_state: ParcelState,
         timestep: Float,
+        buoyancy: BuoyancyFormulation,
+        overshoot_steps: u32,
+        thermo: Thermo,
         environment: &'a Arc<Environment>,
     ) -> Self {
         let parcel_log = vec![initial_state];
 
         RungeKuttaDynamics {
             timestep,
+            buoyancy,
+            overshoot_budget: overshoot_steps,
+            thermo,
             env: environment,
+            search_cache: SearchCache::default(),
             parcel_log,
+            step_count: 0,
         }
     }
 
@@ -72,9 +96,48 @@ impl<'a> RungeKuttaDynamics<'a> {
         // so changing ascent for performance and accuracy
         self.ascent_adiabatically()?;
 
+        // ascent can also end in the middle of overshoot
+        // when parcel phase changes, so it is trimmed here too
+        self.trim_descent();
+
+        // buoyancy is assigned to the reference state at the beginning
+        // of each step, so the final state needs to be computed separately
+        let final_state = *self.parcel_log.last().unwrap();
+        if final_state.buoyancy.is_nan() {
+            self.parcel_log.last_mut().unwrap().buoyancy =
+                self.calculate_bouyancy_force(&final_state)?.z;
+        }
+
         Ok(())
     }
 
+    /// Checks if the parcel ascent ends at the given state.
+    ///
+    /// Parcel with non-positive vertical velocity can continue the ascent
+    /// as long as there are overshoot steps left. When the ascent ends
+    /// steps in which the parcel was descending are removed from the log.
+    fn ascent_stopped(&mut self, parcel: &ParcelState) -> bool {
+        if parcel.velocity.z > 0.0 {
+            return false;
+        }
+
+        if self.overshoot_budget == 0 {
+            self.trim_descent();
+            return true;
+        }
+
+        self.overshoot_budget -= 1;
+        false
+    }
+
+    /// Removes trailing states with non-positive vertical
+    /// velocity from the log, so that it ends at the parcel top.
+    fn trim_descent(&mut self) {
+        while self.parcel_log.len() > 1 && self.parcel_log.last().unwrap().velocity.z <= 0.0 {
+            self.parcel_log.pop();
+        }
+    }
+
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
@@ -88,74 +151,88 @@ impl<'a> RungeKuttaDynamics<'a> {
         debug!("Starting adiabatic ascent");
         debug!("Init state: {:?}", initial_state);
 
-        let adiabatic_scheme = AdiabaticScheme::new(initial_state, self.env);
+        let adiabatic_scheme = AdiabaticScheme::new(initial_state, self.thermo, self.env);
 
         loop {
+            self.step_count += 1;
             let ref_parcel = *self.parcel_log.last().unwrap();
+            let half_step = Duration::milliseconds((0.5 * self.timestep * 1000.0) as i64);
+            let full_step = Duration::milliseconds((self.timestep * 1000.0) as i64);
 
             // holographic parcel is a virtual parcel that is moved
             // around for RK4 computations but doesn't change its
             // thermodynamic properties in reference to the prestep state
             let holo_parcel = ref_parcel;
-            let c_0 = ref_parcel.velocity;
-            let k_0 =
-                self.calculate_bouyancy_force(&adiabatic_scheme.state_at_position(&holo_parcel)?)?;
+            let c_0 = self.advected_velocity(&holo_parcel, ref_parcel.velocity)?;
+            let k_0 = self.calculate_bouyancy_force(
+                &adiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
+            )?;
+
+            // force at the first sub-step is the buoyancy of reference state
+            self.parcel_log.last_mut().unwrap().buoyancy = k_0.z;
 
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += half_step;
             holo_parcel.position += 0.5 * self.timestep * c_0;
-            let c_1 = ref_parcel.velocity + 0.5 * self.timestep * k_0;
-            let k_1 =
-                self.calculate_bouyancy_force(&adiabatic_scheme.state_at_position(&holo_parcel)?)?;
+            let c_1 = self.advected_velocity(
+                &holo_parcel,
+                ref_parcel.velocity + 0.5 * self.timestep * k_0,
+            )?;
+            let k_1 = self.calculate_bouyancy_force(
+                &adiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
+            )?;
 
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += half_step;
             holo_parcel.position += 0.5 * self.timestep * c_1;
-            let c_2 = ref_parcel.velocity + 0.5 * self.timestep * k_1;
-            let k_2 =
-                self.calculate_bouyancy_force(&adiabatic_scheme.state_at_position(&holo_parcel)?)?;
+            let c_2 = self.advected_velocity(
+                &holo_parcel,
+                ref_parcel.velocity + 0.5 * self.timestep * k_1,
+            )?;
+            let k_2 = self.calculate_bouyancy_force(
+                &adiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
+            )?;
 
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += full_step;
             holo_parcel.position += self.timestep * c_2;
-            let c_3 = ref_parcel.velocity + self.timestep * k_2;
-            let k_3 =
-                self.calculate_bouyancy_force(&adiabatic_scheme.state_at_position(&holo_parcel)?)?;
+            let c_3 =
+                self.advected_velocity(&holo_parcel, ref_parcel.velocity + self.timestep * k_2)?;
+            let k_3 = self.calculate_bouyancy_force(
+                &adiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
+            )?;
 
             let delta_pos = (self.timestep / 6.0) * (c_0 + 2.0 * c_1 + 2.0 * c_2 + c_3);
             let delta_vel = (self.timestep / 6.0) * (k_0 + 2.0 * k_1 + 2.0 * k_2 + k_3);
 
             let mut result_parcel = ref_parcel;
-            result_parcel.datetime += Duration::milliseconds((self.timestep * 1000.0) as i64);
+            result_parcel.datetime += full_step;
             result_parcel.position += delta_pos;
             result_parcel.velocity += delta_vel;
 
-            if cfg!(feature = "3d") {
-                result_parcel.velocity.x = self.env.get_field_value(
-                    result_parcel.position.x,
-                    result_parcel.position.y,
-                    result_parcel.position.z,
-                    UWind,
-                )?;
-
-                result_parcel.velocity.y = self.env.get_field_value(
-                    result_parcel.position.x,
-                    result_parcel.position.y,
-                    result_parcel.position.z,
-                    VWind,
-                )?;
-            }
+            // horizontal velocity of the parcel is the environmental
+            // wind at its new position
+            result_parcel.velocity =
+                self.advected_velocity(&result_parcel, result_parcel.velocity)?;
 
             if cfg!(feature = "env_vertical_motion") {
-                result_parcel.velocity.z += self.env.get_field_value(
+                result_parcel.velocity.z += self.env.get_field_value_cached_at(
                     result_parcel.position.x,
                     result_parcel.position.y,
                     result_parcel.position.z,
                     VerticalVel,
+                    result_parcel.datetime,
+                    &self.search_cache,
                 )?;
             }
 
-            result_parcel = adiabatic_scheme.state_at_position(&result_parcel)?;
+            result_parcel =
+                adiabatic_scheme.state_at_position(&result_parcel, &self.search_cache)?;
 
-            if result_parcel.velocity.z <= 0.0
-                || result_parcel.mxng_rto > result_parcel.satr_mxng_rto
+            // phase change is checked first, so that the state which
+            // is not logged does not use the overshoot budget
+            if result_parcel.mxng_rto > result_parcel.satr_mxng_rto
+                || self.ascent_stopped(&result_parcel)
             {
                 break;
             }
@@ -172,84 +249,96 @@ impl<'a> RungeKuttaDynamics<'a> {
     fn ascent_pseudoadiabatically(&mut self) -> Result<(), ParcelSimulationError> {
         let initial_state = self.parcel_log.last().unwrap();
 
-        if initial_state.velocity.z <= 0.0 || initial_state.mxng_rto < 0.000_001 {
+        if initial_state.velocity.z <= 0.0 || initial_state.mxng_rto < self.thermo.min_mixing_ratio
+        {
             return Ok(());
         }
 
         debug!("Starting pseudoadiabatic ascent");
         debug!("Init state: {:?}", initial_state);
 
-        let mut pseudoadiabatic_scheme = PseudoAdiabaticScheme::new(initial_state, self.env);
+        let mut pseudoadiabatic_scheme =
+            PseudoAdiabaticScheme::new(initial_state, self.thermo, self.env);
 
         loop {
+            self.step_count += 1;
             let ref_parcel = *self.parcel_log.last().unwrap();
+            let half_step = Duration::milliseconds((0.5 * self.timestep * 1000.0) as i64);
+            let full_step = Duration::milliseconds((self.timestep * 1000.0) as i64);
 
             // holographic parcel is a virtual parcel that is moved
             // around for RK4 computations but doesn't change its
             // thermodynamic properties in reference to the prestep state
             let holo_parcel = ref_parcel;
-            let c_0 = ref_parcel.velocity;
+            let c_0 = self.advected_velocity(&holo_parcel, ref_parcel.velocity)?;
             let k_0 = self.calculate_bouyancy_force(
-                &pseudoadiabatic_scheme.state_at_position(&holo_parcel)?,
+                &pseudoadiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
             )?;
 
+            // force at the first sub-step is the buoyancy of reference state
+            self.parcel_log.last_mut().unwrap().buoyancy = k_0.z;
+
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += half_step;
             holo_parcel.position += 0.5 * self.timestep * c_0;
-            let c_1 = ref_parcel.velocity + 0.5 * self.timestep * k_0;
+            let c_1 = self.advected_velocity(
+                &holo_parcel,
+                ref_parcel.velocity + 0.5 * self.timestep * k_0,
+            )?;
             let k_1 = self.calculate_bouyancy_force(
-                &pseudoadiabatic_scheme.state_at_position(&holo_parcel)?,
+                &pseudoadiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
             )?;
 
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += half_step;
             holo_parcel.position += 0.5 * self.timestep * c_1;
-            let c_2 = ref_parcel.velocity + 0.5 * self.timestep * k_1;
+            let c_2 = self.advected_velocity(
+                &holo_parcel,
+                ref_parcel.velocity + 0.5 * self.timestep * k_1,
+            )?;
             let k_2 = self.calculate_bouyancy_force(
-                &pseudoadiabatic_scheme.state_at_position(&holo_parcel)?,
+                &pseudoadiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
             )?;
 
             let mut holo_parcel = ref_parcel;
+            holo_parcel.datetime += full_step;
             holo_parcel.position += self.timestep * c_2;
-            let c_3 = ref_parcel.velocity + self.timestep * k_2;
+            let c_3 =
+                self.advected_velocity(&holo_parcel, ref_parcel.velocity + self.timestep * k_2)?;
             let k_3 = self.calculate_bouyancy_force(
-                &pseudoadiabatic_scheme.state_at_position(&holo_parcel)?,
+                &pseudoadiabatic_scheme.state_at_position(&holo_parcel, &self.search_cache)?,
             )?;
 
             let delta_pos = (self.timestep / 6.0) * (c_0 + 2.0 * c_1 + 2.0 * c_2 + c_3);
             let delta_vel = (self.timestep / 6.0) * (k_0 + 2.0 * k_1 + 2.0 * k_2 + k_3);
 
             let mut result_parcel = ref_parcel;
-            result_parcel.datetime += Duration::milliseconds((self.timestep * 1000.0) as i64);
+            result_parcel.datetime += full_step;
             result_parcel.position += delta_pos;
             result_parcel.velocity += delta_vel;
 
-            if cfg!(feature = "3d") {
-                result_parcel.velocity.x = self.env.get_field_value(
-                    result_parcel.position.x,
-                    result_parcel.position.y,
-                    result_parcel.position.z,
-                    UWind,
-                )?;
-
-                result_parcel.velocity.y = self.env.get_field_value(
-                    result_parcel.position.x,
-                    result_parcel.position.y,
-                    result_parcel.position.z,
-                    VWind,
-                )?;
-            }
+            // horizontal velocity of the parcel is the environmental
+            // wind at its new position
+            result_parcel.velocity =
+                self.advected_velocity(&result_parcel, result_parcel.velocity)?;
 
             if cfg!(feature = "env_vertical_motion") {
-                result_parcel.velocity.z += self.env.get_field_value(
+                result_parcel.velocity.z += self.env.get_field_value_cached_at(
                     result_parcel.position.x,
                     result_parcel.position.y,
                     result_parcel.position.z,
                     VerticalVel,
+                    result_parcel.datetime,
+                    &self.search_cache,
                 )?;
             }
 
-            result_parcel = pseudoadiabatic_scheme.state_at_position(&result_parcel)?;
+            result_parcel =
+                pseudoadiabatic_scheme.state_at_position(&result_parcel, &self.search_cache)?;
 
-            if result_parcel.velocity.z <= 0.0 || result_parcel.mxng_rto < 0.000_001 {
+            if result_parcel.mxng_rto < self.thermo.min_mixing_ratio
+                || self.ascent_stopped(&result_parcel)
+            {
                 break;
             }
 
@@ -260,6 +349,47 @@ impl<'a> RungeKuttaDynamics<'a> {
         Ok(())
     }
 
+    /// Computes the velocity with which parcel
+    /// at given position (and time) is moved.
+    ///
+    /// Vertical velocity comes from the integration of buoyancy force.
+    /// In 3D mode parcel is advected horizontally by the environmental wind,
+    /// so horizontal components are replaced with the wind sampled
+    /// at the provided position. This function is used at each RK4
+    /// sub-step, so that the horizontal displacement is integrated
+    /// with wind at sub-step positions (as in semi-Lagrangian advection),
+    /// instead of using the wind from previous step.
+    fn advected_velocity(
+        &self,
+        parcel: &ParcelState,
+        velocity: Vec3,
+    ) -> Result<Vec3, ParcelSimulationError> {
+        let mut velocity = velocity;
+
+        if cfg!(feature = "3d") {
+            let u_wind = self.env.get_field_value_cached_at(
+                parcel.position.x,
+                parcel.position.y,
+                parcel.position.z,
+                UWind,
+                parcel.datetime,
+                &self.search_cache,
+            )?;
+            let v_wind = self.env.get_field_value_cached_at(
+                parcel.position.x,
+                parcel.position.y,
+                parcel.position.z,
+                VWind,
+                parcel.datetime,
+                &self.search_cache,
+            )?;
+
+            (velocity.x, velocity.y) = self.env.projection.rotate_vector(u_wind, v_wind);
+        }
+
+        Ok(velocity)
+    }
+
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
@@ -267,13 +397,25 @@ impl<'a> RungeKuttaDynamics<'a> {
         &self,
         parcel: &ParcelState,
     ) -> Result<Vec3, ParcelSimulationError> {
-        let tv_env = self.env.get_field_value(
+        let tv_env = self.env.get_field_value_cached_at(
             parcel.position.x,
             parcel.position.y,
             parcel.position.z,
             VirtualTemperature,
+            parcel.datetime,
+            &self.search_cache,
         )?;
-        let bouyancy_force = G * ((parcel.vrt_temp - tv_env) / tv_env);
+
+        // density temperature includes the weight of condensed water
+        let parcel_temp = match self.buoyancy {
+            BuoyancyFormulation::Virtual => parcel.vrt_temp,
+            BuoyancyFormulation::Density => {
+                parcel.vrt_temp * (1.0 + parcel.mxng_rto)
+                    / (1.0 + parcel.mxng_rto + parcel.cndns_mxng_rto)
+            }
+        };
+
+        let bouyancy_force = G * ((parcel_temp - tv_env) / tv_env);
 
         Ok(Vec3 {
             x: 0.0,
@@ -282,3 +424,77 @@ impl<'a> RungeKuttaDynamics<'a> {
         })
     }
 }
+
+/// Checks whether the parcel released in given state can have CAPE,
+/// by lifting it vertically in the environment column above the release
+/// point, without integrating its motion.
+///
+/// The parcel has no CAPE when above its condensation level it is nowhere
+/// warmer (in virtual temperature) than the environment. Errors are left
+/// for the full simulation, so the check is then passed.
+pub(super) fn buoyancy_possible(
+    initial_state: &ParcelState,
+    thermo: Thermo,
+    environment: &Arc<Environment>,
+) -> bool {
+    match lift_in_column(initial_state, thermo, environment) {
+        Ok(buoyant) => buoyant,
+        // parcel reached the top of the column without becoming buoyant
+        Err(ParcelSimulationError::EnvironmentAccess(EnvironmentError::SearchUnable(
+            SearchError::OutOfBounds,
+        ))) => false,
+        Err(_) => true,
+    }
+}
+
+/// Lifts the parcel adiabatically until saturation and pseudoadiabatically
+/// above, returning at the first saturated level where it is buoyant.
+fn lift_in_column(
+    initial_state: &ParcelState,
+    thermo: Thermo,
+    environment: &Arc<Environment>,
+) -> Result<bool, ParcelSimulationError> {
+    let search_cache = SearchCache::default();
+    let adiabatic_scheme = AdiabaticScheme::new(initial_state, thermo, environment);
+    let mut pseudoadiabatic_scheme = PseudoAdiabaticScheme::new(initial_state, thermo, environment);
+
+    let mut parcel = *initial_state;
+    let mut saturated = false;
+
+    loop {
+        let tv_env = environment.get_field_value_cached_at(
+            parcel.position.x,
+            parcel.position.y,
+            parcel.position.z,
+            VirtualTemperature,
+            parcel.datetime,
+            &search_cache,
+        )?;
+
+        // LFC is searched only from the condensation level
+        if saturated && parcel.vrt_temp > tv_env {
+            return Ok(true);
+        }
+
+        let mut lifted = parcel;
+        lifted.position.z += PRESCREEN_STEP;
+
+        if !saturated {
+            lifted = adiabatic_scheme.state_at_position(&lifted, &search_cache)?;
+            saturated = lifted.mxng_rto > lifted.satr_mxng_rto;
+
+            // as in the simulation, pseudoadiabatic ascent
+            // starts from the last unsaturated state
+            if saturated {
+                pseudoadiabatic_scheme.update_ref_state(&parcel);
+            }
+        }
+
+        if saturated {
+            lifted = pseudoadiabatic_scheme.state_at_position(&lifted, &search_cache)?;
+            pseudoadiabatic_scheme.update_ref_state(&lifted);
+        }
+
+        parcel = lifted;
+    }
+}
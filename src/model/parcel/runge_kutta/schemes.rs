@@ -22,11 +22,14 @@ along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/
 //! (Why it is neccessary)
 
 use super::ParcelState;
+use crate::constants::L_S;
 use crate::errors::ParcelSimulationError;
-use crate::model::environment::EnvFields::Pressure;
+use crate::model::configuration::{SaturationFormula, Thermo};
+use crate::model::environment::{EnvFields::Pressure, SearchCache};
 use crate::{model::environment::Environment, Float};
 use floccus::{
-    constants::{C_P, C_PV, C_V, C_VV, EPSILON, L_V, R_D},
+    constants::{C_P, C_PV, C_V, C_VV, EPSILON, L_V, R_D, ZERO_CELSIUS},
+    errors::InputError,
     mixing_ratio, vapour_pressure, virtual_temperature,
 };
 use std::sync::Arc;
@@ -38,6 +41,7 @@ use std::sync::Arc;
 pub(super) struct AdiabaticScheme<'a> {
     lambda: Float,
     gamma: Float,
+    thermo: Thermo,
     env: &'a Arc<Environment>,
 }
 
@@ -45,7 +49,7 @@ impl<'a> AdiabaticScheme<'a> {
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    pub fn new(refrence: &ParcelState, environment: &'a Arc<Environment>) -> Self {
+    pub fn new(refrence: &ParcelState, thermo: Thermo, environment: &'a Arc<Environment>) -> Self {
         let gamma = (C_P * ((1.0 + refrence.mxng_rto * (C_PV / C_P)) / (1.0 + refrence.mxng_rto)))
             / (C_V * ((1.0 + refrence.mxng_rto * (C_VV / C_V)) / (1.0 + refrence.mxng_rto)));
 
@@ -54,6 +58,7 @@ impl<'a> AdiabaticScheme<'a> {
         Self {
             lambda,
             gamma,
+            thermo,
             env: environment,
         }
     }
@@ -64,30 +69,27 @@ impl<'a> AdiabaticScheme<'a> {
     pub fn state_at_position(
         &self,
         ref_state: &ParcelState,
+        search_cache: &SearchCache,
     ) -> Result<ParcelState, ParcelSimulationError> {
         let mut updated_state = *ref_state;
 
-        updated_state.pres = self.env.get_field_value(
+        updated_state.pres = self.env.get_field_value_cached_at(
             ref_state.position.x,
             ref_state.position.y,
             ref_state.position.z,
             Pressure,
+            ref_state.datetime,
+            search_cache,
         )?;
 
         updated_state.temp =
             (self.lambda / updated_state.pres.powf(1.0 - self.gamma)).powf(1.0 / self.gamma);
 
-        let satr_vap_pres;
-        if updated_state.temp > 273.15 {
-            // for most ranges use usual buck formula over water
-            satr_vap_pres = vapour_pressure::buck1(updated_state.temp, updated_state.pres)?;
-        } else if updated_state.temp > 193.0 {
-            // if the temperature is very low use dedicated formula
-            satr_vap_pres = vapour_pressure::buck2(updated_state.temp, updated_state.pres)?;
-        } else {
-            // as last resort if the temperature is very very low use more expensive dedicated formula
-            satr_vap_pres = vapour_pressure::wexler2(updated_state.temp)?;
-        }
+        let satr_vap_pres = parcel_saturation_vapour_pressure(
+            updated_state.temp,
+            updated_state.pres,
+            &self.thermo,
+        )?;
 
         updated_state.satr_mxng_rto = mixing_ratio::general1(updated_state.pres, satr_vap_pres)?;
         updated_state.vrt_temp =
@@ -106,6 +108,7 @@ pub(super) struct PseudoAdiabaticScheme<'a> {
     ref_pres: Float,
     ref_mxng_rto: Float,
     ref_satr_mxng_rto: Float,
+    thermo: Thermo,
     env: &'a Arc<Environment>,
 }
 
@@ -113,13 +116,14 @@ impl<'a> PseudoAdiabaticScheme<'a> {
     /// (TODO: What it is)
     ///
     /// (Why it is neccessary)
-    pub fn new(refrence: &ParcelState, environment: &'a Arc<Environment>) -> Self {
+    pub fn new(refrence: &ParcelState, thermo: Thermo, environment: &'a Arc<Environment>) -> Self {
         PseudoAdiabaticScheme {
             ref_temp: refrence.temp,
             ref_pres: refrence.pres,
             env: environment,
             ref_mxng_rto: refrence.mxng_rto,
             ref_satr_mxng_rto: refrence.satr_mxng_rto,
+            thermo,
         }
     }
 
@@ -139,35 +143,33 @@ impl<'a> PseudoAdiabaticScheme<'a> {
     pub fn state_at_position(
         &self,
         ref_state: &ParcelState,
+        search_cache: &SearchCache,
     ) -> Result<ParcelState, ParcelSimulationError> {
         let mut updated_state = *ref_state;
 
-        updated_state.pres = self.env.get_field_value(
+        updated_state.pres = self.env.get_field_value_cached_at(
             ref_state.position.x,
             ref_state.position.y,
             ref_state.position.z,
             Pressure,
+            ref_state.datetime,
+            search_cache,
         )?;
 
         updated_state.temp = self.iterate_to_temperature(updated_state.pres);
 
-        let satr_vap_pres;
-        if updated_state.temp > 273.15 {
-            // for most ranges use usual buck formula over water
-            satr_vap_pres = vapour_pressure::buck1(updated_state.temp, updated_state.pres)?;
-        } else if updated_state.temp > 193.0 {
-            // if the temperature is very low use dedicated formula
-            satr_vap_pres = vapour_pressure::buck2(updated_state.temp, updated_state.pres)?;
-        } else {
-            // as last resort if the temperature is very very low use more expensive dedicated formula
-            satr_vap_pres = vapour_pressure::wexler2(updated_state.temp)?;
-        }
+        let satr_vap_pres = parcel_saturation_vapour_pressure(
+            updated_state.temp,
+            updated_state.pres,
+            &self.thermo,
+        )?;
 
         updated_state.satr_mxng_rto = mixing_ratio::general1(updated_state.pres, satr_vap_pres)?;
 
         // if saturation mixing ratio dropped we bring the parcel back to
-        // 100% saturation
+        // 100% saturation and keep track of condensed water
         if updated_state.satr_mxng_rto < updated_state.mxng_rto {
+            updated_state.cndns_mxng_rto += updated_state.mxng_rto - updated_state.satr_mxng_rto;
             updated_state.mxng_rto = updated_state.satr_mxng_rto;
         }
 
@@ -190,30 +192,10 @@ impl<'a> PseudoAdiabaticScheme<'a> {
         // throughout the derivation we're keeping mixing ratios constant
         // as the derivative is a partial derivative of the pressure and temperature
         for _ in 0..step_count {
-            let k_0 = pseudoadiabatic_derivative(
-                temp_n,
-                pres_n,
-                self.ref_mxng_rto,
-                self.ref_satr_mxng_rto,
-            );
-            let k_1 = pseudoadiabatic_derivative(
-                temp_n + 0.5 * step * k_0,
-                pres_n + 0.5 * step,
-                self.ref_mxng_rto,
-                self.ref_satr_mxng_rto,
-            );
-            let k_2 = pseudoadiabatic_derivative(
-                temp_n + 0.5 * step * k_1,
-                pres_n + 0.5 * step,
-                self.ref_mxng_rto,
-                self.ref_satr_mxng_rto,
-            );
-            let k_3 = pseudoadiabatic_derivative(
-                temp_n + step * k_2,
-                pres_n + step,
-                self.ref_mxng_rto,
-                self.ref_satr_mxng_rto,
-            );
+            let k_0 = self.derivative(temp_n, pres_n);
+            let k_1 = self.derivative(temp_n + 0.5 * step * k_0, pres_n + 0.5 * step);
+            let k_2 = self.derivative(temp_n + 0.5 * step * k_1, pres_n + 0.5 * step);
+            let k_3 = self.derivative(temp_n + step * k_2, pres_n + step);
 
             pres_n += step;
             temp_n += (step / 6.0) * (k_0 + 2.0 * k_1 + 2.0 * k_2 + k_3);
@@ -221,6 +203,122 @@ impl<'a> PseudoAdiabaticScheme<'a> {
 
         temp_n
     }
+
+    /// Pseudoadiabatic derivative with reference mixing ratios
+    /// and latent heat appropriate for the temperature.
+    fn derivative(&self, temp: Float, pres: Float) -> Float {
+        pseudoadiabatic_derivative(
+            temp,
+            pres,
+            self.ref_mxng_rto,
+            self.ref_satr_mxng_rto,
+            latent_heat(temp, &self.thermo),
+        )
+    }
+}
+
+/// Fraction of ice in the parcel condensate, changing linearly
+/// with temperature in the mixed-phase range.
+///
+/// Always zero when ice phase is disabled.
+fn ice_fraction(temp: Float, thermo: &Thermo) -> Float {
+    if !thermo.ice_phase {
+        return 0.0;
+    }
+
+    let (warm, cold) = thermo.mixed_phase_temperatures;
+
+    ((warm - temp) / (warm - cold)).clamp(0.0, 1.0)
+}
+
+/// Latent heat released by condensation (and deposition)
+/// of water vapour in the parcel.
+fn latent_heat(temp: Float, thermo: &Thermo) -> Float {
+    L_V + ice_fraction(temp, thermo) * (L_S - L_V)
+}
+
+/// Computes saturation vapour pressure of the parcel
+/// taking into account ice phase when it is enabled.
+///
+/// In the mixed-phase range saturation vapour pressure is blended
+/// between values over supercooled water and over ice.
+fn parcel_saturation_vapour_pressure(
+    temp: Float,
+    pres: Float,
+    thermo: &Thermo,
+) -> Result<Float, InputError> {
+    if !thermo.ice_phase {
+        return saturation_vapour_pressure(temp, pres, thermo.saturation_formula);
+    }
+
+    let ice_fraction = ice_fraction(temp, thermo);
+
+    // selected formulas are over water above 0°C and over ice below
+    let over_water = if temp > ZERO_CELSIUS {
+        saturation_vapour_pressure(temp, pres, thermo.saturation_formula)?
+    } else if ice_fraction < 1.0 {
+        // Buck formula is valid over supercooled water down to 232K
+        vapour_pressure::buck1(temp, pres)?
+    } else {
+        0.0
+    };
+
+    let over_ice = if ice_fraction > 0.0 {
+        saturation_vapour_pressure(temp, pres, thermo.saturation_formula)?
+    } else {
+        0.0
+    };
+
+    Ok((1.0 - ice_fraction) * over_water + ice_fraction * over_ice)
+}
+
+/// Computes saturation vapour pressure
+/// with the formula selected in configuration.
+///
+/// Above 0°C saturation is computed over water and below over ice.
+fn saturation_vapour_pressure(
+    temp: Float,
+    pres: Float,
+    formula: SaturationFormula,
+) -> Result<Float, InputError> {
+    let over_water = temp > ZERO_CELSIUS;
+
+    match formula {
+        SaturationFormula::Cascade => {
+            if over_water {
+                // for most ranges use usual buck formula over water
+                vapour_pressure::buck1(temp, pres)
+            } else if temp > 193.0 {
+                // if the temperature is very low use dedicated formula
+                vapour_pressure::buck2(temp, pres)
+            } else {
+                // as last resort if the temperature is very very low use more expensive dedicated formula
+                vapour_pressure::wexler2(temp)
+            }
+        }
+        SaturationFormula::Buck => {
+            if over_water {
+                vapour_pressure::buck1(temp, pres)
+            } else {
+                vapour_pressure::buck2(temp, pres)
+            }
+        }
+        SaturationFormula::Wexler => {
+            if over_water {
+                vapour_pressure::wexler1(temp)
+            } else {
+                vapour_pressure::wexler2(temp)
+            }
+        }
+        SaturationFormula::Tetens => {
+            if over_water {
+                vapour_pressure::tetens1(temp)
+            } else {
+                // Tetens formula over ice with coefficients from Murray (1967)
+                Ok(610.78 * ((21.875 * (temp - ZERO_CELSIUS)) / (temp - 7.66)).exp())
+            }
+        }
+    }
 }
 
 /// (TODO: What it is)
@@ -231,10 +329,69 @@ fn pseudoadiabatic_derivative(
     pres: Float,
     mxng_rto: Float,
     satr_mxng_rto: Float,
+    latent_heat: Float,
 ) -> Float {
     let b = (1.0 + (mxng_rto / EPSILON)) / (1.0 + (mxng_rto / (C_P / C_PV)));
 
     (b / pres)
-        * ((R_D * temp + L_V * satr_mxng_rto)
-            / (C_P + ((L_V * L_V * satr_mxng_rto * EPSILON * b) / (R_D * temp * temp))))
+        * ((R_D * temp + latent_heat * satr_mxng_rto)
+            / (C_P
+                + ((latent_heat * latent_heat * satr_mxng_rto * EPSILON * b)
+                    / (R_D * temp * temp))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{latent_heat, parcel_saturation_vapour_pressure, saturation_vapour_pressure};
+    use crate::{
+        constants::L_S,
+        model::configuration::{SaturationFormula, Thermo},
+        Float,
+    };
+    use floccus::{constants::L_V, vapour_pressure};
+
+    #[test]
+    fn saturation_formulas_agreement() {
+        let formulas = [
+            SaturationFormula::Buck,
+            SaturationFormula::Wexler,
+            SaturationFormula::Tetens,
+        ];
+
+        // over water and over ice, formulas should agree within 2%
+        // (Buck formulas include the enhancement factor, Tetens is the least accurate)
+        for temp in [300.0, 280.0, 260.0, 240.0] {
+            let reference =
+                saturation_vapour_pressure(temp, 80_000.0, SaturationFormula::Cascade).unwrap();
+
+            for formula in formulas {
+                let value: Float = saturation_vapour_pressure(temp, 80_000.0, formula).unwrap();
+                assert!((value - reference).abs() / reference < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_phase_blending() {
+        let thermo = Thermo {
+            ice_phase: true,
+            ..Thermo::default()
+        };
+
+        // in the middle of mixed-phase range water and ice are blended equally
+        let temp = 253.15;
+        let over_water = vapour_pressure::buck1(temp, 50_000.0).unwrap();
+        let over_ice = vapour_pressure::buck2(temp, 50_000.0).unwrap();
+        let blended = parcel_saturation_vapour_pressure(temp, 50_000.0, &thermo).unwrap();
+
+        assert!((blended - 0.5 * (over_water + over_ice)).abs() < 1e-9);
+        assert!((latent_heat(temp, &thermo) - 0.5 * (L_V + L_S)).abs() < 1e-6);
+
+        // outside of the range only one phase is present
+        assert!((latent_heat(280.0, &thermo) - L_V).abs() < 1e-6);
+        assert!((latent_heat(220.0, &thermo) - L_S).abs() < 1e-6);
+
+        // without ice phase latent heat of vaporisation is used everywhere
+        assert!((latent_heat(220.0, &Thermo::default()) - L_V).abs() < 1e-6);
+    }
 }
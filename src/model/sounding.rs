@@ -0,0 +1,227 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Module for computing convective parameters
+//! of a parcel released from a single sounding.
+//!
+//! Parcel is simulated with exactly the same schemes as in the model,
+//! but in a horizontally homogeneous environment built from the sounding.
+//! Those schemes read environment fields at parcel position only through
+//! [`Environment`], so the column is buffered on a single gridpoint domain
+//! instead of duplicating the schemes for bare profiles.
+//! That allows to validate the model against other tools without GRIB input.
+
+use super::{
+    configuration::{
+        Config, DateTime, Domain, Input, Interpolation, Output, Parcel, Resources, Run, Thermo,
+    },
+    environment::{Column, Environment},
+    parcel::{self, conv_params::ConvectiveParams},
+};
+use crate::{
+    errors::{ConfigError, ModelError},
+    Float,
+};
+use chrono::NaiveDateTime;
+use floccus::{
+    constants::{G, R_D},
+    mixing_ratio, virtual_temperature,
+};
+use std::sync::Arc;
+
+/// Environmental profile from a sounding,
+/// with settings of the parcel released from it.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct Sounding {
+    /// Pressure (in Pa), decreasing with height.
+    pub pressure: Vec<Float>,
+
+    /// Temperature (in K).
+    pub temperature: Vec<Float>,
+
+    /// Dewpoint (in K).
+    pub dewpoint: Vec<Float>,
+
+    /// Height (in meters above mean sea level) of the lowest level,
+    /// from which the parcel is released. Defaults to `0`.
+    pub surface_height: Float,
+
+    /// Timestep (in seconds) of parcel simulation. Defaults to `1`.
+    pub timestep: Float,
+
+    /// Settings of the released parcel. Defaults as in configuration.
+    pub parcel: Parcel,
+
+    /// Settings of thermodynamic formulas. Defaults as in configuration.
+    pub thermo: Thermo,
+}
+
+impl Sounding {
+    /// Creates the sounding with default settings,
+    /// checking that the profile is correct.
+    pub fn new(
+        pressure: Vec<Float>,
+        temperature: Vec<Float>,
+        dewpoint: Vec<Float>,
+    ) -> Result<Self, ConfigError> {
+        if pressure.len() != temperature.len() || pressure.len() != dewpoint.len() {
+            return Err(ConfigError::OutOfBounds(
+                "Sounding variables must have the same number of levels",
+            ));
+        }
+
+        if pressure.len() < 2 {
+            return Err(ConfigError::OutOfBounds(
+                "Sounding must have at least 2 levels",
+            ));
+        }
+
+        if pressure[pressure.len() - 1] <= 0.0 || pressure.windows(2).any(|p| p[1] >= p[0]) {
+            return Err(ConfigError::OutOfBounds(
+                "Sounding pressure must be positive and decreasing with height",
+            ));
+        }
+
+        if dewpoint.iter().zip(&temperature).any(|(td, t)| td > t) {
+            return Err(ConfigError::OutOfBounds(
+                "Sounding dewpoint cannot be higher than temperature",
+            ));
+        }
+
+        Ok(Sounding {
+            pressure,
+            temperature,
+            dewpoint,
+            surface_height: 0.0,
+            timestep: 1.0,
+            parcel: Parcel::default(),
+            thermo: Thermo::default(),
+        })
+    }
+
+    /// Computes heights of the sounding levels
+    /// with the hypsometric equation.
+    fn heights(&self) -> Result<Vec<Float>, ModelError> {
+        let mut virtual_temp = Vec::with_capacity(self.pressure.len());
+
+        for z in 0..self.pressure.len() {
+            let mxng_rto = mixing_ratio::accuracy1(self.dewpoint[z], self.pressure[z])?;
+            virtual_temp.push(virtual_temperature::general1(
+                self.temperature[z],
+                mxng_rto,
+            )?);
+        }
+
+        let mut heights = vec![self.surface_height];
+
+        for z in 1..self.pressure.len() {
+            let layer_temp = 0.5 * (virtual_temp[z - 1] + virtual_temp[z]);
+            let thickness = (R_D * layer_temp / G) * (self.pressure[z - 1] / self.pressure[z]).ln();
+
+            heights.push(heights[z - 1] + thickness);
+        }
+
+        Ok(heights)
+    }
+}
+
+/// Computes convective parameters (including CAPE and CIN)
+/// of the parcel released from the lowest level of the sounding.
+///
+/// Parameters which depend on wind are computed with zero wind.
+pub fn cape_cin_from_sounding(sounding: &Sounding) -> Result<ConvectiveParams, ModelError> {
+    let column = Column {
+        heights: sounding.heights()?,
+        temperature: sounding.temperature.clone(),
+        dewpoint: sounding.dewpoint.clone(),
+        pressure: sounding.pressure.clone(),
+    };
+
+    // location of the sounding does not matter
+    // as the environment is horizontally homogeneous
+    let domain = Domain::new(0.0, 45.0, 1000.0, (1, 1))?;
+    let environment = Environment::from_column(&domain, &column)?;
+
+    let start_coords = environment
+        .projection
+        .project(domain.ref_lon, domain.ref_lat);
+
+    sounding.thermo.check_bounds()?;
+    sounding.parcel.check_bounds()?;
+
+    let config = Config {
+        domain,
+        datetime: DateTime::new(NaiveDateTime::default(), sounding.timestep)?,
+        input: Input::new("sounding", vec![]),
+        resources: Resources::default(),
+        output: Output::default(),
+        parcel: sounding.parcel.clone(),
+        thermo: sounding.thermo,
+        interpolation: Interpolation::default(),
+        run: Run::default(),
+    };
+
+    // parcel log is not written, as only parameters are returned
+    let params = parcel::deploy(
+        start_coords,
+        (0, 0),
+        None,
+        &Arc::new(config),
+        &Arc::new(environment),
+        None,
+    )?;
+
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cape_cin_from_sounding, Sounding};
+    use crate::{model::configuration::ReleaseLevel, Float};
+    use floccus::constants::{C_P, R_D};
+
+    #[test]
+    fn unstable_sounding() {
+        // dry adiabatic layer up to 700 hPa with isothermal layer above
+        let pressure: Vec<Float> = (0..=75)
+            .map(|i| 100_000.0 - Float::from(i) * 1000.0)
+            .collect();
+        let temperature: Vec<Float> = pressure
+            .iter()
+            .map(|p| 300.0 * (p.max(70_000.0) / 100_000.0).powf(R_D / C_P))
+            .collect();
+        let dewpoint = temperature.iter().map(|t| (t - 10.0).max(240.0)).collect();
+
+        let sounding = Sounding::new(pressure, temperature, dewpoint).unwrap();
+        let params = cape_cin_from_sounding(&sounding).unwrap();
+
+        assert!(params.cape().unwrap() > 0.0);
+        assert!(params.lfc().is_some());
+        assert!(params.el().unwrap() > params.lfc().unwrap());
+        assert!(params.step_count() > 0);
+
+        // settings of the parcel are checked as in configuration
+        let mut sounding = sounding;
+        sounding.parcel.release_level = ReleaseLevel::PressureHpa(2000.0);
+        assert!(cape_cin_from_sounding(&sounding).is_err());
+
+        assert!(Sounding::new(vec![100_000.0, 101_000.0], vec![300.0; 2], vec![290.0; 2]).is_err());
+        assert!(Sounding::new(vec![100_000.0, 90_000.0], vec![300.0; 2], vec![301.0; 2]).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+/*
+Copyright 2021 - 2022 Jakub Lewandowski
+
+This file is part of Parcel Ascent Tracing System (PATS).
+
+Parcel Ascent Tracing System (PATS) is a free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation; either version 3 of the License, or
+(at your option) any later version.
+
+Parcel Ascent Tracing System (PATS) is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with Parcel Ascent Tracing System (PATS). If not, see https://www.gnu.org/licenses/.
+*/
+
+//! Module responsible for collecting statistics of the model run
+//! and writing them as a machine-readable summary.
+
+use super::{configuration::Config, parcel::conv_params::ConvectiveParams};
+use crate::{
+    errors::{ErrorCode, ModelError},
+    Float, ALLOCATOR,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::Path, time::Duration};
+
+/// Statistics of simulated parcels, accumulated
+/// while receiving their results.
+#[derive(Clone, PartialEq, PartialOrd, Debug, Default)]
+pub struct RunStats {
+    succeeded: usize,
+    prescreened: usize,
+    failed: usize,
+    failed_by_code: BTreeMap<ErrorCode, usize>,
+    cape_min: Option<Float>,
+    cape_max: Option<Float>,
+    cape_sum: Float,
+    cape_count: usize,
+    steps_total: u64,
+    steps_max: u32,
+    peak_memory: usize,
+}
+
+impl RunStats {
+    /// Adds parameters of successfully simulated parcel.
+    pub fn add_succeeded(&mut self, params: &ConvectiveParams) {
+        self.succeeded += 1;
+
+        if params.prescreened() {
+            self.prescreened += 1;
+        }
+
+        if let Some(cape) = params.cape() {
+            self.cape_min = Some(self.cape_min.map_or(cape, |min| min.min(cape)));
+            self.cape_max = Some(self.cape_max.map_or(cape, |max| max.max(cape)));
+            self.cape_sum += cape;
+            self.cape_count += 1;
+        }
+
+        self.steps_total += u64::from(params.step_count());
+        self.steps_max = self.steps_max.max(params.step_count());
+
+        self.sample_memory();
+    }
+
+    /// Adds the parcel which simulation failed with error of given code.
+    pub fn add_failed(&mut self, code: ErrorCode) {
+        self.failed += 1;
+        *self.failed_by_code.entry(code).or_default() += 1;
+        self.sample_memory();
+    }
+
+    /// Number of parcels which simulation failed.
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Fraction of added parcels which simulation failed.
+    pub fn failed_fraction(&self) -> Float {
+        let count = self.succeeded + self.failed;
+
+        if count == 0 {
+            return 0.0;
+        }
+
+        self.failed as Float / count as Float
+    }
+
+    /// Code of the most frequent error of failed parcels.
+    pub fn dominant_failure(&self) -> Option<ErrorCode> {
+        self.failed_by_code
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(code, _)| *code)
+    }
+
+    /// Updates peak memory with currently allocated memory.
+    ///
+    /// Memory is sampled with each added parcel, and it should be
+    /// also sampled after loading the input and saving the output.
+    pub fn sample_memory(&mut self) {
+        self.peak_memory = self.peak_memory.max(ALLOCATOR.allocated());
+    }
+}
+
+/// Summary of the model run written to `run_summary.json`.
+#[derive(Serialize)]
+struct RunSummary<'a> {
+    interrupted: bool,
+    aborted: bool,
+    succeeded_parcels: usize,
+    prescreened_parcels: usize,
+    failed_parcels: usize,
+    failed_by_code: &'a BTreeMap<ErrorCode, usize>,
+    cape_min: Option<Float>,
+    cape_max: Option<Float>,
+    cape_mean: Option<Float>,
+    steps_total: u64,
+    steps_max: u32,
+    wall_clock_seconds: Float,
+    peak_memory_mb: Float,
+    config: &'a Config,
+}
+
+/// Writes the run summary with collected statistics
+/// and effective model configuration.
+///
+/// Run is `aborted` when too many parcels failed.
+pub fn save_run_summary(
+    stats: &RunStats,
+    elapsed: Duration,
+    interrupted: bool,
+    aborted: bool,
+    config: &Config,
+    out_path: &Path,
+) -> Result<(), ModelError> {
+    let cape_mean = if stats.cape_count > 0 {
+        Some(stats.cape_sum / stats.cape_count as Float)
+    } else {
+        None
+    };
+
+    let summary = RunSummary {
+        interrupted,
+        aborted,
+        succeeded_parcels: stats.succeeded,
+        prescreened_parcels: stats.prescreened,
+        failed_parcels: stats.failed,
+        failed_by_code: &stats.failed_by_code,
+        cape_min: stats.cape_min,
+        cape_max: stats.cape_max,
+        cape_mean,
+        steps_total: stats.steps_total,
+        steps_max: stats.steps_max,
+        wall_clock_seconds: elapsed.as_secs_f64() as Float,
+        peak_memory_mb: stats.peak_memory as Float / (1024.0 * 1024.0),
+        config,
+    };
+
+    let out_file = BufWriter::new(File::create(out_path)?);
+    serde_json::to_writer_pretty(out_file, &summary)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunStats;
+    use crate::{errors::ErrorCode, model::parcel::conv_params::ConvectiveParams};
+
+    #[test]
+    fn failure_statistics() {
+        let mut stats = RunStats::default();
+        assert_eq!(stats.failed_fraction(), 0.0);
+        assert_eq!(stats.dominant_failure(), None);
+
+        stats.add_succeeded(&ConvectiveParams::default());
+        stats.add_failed(ErrorCode::OutOfDomain);
+        stats.add_failed(ErrorCode::MissingData);
+        stats.add_failed(ErrorCode::OutOfDomain);
+
+        assert_eq!(stats.failed(), 3);
+        assert_eq!(stats.failed_fraction(), 0.75);
+        assert_eq!(stats.dominant_failure(), Some(ErrorCode::OutOfDomain));
+    }
+}
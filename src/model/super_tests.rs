@@ -7,8 +7,18 @@
 //! for that issue.
 
 use super::configuration::Config;
-use super::environment::{EnvFields, Environment};
-use std::path::Path;
+use super::environment::{
+    analytic::AnalyticProfile,
+    grib_fixture::{ConstantField, GribFixture},
+    EnvFields, Environment,
+};
+use super::{prepare_output_dir, run, Core};
+use crate::errors::{ErrorCode, ModelError};
+use crate::Float;
+use chrono::NaiveDate;
+use floccus::constants::G;
+use rayon::ThreadPoolBuilder;
+use std::{env, fs, path::Path, process, time::Instant};
 
 #[test]
 fn pressure_interpolation() {
@@ -27,3 +37,216 @@ fn pressure_interpolation() {
         println!("{:>5.1} {:>5.2}", z as f64, v);
     }
 }
+
+/// Pressure levels (in hPa) of the tiny domain fixture.
+const TINY_DOMAIN_LEVELS: [i64; 14] = [
+    1000, 925, 850, 700, 600, 500, 400, 300, 250, 200, 150, 100, 70, 50,
+];
+
+/// Writes GRIB files of the tiny domain fixture into `out_dir`,
+/// with the same parameters and level types as ERA5 data on a small
+/// regular lat-lon grid, and returns the configuration reading them.
+///
+/// The environment is conditionally unstable, with horizontally
+/// homogeneous profile and winds increasing with height.
+fn write_tiny_domain_fixture(out_dir: &Path) -> Config {
+    let fixture = GribFixture {
+        lon_west: 19.0,
+        lat_north: 54.0,
+        step: 0.25,
+        shape: (13, 13),
+        datetime: NaiveDate::from_ymd_opt(2021, 7, 14)
+            .and_then(|date| date.and_hms_opt(12, 0, 0))
+            .unwrap(),
+    };
+
+    // troposphere with constant lapse rate below isothermal stratosphere
+    let temperature = |z: Float| 303.0 - 0.0065 * z.min(12_000.0);
+    let dewpoint = |z: Float| 292.0 - 0.008 * z.min(10_000.0);
+    let height = |p: Float| (1.0 - (p / 101_325.0).powf(1.0 / 5.2559)) / 2.2557e-5;
+    let pressure = |z: Float| 101_325.0 * (1.0 - 2.2557e-5 * z).powf(5.2559);
+    let wind = |z: Float| (3.0 + 0.002 * z, 1.0 + 0.0005 * z);
+    let spec_humidity = |z: Float| {
+        let dewpoint_c = dewpoint(z) - 273.15;
+        let vapour_pres = 611.2 * (17.67 * dewpoint_c / (dewpoint_c + 243.5)).exp();
+        0.622 * vapour_pres / (pressure(z) - 0.378 * vapour_pres)
+    };
+
+    let field = |param_id, type_of_level, level, value| ConstantField {
+        param_id,
+        type_of_level,
+        level,
+        value,
+    };
+
+    let mut level_fields = vec![];
+
+    for level in TINY_DOMAIN_LEVELS {
+        let z = height(level as Float * 100.0);
+        let (u_wind, v_wind) = wind(z);
+
+        level_fields.extend([
+            field(129, "isobaricInhPa", level, G * z),
+            field(130, "isobaricInhPa", level, temperature(z)),
+            field(133, "isobaricInhPa", level, spec_humidity(z)),
+            field(131, "isobaricInhPa", level, u_wind),
+            field(132, "isobaricInhPa", level, v_wind),
+        ]);
+    }
+
+    // surface is slightly superheated
+    let surface_height = 120.0;
+    let (u_wind, v_wind) = wind(surface_height);
+
+    let surface_fields = [
+        field(129, "surface", 0, G * surface_height),
+        field(134, "surface", 0, pressure(surface_height)),
+        field(167, "surface", 0, temperature(surface_height) + 1.5),
+        field(168, "surface", 0, dewpoint(surface_height)),
+        field(165, "surface", 0, u_wind),
+        field(166, "surface", 0, v_wind),
+    ];
+
+    let levels_path = out_dir.join("pressure_levels.grib");
+    let surface_path = out_dir.join("surface.grib");
+
+    fixture.write(&levels_path, &level_fields).unwrap();
+    fixture.write(&surface_path, &surface_fields).unwrap();
+
+    let config_path = out_dir.join("config.yaml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+            domain:
+              ref_lat: 52.25
+              ref_lon: 20.25
+              spacing: 10000.0
+              shape: [2, 2]
+            datetime:
+              start: "2021-07-14T12:00:00"
+              timestep: 1.0
+            input:
+              level_type: "isobaricInhPa"
+              data_files: [{:?}, {:?}]
+            resources:
+              threads: 2
+              memory: 2048
+            "#,
+            levels_path, surface_path
+        ),
+    )
+    .unwrap();
+
+    Config::new_from_file(&config_path).unwrap()
+}
+
+#[test]
+fn tiny_domain_end_to_end() {
+    let out_dir = env::temp_dir().join(format!("pats_tiny_domain_{}", process::id()));
+
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    prepare_output_dir(&out_dir).unwrap();
+
+    let cfg = write_tiny_domain_fixture(&out_dir);
+    let model_core = Core::from_config(cfg).unwrap();
+
+    // levels of the fixture, from the lowest one
+    assert_eq!(model_core.environ.levels(), TINY_DOMAIN_LEVELS);
+
+    // buffered extent contains the domain and is within the fixture grid
+    let domain = &model_core.config.domain;
+    let extent = model_core.environ.buffered_extent();
+    assert!(extent.west >= 19.0 && extent.west <= domain.ref_lon);
+    assert!(extent.south >= 51.0 && extent.south <= domain.ref_lat);
+    assert!(extent.east > domain.ref_lon && extent.east <= 22.0);
+    assert!(extent.north > domain.ref_lat && extent.north <= 54.0);
+
+    run(model_core, &out_dir, Instant::now()).unwrap();
+
+    let mut reader = csv::Reader::from_path(out_dir.join("model_convective_params.csv")).unwrap();
+    let cape_column = reader
+        .headers()
+        .unwrap()
+        .iter()
+        .position(|name| name == "cape")
+        .unwrap();
+    let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+
+    // 2x2 domain
+    assert_eq!(records.len(), 4);
+
+    // fixture environment is conditionally unstable
+    for record in &records {
+        let cape: Float = record[cape_column].parse().unwrap();
+        assert!(cape.is_finite() && cape > 0.0);
+    }
+
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn too_many_failures() {
+    let out_dir = env::temp_dir().join(format!("pats_too_many_failures_{}", process::id()));
+    prepare_output_dir(&out_dir).unwrap();
+
+    // parcels are released above the top of the environment, so all of them
+    // fail, and there are fewer of them than the periodic check interval
+    let config: Config = serde_yaml::from_str(
+        r#"
+        domain:
+          ref_lat: 50.0
+          ref_lon: 20.0
+          spacing: 1000.0
+          shape: [3, 3]
+        datetime:
+          start: "2021-06-24T06:00:00"
+          timestep: 1.0
+        input:
+          level_type: "isobaricInhPa"
+          data_files: []
+        output:
+          enabled: false
+        parcel:
+          release_level: { height_agl: 20000.0 }
+        run:
+          max_failure_fraction: 0.5
+        "#,
+    )
+    .unwrap();
+
+    let profile = AnalyticProfile {
+        temperature: |z| 288.0 - 0.0065 * z,
+        dewpoint: |z| 280.0 - 0.002 * z,
+        pressure: |z| 101_325.0 * (1.0 - 2.2557e-5 * z).powf(5.2559),
+    };
+    let heights: Vec<Float> = (0..=120).map(|i| Float::from(i) * 100.0).collect();
+
+    let model_core = Core {
+        environ: Environment::from_analytic(&config.domain, &heights, &profile).unwrap(),
+        threadpool: ThreadPoolBuilder::new().num_threads(2).build().unwrap(),
+        config,
+    };
+
+    let result = run(model_core, &out_dir, Instant::now());
+
+    match result {
+        Err(
+            err @ ModelError::TooManyFailures {
+                failed,
+                received,
+                dominant,
+            },
+        ) => {
+            assert_eq!((failed, received), (9, 9));
+            assert_eq!(dominant, ErrorCode::ReleaseLevel);
+            assert_eq!(err.code(), ErrorCode::TooManyFailures);
+        }
+        other => panic!("Run should be aborted, got {:?}", other),
+    }
+
+    fs::remove_dir_all(&out_dir).unwrap();
+}